@@ -13,6 +13,7 @@ const DEFAULT_SETTINGS: Settings = {
     samples: 500,
     bounces: 50,
     gamma: 2.2,
+    spectral: true,
   },
   camera: {
     position: { x: 0, y: 0, z: 0 },
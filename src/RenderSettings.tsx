@@ -15,6 +15,7 @@ export interface Settings {
     samples: number;
     bounces: number;
     gamma: number;
+    spectral: boolean;
   };
   camera: CameraSettings;
   scene: {
@@ -102,6 +103,16 @@ function RenderSettings({ settings, onSettingsChange }: RenderSettingsProps) {
               />
             </label>
           </li>
+          <li>
+            <label>
+              Spectral
+              <input
+                type="checkbox"
+                checked={local.render.spectral}
+                onChange={(e) => updateRender({ spectral: e.target.checked })}
+              />
+            </label>
+          </li>
         </ul>
       </fieldset>
 
@@ -8,6 +8,10 @@ interface BaseObject {
   roughness: number;
   transmission: number;
   ior: number;
+  /** Cauchy dispersion coefficient in square micrometres, only visible when rendering spectrally. */
+  dispersion?: number;
+  /** Watts spread over the surface with `emission` as the colour, so lights stay as bright when resized. */
+  emissionPower?: number;
 }
 
 export interface Sphere extends BaseObject {
@@ -55,6 +59,7 @@ export interface WorkerSettings {
   aperture: number;
   samples: number;
   bounces: number;
+  spectral: boolean;
 }
 
 export type WorkerInMessage = {
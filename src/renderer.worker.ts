@@ -9,7 +9,7 @@ console.log("renderer worker loaded");
 const ctx = self as unknown as Worker;
 
 const createEntity = (obj: SceneObject): Entity => {
-  const material = new Material(
+  const base = new Material(
     wasmRGB(obj.emission),
     wasmRGB(obj.albedo),
     obj.metallic,
@@ -17,6 +17,8 @@ const createEntity = (obj: SceneObject): Entity => {
     obj.transmission,
     obj.ior,
   );
+  const glowing = obj.emissionPower ? base.with_emission_power(wasmRGB(obj.emission), obj.emissionPower) : base;
+  const material = obj.dispersion ? glowing.with_cauchy(obj.dispersion) : glowing;
 
   const shape = obj.shape;
   switch (shape) {
@@ -68,6 +70,7 @@ ctx.onmessage = async (e: MessageEvent<WorkerInMessage>): Promise<void> => {
   }
 
   scene.set_gamma_correction(gamma);
+  scene.spectral = settings.spectral;
 
   for (const obj of entities) {
     const entity: Entity = createEntity(obj);
@@ -29,13 +29,15 @@ const BASE_SCENE: SceneObject[] = [
     roughness: 0,
     transmission: 1.0,
     ior: 1.5,
+    dispersion: 0.02,
   },
   // red light
   {
     shape: "sphere",
     radius: MAIN_SIZE,
     position: vec3(MAIN_SIZE * 2.5, 0, 150),
-    emission: rgb(768, 0, 0),
+    emission: rgb(1, 0, 0),
+    emissionPower: 16000,
     albedo: rgb(1.0, 0.0, 0.0),
     metallic: 0.0,
     roughness: 1.0,
@@ -0,0 +1,288 @@
+use wasm_bindgen::prelude::*;
+
+use crate::intersection::Intersection;
+use crate::material::Surface;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// Auxiliary output variables recorded at the first hit of each camera ray.
+#[wasm_bindgen]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Aov {
+    Depth,
+    Normal,
+    Albedo,
+    Position,
+    EntityId,
+    MaterialId,
+}
+
+impl Aov {
+    pub fn components(self) -> usize {
+        match self {
+            Aov::Depth | Aov::EntityId | Aov::MaterialId => 1,
+            Aov::Normal | Aov::Albedo | Aov::Position => 3,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FirstHit {
+    /// Distance from the camera along the ray.
+    pub depth: f32,
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub albedo: Vec3,
+    pub entity_id: u32,
+    pub material_id: u32,
+}
+
+impl FirstHit {
+    pub fn new(ray: Ray, intersection: &Intersection, surface: &Surface) -> Self {
+        let entity = intersection.entity.unwrap();
+        Self {
+            depth: intersection.dist * ray.direction.mag(),
+            position: intersection.point,
+            normal: intersection.normal,
+            albedo: surface.albedo,
+            entity_id: entity.id(),
+            material_id: entity.material_id(),
+        }
+    }
+}
+
+/// Running totals for one pixel. Continuous values are averaged over the samples that hit something, while the
+/// ids keep whatever the first sample to hit saw since they can't be blended.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct AovPixel {
+    hits: u32,
+    depth: f32,
+    position: Vec3,
+    normal: Vec3,
+    albedo: Vec3,
+    entity_id: Option<u32>,
+    material_id: Option<u32>,
+}
+
+impl AovPixel {
+    pub fn empty() -> Self {
+        Self {
+            hits: 0,
+            depth: 0.0,
+            position: Vec3::zero(),
+            normal: Vec3::zero(),
+            albedo: Vec3::zero(),
+            entity_id: None,
+            material_id: None,
+        }
+    }
+
+    pub fn add(&mut self, hit: &FirstHit) {
+        self.hits += 1;
+        self.depth += hit.depth;
+        self.position += hit.position;
+        self.normal += hit.normal;
+        self.albedo += hit.albedo;
+        self.entity_id.get_or_insert(hit.entity_id);
+        self.material_id.get_or_insert(hit.material_id);
+    }
+
+    fn mean(&self, sum: Vec3) -> Vec3 {
+        if self.hits == 0 {
+            Vec3::zero()
+        } else {
+            sum / self.hits
+        }
+    }
+
+    /// Misses report an infinite depth and an id of -1.
+    pub fn write(&self, aov: Aov, out: &mut Vec<f32>) {
+        let id = |id: Option<u32>| id.map_or(-1.0, |id| id as f32);
+        match aov {
+            Aov::Depth if self.hits == 0 => out.push(f32::INFINITY),
+            Aov::Depth => out.push(self.depth / self.hits as f32),
+            Aov::EntityId => out.push(id(self.entity_id)),
+            Aov::MaterialId => out.push(id(self.material_id)),
+            Aov::Normal | Aov::Albedo | Aov::Position => {
+                let v = match aov {
+                    Aov::Normal => {
+                        let n = self.mean(self.normal);
+                        if n.mag_squared() > 0.0 {
+                            n.normalize()
+                        } else {
+                            n
+                        }
+                    }
+                    Aov::Albedo => self.mean(self.albedo),
+                    _ => self.mean(self.position),
+                };
+                out.extend([v.x, v.y, v.z]);
+            }
+        }
+    }
+}
+
+pub struct AovBuffers {
+    rows: Vec<Vec<AovPixel>>,
+}
+
+impl AovBuffers {
+    /// Disabled buffers have empty rows, so the renderer's per-pixel writes fall through.
+    pub fn new(width: u32, height: u32, enabled: bool) -> Self {
+        let width = if enabled { width as usize } else { 0 };
+        Self {
+            rows: vec![vec![AovPixel::empty(); width]; height as usize],
+        }
+    }
+
+    pub fn rows_mut(&mut self) -> &mut [Vec<AovPixel>] {
+        &mut self.rows
+    }
+
+    /// Row-major values with `aov.components()` floats per pixel.
+    pub fn channel(&self, aov: Aov) -> Vec<f32> {
+        let mut out = Vec::with_capacity(self.rows.iter().map(Vec::len).sum::<usize>() * aov.components());
+        for pixel in self.rows.iter().flatten() {
+            pixel.write(aov, &mut out);
+        }
+        out
+    }
+
+    /// Maps a channel onto 0-255 so it can go through the same pixel writers as the beauty image.
+    pub fn to_pixels(&self, aov: Aov) -> Vec<Vec<Vec3>> {
+        let data = self.channel(aov);
+        let width = self.rows.first().map_or(0, Vec::len);
+        let n = aov.components();
+        let values: Vec<Vec3> = data
+            .chunks(n)
+            .map(|c| {
+                if n == 1 {
+                    Vec3::new(c[0], c[0], c[0])
+                } else {
+                    Vec3::new(c[0], c[1], c[2])
+                }
+            })
+            .collect();
+
+        let finite = values.iter().filter(|v| v.x.is_finite());
+        let (min, max) = finite.fold(
+            (
+                Vec3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+                Vec3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+            ),
+            |(min, max), v| (min.min(*v), max.max(*v)),
+        );
+
+        let display = |v: Vec3| -> Vec3 {
+            match aov {
+                // Near is bright, far and background are black
+                Aov::Depth if !v.x.is_finite() => Vec3::zero(),
+                Aov::Depth => {
+                    Vec3::new(1.0, 1.0, 1.0) * (255.0 * (1.0 - (v.x - min.x) / (max.x - min.x).max(f32::EPSILON)))
+                }
+                Aov::Normal => (v * 0.5 + 0.5) * 255.0,
+                Aov::Albedo => v.min(Vec3::new(1.0, 1.0, 1.0)) * 255.0,
+                Aov::Position => {
+                    let range = (max - min).max(Vec3::new(f32::EPSILON, f32::EPSILON, f32::EPSILON));
+                    (v - min) / range * 255.0
+                }
+                Aov::EntityId | Aov::MaterialId if v.x < 0.0 => Vec3::zero(),
+                Aov::EntityId | Aov::MaterialId => id_colour(v.x as u32),
+            }
+        };
+
+        values
+            .chunks(width.max(1))
+            .map(|row| row.iter().map(|v| display(*v)).collect())
+            .collect()
+    }
+}
+
+/// Spreads consecutive ids across visibly different colours.
+fn id_colour(id: u32) -> Vec3 {
+    let hash = (id.wrapping_add(1)).wrapping_mul(2_654_435_761);
+    Vec3::new(
+        (hash & 0xff) as f32,
+        ((hash >> 8) & 0xff) as f32,
+        ((hash >> 16) & 0xff) as f32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(depth: f32, entity_id: u32) -> FirstHit {
+        FirstHit {
+            depth,
+            position: Vec3::new(depth, 0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, -1.0),
+            albedo: Vec3::new(0.5, 0.25, 1.0),
+            entity_id,
+            material_id: entity_id * 10,
+        }
+    }
+
+    #[test]
+    fn test_pixel_averages_continuous_values() {
+        let mut pixel = AovPixel::empty();
+        pixel.add(&hit(2.0, 1));
+        pixel.add(&hit(4.0, 2));
+
+        let mut out = vec![];
+        pixel.write(Aov::Depth, &mut out);
+        pixel.write(Aov::Position, &mut out);
+        pixel.write(Aov::Normal, &mut out);
+        assert_eq!(out, vec![3.0, 3.0, 0.0, 0.0, 0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn test_pixel_keeps_first_id() {
+        let mut pixel = AovPixel::empty();
+        pixel.add(&hit(2.0, 1));
+        pixel.add(&hit(4.0, 2));
+
+        let mut out = vec![];
+        pixel.write(Aov::EntityId, &mut out);
+        pixel.write(Aov::MaterialId, &mut out);
+        assert_eq!(out, vec![1.0, 10.0]);
+    }
+
+    #[test]
+    fn test_missed_pixel() {
+        let pixel = AovPixel::empty();
+        let mut out = vec![];
+        pixel.write(Aov::Depth, &mut out);
+        pixel.write(Aov::EntityId, &mut out);
+        pixel.write(Aov::Albedo, &mut out);
+        assert_eq!(out, vec![f32::INFINITY, -1.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_channel_layout() {
+        let mut buffers = AovBuffers::new(2, 1, true);
+        buffers.rows_mut()[0][1].add(&hit(1.0, 0));
+        assert_eq!(buffers.channel(Aov::Depth), vec![f32::INFINITY, 1.0]);
+        assert_eq!(buffers.channel(Aov::Albedo).len(), 6);
+    }
+
+    #[test]
+    fn test_disabled_buffers_are_empty() {
+        let buffers = AovBuffers::new(4, 3, false);
+        assert!(buffers.channel(Aov::Normal).is_empty());
+    }
+
+    #[test]
+    fn test_to_pixels() {
+        let mut buffers = AovBuffers::new(2, 1, true);
+        buffers.rows_mut()[0][0].add(&hit(5.0, 3));
+        let normal = buffers.to_pixels(Aov::Normal);
+        assert_eq!(normal[0][0], Vec3::new(127.5, 127.5, 0.0));
+        let depth = buffers.to_pixels(Aov::Depth);
+        assert_eq!(depth[0][0], Vec3::new(255.0, 255.0, 255.0));
+        assert_eq!(depth[0][1], Vec3::zero());
+        let ids = buffers.to_pixels(Aov::EntityId);
+        assert_eq!(ids[0][0], id_colour(3));
+        assert_eq!(ids[0][1], Vec3::zero());
+    }
+}
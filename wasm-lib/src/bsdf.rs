@@ -0,0 +1,1232 @@
+use std::f32::consts::PI;
+use std::sync::OnceLock;
+
+use rand::Rng;
+
+use crate::material::{Conductor, Surface};
+use crate::vec3::Vec3;
+
+/// Below this GGX alpha a lobe is treated as a perfect mirror, since the distribution becomes too peaked to
+/// evaluate reliably in `f32`.
+const SMOOTH_ALPHA: f32 = 1e-3;
+
+/// Shading frame with the normal along +z, which keeps the microfacet maths in its textbook form.
+#[derive(Copy, Clone, Debug)]
+pub struct Frame {
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    pub normal: Vec3,
+}
+
+impl Frame {
+    pub fn from_normal(normal: Vec3) -> Self {
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        Self {
+            tangent,
+            bitangent,
+            normal,
+        }
+    }
+
+    /// A frame with its x axis along `tangent` projected onto the surface, or an arbitrary one if that leaves
+    /// nothing.
+    pub fn from_normal_and_tangent(normal: Vec3, tangent: Vec3) -> Self {
+        let tangent = tangent - normal * normal.dot(tangent);
+        if tangent.mag_squared() < 1e-12 {
+            return Self::from_normal(normal);
+        }
+        let tangent = tangent.normalize();
+        Self {
+            tangent,
+            bitangent: normal.cross(tangent),
+            normal,
+        }
+    }
+
+    /// The same frame turned by `angle` radians about the normal.
+    pub fn rotated(self, angle: f32) -> Self {
+        if angle == 0.0 {
+            return self;
+        }
+        let (sin, cos) = angle.sin_cos();
+        Self {
+            tangent: self.tangent * cos + self.bitangent * sin,
+            bitangent: self.bitangent * cos - self.tangent * sin,
+            normal: self.normal,
+        }
+    }
+
+    pub fn to_local(self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.tangent), v.dot(self.bitangent), v.dot(self.normal))
+    }
+
+    pub fn to_world(self, v: Vec3) -> Vec3 {
+        self.tangent * v.x + self.bitangent * v.y + self.normal * v.z
+    }
+}
+
+/// Reflects `w` about `h`, with both pointing away from the surface.
+pub fn reflect(w: Vec3, h: Vec3) -> Vec3 {
+    h * (2.0 * w.dot(h)) - w
+}
+
+/// The GGX (Trowbridge-Reitz) microfacet distribution with the Smith height-correlated masking-shadowing term.
+/// Directions are in the local shading frame, where the roughness can differ along x and y.
+#[derive(Copy, Clone, Debug)]
+pub struct Ggx {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl Ggx {
+    /// Uses the usual perceptual mapping of `alpha = roughness²`, as in glTF and Blender's Principled BSDF.
+    pub fn from_roughness(roughness: f32) -> Self {
+        Self::anisotropic(roughness, 0.0)
+    }
+
+    /// Stretches the highlight along the x axis by `anisotropy` from 0 to 1, with the mapping from glTF's
+    /// KHR_materials_anisotropy: the roughness across stays put while the one along rises towards 1.
+    pub fn anisotropic(roughness: f32, anisotropy: f32) -> Self {
+        let roughness = roughness.clamp(0.0, 1.0);
+        let alpha = roughness * roughness;
+        let anisotropy = anisotropy.clamp(0.0, 1.0);
+        Self {
+            alpha_x: alpha + (1.0 - alpha) * anisotropy * anisotropy,
+            alpha_y: alpha,
+        }
+    }
+
+    /// A perfect mirror only if it is smooth in both directions, since a brushed surface still spreads light
+    /// along its grain.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < SMOOTH_ALPHA
+    }
+
+    /// The alphas with the smaller one kept just above `SMOOTH_ALPHA`, so stretched lobes stay evaluable.
+    fn alphas(&self) -> (f32, f32) {
+        (self.alpha_x.max(SMOOTH_ALPHA), self.alpha_y.max(SMOOTH_ALPHA))
+    }
+
+    pub fn d(&self, h: Vec3) -> f32 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let (ax, ay) = self.alphas();
+        let t = (h.x / ax).powi(2) + (h.y / ay).powi(2) + h.z * h.z;
+        1.0 / (PI * ax * ay * t * t)
+    }
+
+    fn lambda(&self, w: Vec3) -> f32 {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 {
+            return f32::INFINITY;
+        }
+        let (ax, ay) = self.alphas();
+        let alpha2_tan2 = ((w.x * ax).powi(2) + (w.y * ay).powi(2)) / cos2;
+        0.5 * ((1.0 + alpha2_tan2).sqrt() - 1.0)
+    }
+
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Samples a microfacet normal from the distribution of normals visible from `wo` (Heitz 2018).
+    pub fn sample_visible_normal(&self, wo: Vec3, u1: f32, u2: f32) -> Vec3 {
+        let (ax, ay) = self.alphas();
+        let vh = Vec3::new(ax * wo.x, ay * wo.y, wo.z).normalize();
+        let len2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len2 > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / len2.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        Vec3::new(ax * nh.x, ay * nh.y, nh.z.max(1e-6)).normalize()
+    }
+
+    /// Density of `sample_visible_normal` returning `h`.
+    pub fn visible_normal_pdf(&self, wo: Vec3, h: Vec3) -> f32 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z
+    }
+
+    /// Density of reflecting `wo` about a visible normal and arriving at `wi`.
+    pub fn reflection_pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let h = (wo + wi).normalize();
+        let wo_dot_h = wo.dot(h);
+        if wo_dot_h <= 0.0 {
+            return 0.0;
+        }
+        self.visible_normal_pdf(wo, h) / (4.0 * wo_dot_h)
+    }
+}
+
+/// Unpolarised Fresnel reflectance of a dielectric interface, where `eta` is the IOR beyond the surface over the
+/// IOR on the side of `cos_i`. Returns 1 under total internal reflection.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+/// Unpolarised Fresnel reflectance of a conductor with complex IOR `eta + ik` relative to the outside, per
+/// channel, as in Pharr et al., "Physically Based Rendering" (3rd ed.), section 8.2.1.
+pub fn fresnel_conductor(cos_i: f32, eta: Vec3, k: Vec3) -> Vec3 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let channel = |eta: f32, k: f32| {
+        let t0 = eta * eta - k * k - sin2;
+        let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let t1 = a2b2 + cos2;
+        let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_i * a;
+        let perpendicular = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+        0.5 * (parallel + perpendicular)
+    };
+    Vec3::new(channel(eta.x, k.x), channel(eta.y, k.y), channel(eta.z, k.z))
+}
+
+/// Refracts `w` through a surface with normal `n` on the same side, or `None` under total internal reflection.
+pub fn refract(w: Vec3, n: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = w.dot(n);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(w * (-1.0 / eta) + n * (cos_i / eta - cos_t))
+}
+
+pub struct BsdfSample {
+    pub direction: Vec3,
+    /// The BSDF times the cosine term, divided by the sampling density.
+    pub weight: Vec3,
+    /// `None` when the direction came from a perfectly specular lobe, which other strategies can't hit.
+    pub pdf: Option<f32>,
+}
+
+/// A sample in the local frame, before it is handed back in world space.
+struct LocalSample {
+    wi: Vec3,
+    weight: Vec3,
+    pdf: Option<f32>,
+}
+
+impl LocalSample {
+    /// Weights a direction drawn from a non-delta lobe by the full BSDF and its combined density.
+    fn from_eval(wi: Vec3, f: Vec3, pdf: f32) -> Option<Self> {
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(Self {
+            wi,
+            weight: f / pdf,
+            pdf: Some(pdf),
+        })
+    }
+}
+
+/// A Lambertian base under a GGX specular layer whose Fresnel reflectance runs from 4% for dielectrics up to the
+/// albedo for metals, or follows the exact conductor curve for metals given by complex IOR. On a subsurface
+/// material the base transmits instead, carrying light through to the other side with a cosine distribution,
+/// which is how random walks enter and leave the interior.
+struct Opaque {
+    diffuse: Vec3,
+    f0: Vec3,
+    conductor: Option<Conductor>,
+    specular: Ggx,
+    specular_probability: f32,
+    translucent: bool,
+}
+
+impl Opaque {
+    fn new(surface: &Surface, wo: Vec3) -> Self {
+        // The colour of a subsurface material comes from its interior, not the surface
+        let (albedo, metallic) = if surface.subsurface {
+            (Vec3::new(1.0, 1.0, 1.0), 0.0)
+        } else {
+            (surface.albedo, surface.metallic)
+        };
+        let f0 = Vec3::lerp(Vec3::new(0.04, 0.04, 0.04), albedo, metallic);
+        let conductor = surface.conductor;
+        let fresnel = match conductor {
+            Some(c) => fresnel_conductor(wo.z, c.eta, c.k),
+            None => Vec3::fresnel_schlick(f0, wo.z),
+        };
+        // Conductors absorb whatever they don't reflect
+        let diffuse = match conductor {
+            Some(_) => Vec3::zero(),
+            None => albedo * (Vec3::new(1.0, 1.0, 1.0) - fresnel) * (1.0 - metallic),
+        };
+
+        let specular_weight = fresnel.luminance();
+        let diffuse_weight = diffuse.luminance();
+        let specular_probability = if diffuse_weight > 0.0 {
+            (specular_weight / (specular_weight + diffuse_weight)).clamp(0.05, 0.95)
+        } else {
+            1.0
+        };
+
+        Self {
+            diffuse,
+            f0,
+            conductor,
+            specular: surface.specular_distribution(),
+            specular_probability,
+            translucent: surface.subsurface,
+        }
+    }
+
+    fn has_non_delta(&self) -> bool {
+        self.specular_probability < 1.0 || !self.specular.is_smooth()
+    }
+
+    fn fresnel(&self, cos: f32) -> Vec3 {
+        match self.conductor {
+            Some(c) => fresnel_conductor(cos, c.eta, c.k),
+            None => Vec3::fresnel_schlick(self.f0, cos),
+        }
+    }
+
+    /// The cosine of `wi` on the side the diffuse lobe scatters into, negative for the other side.
+    fn diffuse_cos(&self, wi: Vec3) -> f32 {
+        if self.translucent {
+            -wi.z
+        } else {
+            wi.z
+        }
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let cos = self.diffuse_cos(wi);
+        let diffuse = if cos > 0.0 {
+            self.diffuse * (cos / PI)
+        } else {
+            Vec3::zero()
+        };
+        if wi.z <= 0.0 || self.specular.is_smooth() {
+            return diffuse;
+        }
+        let h = (wo + wi).normalize();
+        let fresnel = self.fresnel(wo.dot(h));
+        diffuse + fresnel * (self.specular.d(h) * self.specular.g2(wo, wi) / (4.0 * wo.z))
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let specular = if wi.z <= 0.0 || self.specular.is_smooth() {
+            0.0
+        } else {
+            self.specular.reflection_pdf(wo, wi)
+        };
+        let diffuse = self.diffuse_cos(wi).max(0.0) / PI;
+        self.specular_probability * specular + (1.0 - self.specular_probability) * diffuse
+    }
+
+    fn sample(&self, wo: Vec3, rng: &mut impl Rng) -> Option<LocalSample> {
+        let choose_specular = rng.gen::<f32>() < self.specular_probability;
+
+        if choose_specular && self.specular.is_smooth() {
+            let fresnel = self.fresnel(wo.z);
+            return Some(LocalSample {
+                wi: Vec3::new(-wo.x, -wo.y, wo.z),
+                weight: fresnel / self.specular_probability,
+                pdf: None,
+            });
+        }
+
+        let wi = if choose_specular {
+            let wi = reflect(wo, self.specular.sample_visible_normal(wo, rng.gen(), rng.gen()));
+            if wi.z <= 0.0 {
+                return None;
+            }
+            wi
+        } else {
+            let wi = cosine_hemisphere(rng);
+            if wi.z <= 0.0 {
+                return None;
+            }
+            if self.translucent {
+                Vec3::new(wi.x, wi.y, -wi.z)
+            } else {
+                wi
+            }
+        };
+        LocalSample::from_eval(wi, self.eval(wo, wi), self.pdf(wo, wi))
+    }
+}
+
+/// IOR of the lacquer in a clearcoat.
+const CLEARCOAT_IOR: f32 = 1.5;
+
+/// Resolution of the sheen albedo table along each of its axes, cosine and roughness.
+const SHEEN_TABLE_SIZE: usize = 16;
+
+/// The "Charlie" sheen distribution from Estevez and Kulla, "Production Friendly Microfacet Sheen BRDF" (2017),
+/// with the visibility term from Neubelt and Pettineo, "Crafting a Next-Gen Material Pipeline for The Order: 1886"
+/// (2013).
+#[derive(Copy, Clone, Debug)]
+struct Charlie {
+    alpha: f32,
+}
+
+impl Charlie {
+    /// Very low roughness makes the distribution spiky enough to alias, so it is kept above 0.07 as in glTF viewers.
+    fn from_roughness(roughness: f32) -> Self {
+        let roughness = roughness.clamp(0.07, 1.0);
+        Self {
+            alpha: roughness * roughness,
+        }
+    }
+
+    /// The BRDF times the cosine term.
+    fn eval(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).normalize();
+        let sin2 = (1.0 - h.z * h.z).max(0.0);
+        let inverse = 1.0 / self.alpha;
+        let d = (2.0 + inverse) * sin2.powf(0.5 * inverse) / (2.0 * PI);
+        let v = 1.0 / (4.0 * (wi.z + wo.z - wi.z * wo.z));
+        d * v * wi.z
+    }
+
+    /// The fraction of light arriving along `wo` that a white sheen reflects, integrated numerically.
+    fn directional_albedo(&self, wo: Vec3) -> f32 {
+        let steps = 64;
+        let mut sum = 0.0;
+        for i in 0..steps {
+            let cos = (i as f32 + 0.5) / steps as f32;
+            let sin = (1.0 - cos * cos).sqrt();
+            for j in 0..steps {
+                let phi = 2.0 * PI * (j as f32 + 0.5) / steps as f32;
+                sum += self.eval(wo, Vec3::new(sin * phi.cos(), sin * phi.sin(), cos));
+            }
+        }
+        // Uniform in cosine and angle around the normal, so each cell covers the same solid angle
+        sum * 2.0 * PI / (steps * steps) as f32
+    }
+
+    /// `directional_albedo` looked up from a table over cosine and roughness, built on first use.
+    fn albedo(cos: f32, roughness: f32) -> f32 {
+        static TABLE: OnceLock<Vec<f32>> = OnceLock::new();
+        let last = (SHEEN_TABLE_SIZE - 1) as f32;
+        let table = TABLE.get_or_init(|| {
+            let mut table = Vec::with_capacity(SHEEN_TABLE_SIZE * SHEEN_TABLE_SIZE);
+            for r in 0..SHEEN_TABLE_SIZE {
+                let sheen = Charlie::from_roughness(r as f32 / last);
+                for c in 0..SHEEN_TABLE_SIZE {
+                    let cos = (c as f32 / last).max(0.02);
+                    let wo = Vec3::new((1.0 - cos * cos).sqrt(), 0.0, cos);
+                    table.push(sheen.directional_albedo(wo));
+                }
+            }
+            table
+        });
+
+        let x = cos.clamp(0.0, 1.0) * last;
+        let y = roughness.clamp(0.0, 1.0) * last;
+        let (x0, y0) = (x.floor().min(last - 1.0), y.floor().min(last - 1.0));
+        let (fx, fy) = (x - x0, y - y0);
+        let at = |c: f32, r: f32| table[r as usize * SHEEN_TABLE_SIZE + c as usize];
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        lerp(
+            lerp(at(x0, y0), at(x0 + 1.0, y0), fx),
+            lerp(at(x0, y0 + 1.0), at(x0 + 1.0, y0 + 1.0), fx),
+            fy,
+        )
+    }
+}
+
+/// An `Opaque` base under optional sheen and clearcoat layers, as in the glTF sheen and clearcoat extensions. Each
+/// layer passes on whatever it doesn't reflect to the ones beneath.
+struct Layered {
+    base: Opaque,
+    /// The clearcoat's distribution and how much of the surface it covers.
+    coat: Option<(Ggx, f32)>,
+    sheen: Option<(Charlie, Vec3)>,
+    /// Fractions of the light along `wo` let through by the coat and by the sheen.
+    coat_transmission: f32,
+    sheen_transmission: f32,
+    coat_probability: f32,
+    sheen_probability: f32,
+}
+
+impl Layered {
+    fn new(surface: &Surface, wo: Vec3) -> Self {
+        let coat = surface.clearcoat.map(|c| (Ggx::from_roughness(c.roughness), c.weight));
+        let coat_reflectance = coat.map_or(0.0, |(_, weight)| weight * fresnel_dielectric(wo.z, CLEARCOAT_IOR));
+        let coat_transmission = 1.0 - coat_reflectance;
+
+        let sheen = surface.sheen.map(|s| (Charlie::from_roughness(s.roughness), s.colour));
+        let sheen_reflectance = surface.sheen.map_or(0.0, |s| {
+            s.colour.x.max(s.colour.y).max(s.colour.z) * Charlie::albedo(wo.z, s.roughness)
+        });
+        let sheen_transmission = (1.0 - sheen_reflectance).max(0.0);
+
+        // Pick each layer in proportion to how much of the light it reflects
+        let sheen_weight = coat_transmission * sheen_reflectance;
+        let base_weight = coat_transmission * sheen_transmission;
+        let total = coat_reflectance + sheen_weight + base_weight;
+        let (coat_probability, sheen_probability) = if total > 0.0 {
+            (coat_reflectance / total, sheen_weight / total)
+        } else {
+            (0.0, 0.0)
+        };
+
+        Self {
+            base: Opaque::new(surface, wo),
+            coat,
+            sheen,
+            coat_transmission,
+            sheen_transmission,
+            coat_probability,
+            sheen_probability,
+        }
+    }
+
+    fn has_non_delta(&self) -> bool {
+        self.base.has_non_delta() || self.sheen.is_some() || self.coat.is_some_and(|(ggx, _)| !ggx.is_smooth())
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let coat = match self.coat {
+            Some((ggx, weight)) if wi.z > 0.0 && !ggx.is_smooth() => {
+                let h = (wo + wi).normalize();
+                weight * fresnel_dielectric(wo.dot(h), CLEARCOAT_IOR) * ggx.d(h) * ggx.g2(wo, wi) / (4.0 * wo.z)
+            }
+            _ => 0.0,
+        };
+        let sheen = self
+            .sheen
+            .map_or(Vec3::zero(), |(charlie, colour)| colour * charlie.eval(wo, wi));
+        let beneath = sheen + self.base.eval(wo, wi) * self.sheen_transmission;
+        Vec3::new(coat, coat, coat) + beneath * self.coat_transmission
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let coat = match self.coat {
+            Some((ggx, _)) if wi.z > 0.0 && !ggx.is_smooth() => ggx.reflection_pdf(wo, wi),
+            _ => 0.0,
+        };
+        let sheen = wi.z.max(0.0) / PI;
+        let base_probability = 1.0 - self.coat_probability - self.sheen_probability;
+        self.coat_probability * coat + self.sheen_probability * sheen + base_probability * self.base.pdf(wo, wi)
+    }
+
+    fn sample(&self, wo: Vec3, rng: &mut impl Rng) -> Option<LocalSample> {
+        let u = rng.gen::<f32>();
+        let wi = if u < self.coat_probability {
+            let (ggx, weight) = self.coat?;
+            if ggx.is_smooth() {
+                let reflectance = weight * fresnel_dielectric(wo.z, CLEARCOAT_IOR) / self.coat_probability;
+                return Some(LocalSample {
+                    wi: Vec3::new(-wo.x, -wo.y, wo.z),
+                    weight: Vec3::new(reflectance, reflectance, reflectance),
+                    pdf: None,
+                });
+            }
+            reflect(wo, ggx.sample_visible_normal(wo, rng.gen(), rng.gen()))
+        } else if u < self.coat_probability + self.sheen_probability {
+            cosine_hemisphere(rng)
+        } else {
+            let sample = self.base.sample(wo, rng)?;
+            if sample.pdf.is_none() {
+                let base_probability = 1.0 - self.coat_probability - self.sheen_probability;
+                let transmission = self.coat_transmission * self.sheen_transmission / base_probability;
+                return Some(LocalSample {
+                    weight: sample.weight * transmission,
+                    ..sample
+                });
+            }
+            sample.wi
+        };
+        if wi.z <= 0.0 && !self.base.translucent {
+            return None;
+        }
+        LocalSample::from_eval(wi, self.eval(wo, wi), self.pdf(wo, wi))
+    }
+}
+
+/// Cosine-weighted direction about +z, with a density of `cos(theta) / PI`.
+fn cosine_hemisphere(rng: &mut impl Rng) -> Vec3 {
+    let r = rng.gen::<f32>().sqrt();
+    let phi = 2.0 * PI * rng.gen::<f32>();
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - r * r).max(0.0).sqrt())
+}
+
+/// Rough glass after Walter et al., "Microfacet Models for Refraction through Rough Surfaces" (2007), with
+/// reflection and refraction chosen in proportion to the exact Fresnel term of the sampled microfacet.
+struct Dielectric {
+    /// IOR beyond the surface over the IOR on the side of `wo`.
+    eta: f32,
+    tint: Vec3,
+    distribution: Ggx,
+}
+
+impl Dielectric {
+    fn new(surface: &Surface, eta: f32) -> Self {
+        Self {
+            eta,
+            tint: surface.transmission_tint(),
+            distribution: surface.specular_distribution(),
+        }
+    }
+
+    /// The microfacet normal that scatters `wo` into `wi`, facing `wo`, or `None` for back-facing configurations.
+    fn half_vector(&self, wo: Vec3, wi: Vec3) -> Option<Vec3> {
+        let reflection = wi.z > 0.0;
+        let h = if reflection { wo + wi } else { wo + wi * self.eta };
+        if h.mag_squared() == 0.0 {
+            return None;
+        }
+        let h = h.normalize();
+        let h = if h.z < 0.0 { h * -1.0 } else { h };
+
+        // Both directions must see the same side of the microfacet as they do of the macro surface
+        if wo.dot(h) <= 0.0 || (wi.dot(h) > 0.0) != reflection {
+            return None;
+        }
+        Some(h)
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if self.distribution.is_smooth() || wi.z == 0.0 {
+            return Vec3::zero();
+        }
+        let Some(h) = self.half_vector(wo, wi) else {
+            return Vec3::zero();
+        };
+        let fresnel = fresnel_dielectric(wo.dot(h), self.eta);
+        let dg = self.distribution.d(h) * self.distribution.g2(wo, wi);
+
+        if wi.z > 0.0 {
+            let f = fresnel * dg / (4.0 * wo.z);
+            return Vec3::new(f, f, f);
+        }
+
+        // Radiance is compressed into the smaller solid angle on entering a denser medium, hence the 1/eta²
+        let denom = wi.dot(h) + wo.dot(h) / self.eta;
+        let f = (1.0 - fresnel) * dg * (wi.dot(h) * wo.dot(h)).abs() / (denom * denom * wo.z) / (self.eta * self.eta);
+        self.tint * f
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if self.distribution.is_smooth() || wi.z == 0.0 {
+            return 0.0;
+        }
+        let Some(h) = self.half_vector(wo, wi) else {
+            return 0.0;
+        };
+        let fresnel = fresnel_dielectric(wo.dot(h), self.eta);
+        let pdf_h = self.distribution.visible_normal_pdf(wo, h);
+
+        if wi.z > 0.0 {
+            fresnel * pdf_h / (4.0 * wo.dot(h))
+        } else {
+            let denom = wi.dot(h) + wo.dot(h) / self.eta;
+            (1.0 - fresnel) * pdf_h * wi.dot(h).abs() / (denom * denom)
+        }
+    }
+
+    fn sample(&self, wo: Vec3, rng: &mut impl Rng) -> Option<LocalSample> {
+        if self.distribution.is_smooth() {
+            let normal = Vec3::new(0.0, 0.0, 1.0);
+            let fresnel = fresnel_dielectric(wo.z, self.eta);
+            return match refract(wo, normal, self.eta) {
+                Some(wi) if rng.gen::<f32>() >= fresnel => Some(LocalSample {
+                    wi,
+                    weight: self.tint / (self.eta * self.eta),
+                    pdf: None,
+                }),
+                _ => Some(LocalSample {
+                    wi: Vec3::new(-wo.x, -wo.y, wo.z),
+                    weight: Vec3::new(1.0, 1.0, 1.0),
+                    pdf: None,
+                }),
+            };
+        }
+
+        let h = self.distribution.sample_visible_normal(wo, rng.gen(), rng.gen());
+        let fresnel = fresnel_dielectric(wo.dot(h), self.eta);
+        let wi = match refract(wo, h, self.eta) {
+            Some(refracted) if rng.gen::<f32>() >= fresnel => refracted,
+            _ => reflect(wo, h),
+        };
+        LocalSample::from_eval(wi, self.eval(wo, wi), self.pdf(wo, wi))
+    }
+}
+
+enum Lobes {
+    Opaque(Opaque),
+    Layered(Layered),
+    Dielectric(Dielectric),
+}
+
+/// The scattering function at a surface point. `normal` faces the side `wo` arrives from, and directions point
+/// away from the surface.
+pub struct Bsdf {
+    frame: Frame,
+    wo: Vec3,
+    lobes: Lobes,
+}
+
+impl Bsdf {
+    /// `eta` is the IOR beyond the surface over the IOR on the side of `wo`; opaque surfaces ignore it.
+    pub fn new(surface: &Surface, normal: Vec3, wo: Vec3, eta: f32) -> Self {
+        Self::oriented(surface, Frame::from_normal(normal), wo, eta)
+    }
+
+    /// As `new`, with anisotropic lobes stretched along the frame's tangent turned by the surface's anisotropy
+    /// rotation.
+    pub fn oriented(surface: &Surface, frame: Frame, wo: Vec3, eta: f32) -> Self {
+        let frame = frame.rotated(surface.anisotropy.map_or(0.0, |a| a.rotation));
+        let mut wo = frame.to_local(wo);
+        wo.z = wo.z.max(1e-4);
+        let wo = wo.normalize();
+
+        let lobes = if surface.transmission > 0.0 && !surface.subsurface {
+            Lobes::Dielectric(Dielectric::new(surface, eta))
+        } else if surface.clearcoat.is_some() || surface.sheen.is_some() {
+            Lobes::Layered(Layered::new(surface, wo))
+        } else {
+            Lobes::Opaque(Opaque::new(surface, wo))
+        };
+
+        Self { frame, wo, lobes }
+    }
+
+    /// Whether any lobe can be evaluated for an arbitrary direction, i.e. whether light sampling is worthwhile.
+    pub fn has_non_delta(&self) -> bool {
+        match &self.lobes {
+            Lobes::Opaque(opaque) => opaque.has_non_delta(),
+            Lobes::Layered(layered) => layered.has_non_delta(),
+            Lobes::Dielectric(dielectric) => !dielectric.distribution.is_smooth(),
+        }
+    }
+
+    /// The BSDF times the cosine term for light arriving from `wi`. Perfectly specular lobes contribute nothing.
+    pub fn eval(&self, wi: Vec3) -> Vec3 {
+        let wi = self.frame.to_local(wi);
+        match &self.lobes {
+            Lobes::Opaque(opaque) => opaque.eval(self.wo, wi),
+            Lobes::Layered(layered) => layered.eval(self.wo, wi),
+            Lobes::Dielectric(dielectric) => dielectric.eval(self.wo, wi),
+        }
+    }
+
+    /// Density of `sample` producing `wi`, excluding perfectly specular lobes.
+    pub fn pdf(&self, wi: Vec3) -> f32 {
+        let wi = self.frame.to_local(wi);
+        match &self.lobes {
+            Lobes::Opaque(opaque) => opaque.pdf(self.wo, wi),
+            Lobes::Layered(layered) => layered.pdf(self.wo, wi),
+            Lobes::Dielectric(dielectric) => dielectric.pdf(self.wo, wi),
+        }
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<BsdfSample> {
+        let sample = match &self.lobes {
+            Lobes::Opaque(opaque) => opaque.sample(self.wo, rng),
+            Lobes::Layered(layered) => layered.sample(self.wo, rng),
+            Lobes::Dielectric(dielectric) => dielectric.sample(self.wo, rng),
+        }?;
+        Some(BsdfSample {
+            direction: self.frame.to_world(sample.wi),
+            weight: sample.weight,
+            pdf: sample.pdf,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Anisotropy, Clearcoat, Material, Metal, Sheen};
+    use crate::texture::TexCoord;
+    use crate::vec2::Vec2;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    fn surface(albedo: f32, metallic: f32, roughness: f32) -> Surface {
+        Surface {
+            emission: Vec3::zero(),
+            albedo: Vec3::new(albedo, albedo, albedo),
+            metallic,
+            roughness,
+            transmission: 0.0,
+            ior: 1.5,
+            medium: None,
+            subsurface: false,
+            dispersion: None,
+            clearcoat: None,
+            sheen: None,
+            conductor: None,
+            anisotropy: None,
+            priority: 0,
+        }
+    }
+
+    fn uniform_hemisphere(rng: &mut SmallRng) -> Vec3 {
+        let z = rng.gen::<f32>();
+        let r = (1.0 - z * z).sqrt();
+        let phi = 2.0 * PI * rng.gen::<f32>();
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let frame = Frame::from_normal(Vec3::new(1.0, 2.0, -0.5).normalize());
+        let v = Vec3::new(0.3, -0.2, 0.9);
+        let back = frame.to_world(frame.to_local(v));
+        assert!((back - v).mag() < 1e-5);
+        assert!((frame.to_local(frame.normal) - Vec3::new(0.0, 0.0, 1.0)).mag() < 1e-5);
+    }
+
+    #[test]
+    fn test_ggx_d_integrates_to_one() {
+        // The projected microfacet area, D(h) cos(h), covers the macro surface exactly once
+        let ggx = Ggx::from_roughness(0.6);
+        let mut rng = SmallRng::seed_from_u64(3);
+        let count = 200_000;
+        let sum: f32 = (0..count)
+            .map(|_| {
+                let h = uniform_hemisphere(&mut rng);
+                ggx.d(h) * h.z * 2.0 * PI
+            })
+            .sum();
+        let mean = sum / count as f32;
+        assert!((mean - 1.0).abs() < 0.03, "integral was {}", mean);
+    }
+
+    #[test]
+    fn test_anisotropic_ggx_d_integrates_to_one() {
+        let ggx = Ggx::anisotropic(0.5, 0.8);
+        assert!(ggx.alpha_x > ggx.alpha_y);
+        let mut rng = SmallRng::seed_from_u64(7);
+        let count = 200_000;
+        let sum: f32 = (0..count)
+            .map(|_| {
+                let h = uniform_hemisphere(&mut rng);
+                ggx.d(h) * h.z * 2.0 * PI
+            })
+            .sum();
+        let mean = sum / count as f32;
+        assert!((mean - 1.0).abs() < 0.03, "integral was {}", mean);
+    }
+
+    #[test]
+    fn test_frame_follows_tangent() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let frame = Frame::from_normal_and_tangent(normal, Vec3::new(2.0, 0.0, 1.0));
+        assert!((frame.tangent - Vec3::new(1.0, 0.0, 0.0)).mag() < 1e-6);
+        assert!((frame.bitangent - Vec3::new(0.0, 1.0, 0.0)).mag() < 1e-6);
+        let turned = frame.rotated(PI / 2.0);
+        assert!((turned.tangent - Vec3::new(0.0, 1.0, 0.0)).mag() < 1e-6);
+        // A tangent along the normal says nothing about the surface, so any frame will do
+        let fallback = Frame::from_normal_and_tangent(normal, normal);
+        assert!(fallback.tangent.dot(normal).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_brushed_highlight_stretches_along_tangent() {
+        let brushed = Surface {
+            anisotropy: Some(Anisotropy {
+                strength: 0.9,
+                rotation: 0.0,
+            }),
+            ..surface(1.0, 1.0, 0.2)
+        };
+        let frame = Frame::from_normal_and_tangent(Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 0.0));
+        let bsdf = Bsdf::oriented(&brushed, frame, Vec3::new(0.0, 0.0, 1.0), 1.5);
+        let along = bsdf.eval(Vec3::new(0.5, 0.0, 0.866));
+        let across = bsdf.eval(Vec3::new(0.0, 0.5, 0.866));
+        assert!(along.x > across.x * 10.0, "{} vs {}", along.x, across.x);
+
+        // Turning the grain a quarter turn swaps them over
+        let turned = Surface {
+            anisotropy: Some(Anisotropy {
+                strength: 0.9,
+                rotation: PI / 2.0,
+            }),
+            ..brushed
+        };
+        let bsdf = Bsdf::oriented(&turned, frame, Vec3::new(0.0, 0.0, 1.0), 1.5);
+        assert!((bsdf.eval(Vec3::new(0.0, 0.5, 0.866)) - along).mag() < along.mag() * 1e-3);
+
+        let mut rng = SmallRng::seed_from_u64(19);
+        let bsdf = Bsdf::oriented(&brushed, frame, Vec3::new(0.3, -0.4, 0.8).normalize(), 1.5);
+        for _ in 0..200 {
+            if let Some(sample) = bsdf.sample(&mut rng) {
+                let pdf = sample.pdf.unwrap();
+                assert!((pdf - bsdf.pdf(sample.direction)).abs() <= pdf * 1e-3);
+                let expected = bsdf.eval(sample.direction) / pdf;
+                assert!((sample.weight - expected).mag() <= expected.mag() * 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reflection_pdf_matches_samples() {
+        let ggx = Ggx::from_roughness(0.5);
+        let wo = Vec3::new(0.4, -0.2, 0.8).normalize();
+        let mut rng = SmallRng::seed_from_u64(9);
+        for _ in 0..100 {
+            let h = ggx.sample_visible_normal(wo, rng.gen(), rng.gen());
+            assert!(h.z > 0.0 && wo.dot(h) >= 0.0);
+            let wi = reflect(wo, h);
+            let expected = ggx.visible_normal_pdf(wo, h) / (4.0 * wo.dot(h));
+            let pdf = ggx.reflection_pdf(wo, wi);
+            assert!((pdf - expected).abs() <= expected * 1e-3, "{} vs {}", pdf, expected);
+        }
+    }
+
+    #[test]
+    fn test_sample_weight_is_eval_over_pdf() {
+        let bsdf = Bsdf::new(
+            &surface(0.7, 0.3, 0.4),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.3, -1.0, 0.1).normalize(),
+            1.5,
+        );
+        let mut rng = SmallRng::seed_from_u64(4);
+        for _ in 0..100 {
+            if let Some(sample) = bsdf.sample(&mut rng) {
+                let pdf = sample.pdf.unwrap();
+                assert!((pdf - bsdf.pdf(sample.direction)).abs() <= pdf * 1e-3);
+                let expected = bsdf.eval(sample.direction) / pdf;
+                assert!((sample.weight - expected).mag() <= expected.mag() * 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rough_white_metal_conserves_energy() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let mut rng = SmallRng::seed_from_u64(12);
+        for roughness in [0.1, 0.3, 0.5] {
+            let bsdf = Bsdf::new(
+                &surface(1.0, 1.0, roughness),
+                normal,
+                Vec3::new(0.5, 0.0, 0.8).normalize(),
+                1.5,
+            );
+            let count = 20_000;
+            let total = (0..count)
+                .filter_map(|_| bsdf.sample(&mut rng))
+                .fold(Vec3::zero(), |acc, s| acc + s.weight)
+                / count as f32;
+            // Single scattering loses energy to masking as roughness grows, but never gains any
+            assert!(
+                total.x <= 1.01 && total.x > 0.85,
+                "roughness {} reflected {}",
+                roughness,
+                total.x
+            );
+        }
+    }
+
+    #[test]
+    fn test_smooth_metal_is_a_mirror() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let bsdf = Bsdf::new(&surface(1.0, 1.0, 0.0), normal, wo, 1.5);
+        let mut rng = SmallRng::seed_from_u64(1);
+        let sample = bsdf.sample(&mut rng).unwrap();
+        assert!((sample.direction - Vec3::new(-0.6, 0.0, 0.8)).mag() < 1e-5);
+        assert!(sample.pdf.is_none());
+        assert!(!bsdf.has_non_delta());
+        assert_eq!(bsdf.eval(Vec3::new(-0.6, 0.0, 0.8)), Vec3::zero());
+    }
+
+    fn layered(albedo: f32, clearcoat: Option<Clearcoat>, sheen: Option<Sheen>) -> Surface {
+        Surface {
+            clearcoat,
+            sheen,
+            ..surface(albedo, 0.0, 0.6)
+        }
+    }
+
+    fn directional_albedo(bsdf: &Bsdf, rng: &mut SmallRng) -> Vec3 {
+        let count = 20_000;
+        (0..count)
+            .filter_map(|_| bsdf.sample(rng))
+            .fold(Vec3::zero(), |acc, s| acc + s.weight)
+            / count as f32
+    }
+
+    #[test]
+    fn test_layered_sample_weight_is_eval_over_pdf() {
+        let surface = layered(
+            0.5,
+            Some(Clearcoat {
+                weight: 0.8,
+                roughness: 0.3,
+            }),
+            Some(Sheen {
+                colour: Vec3::new(0.9, 0.6, 0.3),
+                roughness: 0.5,
+            }),
+        );
+        let bsdf = Bsdf::new(
+            &surface,
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.5, 0.1, 0.6).normalize(),
+            1.5,
+        );
+        let mut rng = SmallRng::seed_from_u64(14);
+        for _ in 0..200 {
+            if let Some(sample) = bsdf.sample(&mut rng) {
+                let pdf = sample.pdf.unwrap();
+                assert!((pdf - bsdf.pdf(sample.direction)).abs() <= pdf * 1e-3);
+                let expected = bsdf.eval(sample.direction) / pdf;
+                assert!((sample.weight - expected).mag() <= expected.mag() * 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_clearcoat_over_white_conserves_energy() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let mut rng = SmallRng::seed_from_u64(2);
+        for roughness in [0.0, 0.2] {
+            let coat = Clearcoat { weight: 1.0, roughness };
+            for wo in [
+                Vec3::new(0.0, 0.0, 1.0),
+                Vec3::new(0.8, 0.0, 0.6),
+                Vec3::new(0.95, 0.0, 0.31),
+            ] {
+                let bsdf = Bsdf::new(&layered(1.0, Some(coat), None), normal, wo, 1.5);
+                let total = directional_albedo(&bsdf, &mut rng);
+                assert!(
+                    total.x <= 1.02 && total.x > 0.85,
+                    "roughness {} at {} reflected {}",
+                    roughness,
+                    wo,
+                    total.x
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_clearcoat_adds_a_reflection() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let coat = Clearcoat {
+            weight: 1.0,
+            roughness: 0.1,
+        };
+        let plain = Bsdf::new(&surface(0.2, 0.0, 1.0), normal, wo, 1.5);
+        let coated = Bsdf::new(
+            &Surface {
+                clearcoat: Some(coat),
+                ..surface(0.2, 0.0, 1.0)
+            },
+            normal,
+            wo,
+            1.5,
+        );
+        let mirror = Vec3::new(-0.6, 0.0, 0.8);
+        assert!(coated.eval(mirror).x > plain.eval(mirror).x * 5.0);
+    }
+
+    #[test]
+    fn test_sheen_brightens_grazing_angles() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let mut rng = SmallRng::seed_from_u64(5);
+        let velvet = Sheen {
+            colour: Vec3::new(1.0, 1.0, 1.0),
+            roughness: 0.5,
+        };
+        let grazing = Vec3::new(0.98, 0.0, 0.2).normalize();
+        let plain = directional_albedo(&Bsdf::new(&layered(0.1, None, None), normal, grazing, 1.5), &mut rng);
+        let sheened = directional_albedo(
+            &Bsdf::new(&layered(0.1, None, Some(velvet)), normal, grazing, 1.5),
+            &mut rng,
+        );
+        assert!(sheened.x > plain.x + 0.05, "{} vs {}", sheened.x, plain.x);
+
+        // A white sheen over a white base stays within the light it is given
+        for wo in [Vec3::new(0.0, 0.0, 1.0), grazing] {
+            let bsdf = Bsdf::new(&layered(1.0, None, Some(velvet)), normal, wo, 1.5);
+            let total = directional_albedo(&bsdf, &mut rng);
+            assert!(total.x <= 1.03, "reflected {} at {}", total.x, wo);
+        }
+    }
+
+    #[test]
+    fn test_sheen_albedo_table_matches_integral() {
+        for (cos, roughness) in [(0.5f32, 0.4), (0.9, 0.8), (0.2, 1.0)] {
+            let wo = Vec3::new((1.0 - cos * cos).sqrt(), 0.0, cos);
+            let exact = Charlie::from_roughness(roughness).directional_albedo(wo);
+            let table = Charlie::albedo(cos, roughness);
+            assert!((0.0..=1.0).contains(&table));
+            assert!((exact - table).abs() < 0.02, "{} vs {}", exact, table);
+        }
+    }
+
+    #[test]
+    fn test_subsurface_base_transmits() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let translucent = Surface {
+            subsurface: true,
+            ..surface(0.2, 0.0, 0.3)
+        };
+        let bsdf = Bsdf::new(&translucent, normal, Vec3::new(0.3, 0.0, 0.9).normalize(), 1.4);
+        let mut rng = SmallRng::seed_from_u64(6);
+        let count = 20_000;
+        let mut total = Vec3::zero();
+        let mut transmitted = 0;
+        for _ in 0..count {
+            let Some(sample) = bsdf.sample(&mut rng) else {
+                continue;
+            };
+            transmitted += (sample.direction.z < 0.0) as u32;
+            let pdf = sample.pdf.unwrap();
+            assert!((pdf - bsdf.pdf(sample.direction)).abs() <= pdf * 1e-3);
+            total += sample.weight;
+        }
+        // Light mostly goes in, untinted by the surface albedo, with a little lost to the rough specular layer
+        assert!(transmitted > count / 2);
+        let total = total / count as f32;
+        assert!(total.x <= 1.01 && total.x > 0.9, "{}", total.x);
+        assert!((total.x - total.z).abs() < 1e-3);
+        assert!(bsdf.eval(Vec3::new(0.0, 0.6, 0.8)).x < bsdf.eval(Vec3::new(0.0, 0.6, -0.8)).x);
+    }
+
+    fn glass(roughness: f32) -> Surface {
+        Surface {
+            transmission: 1.0,
+            ..surface(1.0, 0.0, roughness)
+        }
+    }
+
+    #[test]
+    fn test_fresnel_dielectric() {
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-6);
+        assert_eq!(fresnel_dielectric(0.0, 1.5), 1.0);
+        // Total internal reflection leaving glass beyond the critical angle
+        assert_eq!(fresnel_dielectric(0.5, 1.0 / 1.5), 1.0);
+        assert!(fresnel_dielectric(0.9, 1.0 / 1.5) < 0.1);
+    }
+
+    #[test]
+    fn test_fresnel_conductor() {
+        let gold = Metal::Gold.conductor();
+        // Straight on it matches the closed form, and every conductor turns into a mirror at grazing angles
+        let normal = fresnel_conductor(1.0, gold.eta, gold.k);
+        assert!((normal - gold.normal_reflectance()).mag() < 1e-4, "{}", normal);
+        assert!((fresnel_conductor(0.0, gold.eta, gold.k) - Vec3::new(1.0, 1.0, 1.0)).mag() < 1e-4);
+        // With no extinction it is a dielectric
+        let glass = fresnel_conductor(0.7, Vec3::new(1.5, 1.5, 1.5), Vec3::zero());
+        assert!((glass.x - fresnel_dielectric(0.7, 1.5)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_smooth_gold_reflects_its_fresnel() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let at = TexCoord {
+            uv: Vec2::new(0.0, 0.0),
+            point: Vec3::zero(),
+            local: Vec3::zero(),
+        };
+        let surface = Material::metal(Metal::Gold, 0.0).surface(&[], at);
+        let bsdf = Bsdf::new(&surface, normal, wo, 1.0);
+        let sample = bsdf.sample(&mut SmallRng::seed_from_u64(3)).unwrap();
+        let gold = Metal::Gold.conductor();
+        assert!((sample.weight - fresnel_conductor(0.8, gold.eta, gold.k)).mag() < 1e-5);
+    }
+
+    #[test]
+    fn test_refract_obeys_snell() {
+        let w = Vec3::new(0.6, 0.0, 0.8);
+        let t = refract(w, Vec3::new(0.0, 0.0, 1.0), 1.5).unwrap();
+        assert!((t.mag() - 1.0).abs() < 1e-5);
+        assert!((t.x * -1.5 - 0.6).abs() < 1e-5);
+        assert!(t.z < 0.0);
+        assert!(refract(w, Vec3::new(0.0, 0.0, 1.0), 0.5).is_none());
+    }
+
+    #[test]
+    fn test_rough_glass_sample_weight_is_eval_over_pdf() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let mut rng = SmallRng::seed_from_u64(21);
+        for eta in [1.5, 1.0 / 1.5] {
+            let bsdf = Bsdf::new(&glass(0.4), normal, Vec3::new(0.3, 0.2, 0.9).normalize(), eta);
+            let mut transmitted = 0;
+            for _ in 0..200 {
+                if let Some(sample) = bsdf.sample(&mut rng) {
+                    transmitted += (sample.direction.z < 0.0) as u32;
+                    let pdf = sample.pdf.unwrap();
+                    assert!((pdf - bsdf.pdf(sample.direction)).abs() <= pdf * 1e-3);
+                    let expected = bsdf.eval(sample.direction) / pdf;
+                    assert!((sample.weight - expected).mag() <= expected.mag() * 1e-3);
+                }
+            }
+            assert!(transmitted > 100);
+        }
+    }
+
+    #[test]
+    fn test_rough_glass_conserves_energy() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let mut rng = SmallRng::seed_from_u64(8);
+        for eta in [1.5, 1.0 / 1.5] {
+            let bsdf = Bsdf::new(&glass(0.3), normal, Vec3::new(0.4, 0.0, 0.9).normalize(), eta);
+            let count = 20_000;
+            // Undo the 1/eta² radiance scaling on refraction to count energy rather than radiance
+            let total = (0..count)
+                .filter_map(|_| bsdf.sample(&mut rng))
+                .map(|s| {
+                    if s.direction.z < 0.0 {
+                        s.weight.x * eta * eta
+                    } else {
+                        s.weight.x
+                    }
+                })
+                .sum::<f32>()
+                / count as f32;
+            assert!(total <= 1.01 && total > 0.9, "eta {} scattered {}", eta, total);
+        }
+    }
+
+    #[test]
+    fn test_smooth_glass_splits_by_fresnel() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let bsdf = Bsdf::new(&glass(0.0), normal, normal, 1.5);
+        let mut rng = SmallRng::seed_from_u64(2);
+        let count = 10_000;
+        let reflected = (0..count)
+            .filter_map(|_| bsdf.sample(&mut rng))
+            .filter(|s| s.direction.z > 0.0)
+            .count();
+        assert!((reflected as f32 / count as f32 - 0.04).abs() < 0.01);
+        assert!(!bsdf.has_non_delta());
+    }
+}
@@ -1,5 +1,8 @@
+use rand::Rng;
+
 use crate::entity::Entity;
 use crate::ray::Ray;
+use crate::texture::Texture;
 use crate::vec3::Vec3;
 use std::iter::FromIterator;
 
@@ -102,18 +105,27 @@ impl Tree {
         Self { node, unbound }
     }
 
-    pub fn find_intersection(&self, ray: Ray) -> Option<crate::intersection::Intersection> {
+    /// The closest hit along `ray`, passing through surfaces where their material's opacity lets it.
+    pub fn find_intersection(
+        &self,
+        ray: Ray,
+        textures: &[Texture],
+        rng: &mut impl Rng,
+    ) -> Option<crate::intersection::Intersection> {
         let mut closest = crate::intersection::Intersection::empty();
 
         for entity in &self.unbound {
-            if let Some(hit) = entity.intersection(ray) {
-                closest = crate::intersection::Intersection::closest(hit, closest);
+            if let Some(hit) = entity
+                .intersection(ray)
+                .filter(|h| h.dist < closest.dist && h.is_opaque(textures, rng))
+            {
+                closest = hit;
             }
         }
 
         let inv_dir = Vec3::new(1.0, 1.0, 1.0) / ray.direction;
         if self.node.aabb().intersect(ray, inv_dir).is_some() {
-            self.node.find_intersection(ray, inv_dir, &mut closest);
+            self.node.find_intersection(ray, inv_dir, textures, rng, &mut closest);
         }
 
         if closest.entity.is_some() {
@@ -125,7 +137,14 @@ impl Tree {
 }
 
 impl Node {
-    fn find_intersection(&self, ray: Ray, inv_dir: Vec3, closest: &mut crate::intersection::Intersection) {
+    fn find_intersection(
+        &self,
+        ray: Ray,
+        inv_dir: Vec3,
+        textures: &[Texture],
+        rng: &mut impl Rng,
+        closest: &mut crate::intersection::Intersection,
+    ) {
         match self {
             Node::Branch { left, right, .. } => {
                 let t_left = left.aabb().intersect(ray, inv_dir);
@@ -135,28 +154,28 @@ impl Node {
                     (Some(tl), Some(tr)) => {
                         if tl < tr {
                             if tl < closest.dist {
-                                left.find_intersection(ray, inv_dir, closest);
+                                left.find_intersection(ray, inv_dir, textures, rng, closest);
                             }
                             if tr < closest.dist {
-                                right.find_intersection(ray, inv_dir, closest);
+                                right.find_intersection(ray, inv_dir, textures, rng, closest);
                             }
                         } else {
                             if tr < closest.dist {
-                                right.find_intersection(ray, inv_dir, closest);
+                                right.find_intersection(ray, inv_dir, textures, rng, closest);
                             }
                             if tl < closest.dist {
-                                left.find_intersection(ray, inv_dir, closest);
+                                left.find_intersection(ray, inv_dir, textures, rng, closest);
                             }
                         }
                     }
                     (Some(tl), None) => {
                         if tl < closest.dist {
-                            left.find_intersection(ray, inv_dir, closest);
+                            left.find_intersection(ray, inv_dir, textures, rng, closest);
                         }
                     }
                     (None, Some(tr)) => {
                         if tr < closest.dist {
-                            right.find_intersection(ray, inv_dir, closest);
+                            right.find_intersection(ray, inv_dir, textures, rng, closest);
                         }
                     }
                     (None, None) => {}
@@ -164,8 +183,11 @@ impl Node {
             }
             Node::Leaf { entities, .. } => {
                 for entity in entities {
-                    if let Some(hit) = entity.intersection(ray) {
-                        *closest = crate::intersection::Intersection::closest(hit, *closest);
+                    if let Some(hit) = entity
+                        .intersection(ray)
+                        .filter(|h| h.dist < closest.dist && h.is_opaque(textures, rng))
+                    {
+                        *closest = hit;
                     }
                 }
             }
@@ -3,6 +3,7 @@ use wasm_bindgen::prelude::*;
 use crate::vec3::Vec3;
 
 #[wasm_bindgen]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Camera {
     pub position: Vec3,
     pub rotation: Vec3,
@@ -0,0 +1,246 @@
+use crate::ray::Ray;
+use crate::traceable::Traceable;
+use crate::vec2::Vec2;
+use crate::vec3::Vec3;
+
+/// An axis-aligned box centred on its position.
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cuboid {
+    pub half_size: Vec3,
+}
+
+fn axes(v: Vec3) -> [f32; 3] {
+    [v.x, v.y, v.z]
+}
+
+fn unit(axis: usize) -> Vec3 {
+    match axis {
+        0 => Vec3::new(1.0, 0.0, 0.0),
+        1 => Vec3::new(0.0, 1.0, 0.0),
+        _ => Vec3::new(0.0, 0.0, 1.0),
+    }
+}
+
+/// One side of the box, by the axis it faces along and which way.
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Face {
+    axis: usize,
+    sign: f32,
+}
+
+impl Cuboid {
+    pub fn new(size: Vec3) -> Self {
+        Self {
+            half_size: Vec3::new(size.x.abs(), size.y.abs(), size.z.abs()) * 0.5,
+        }
+    }
+
+    fn face_area(&self, axis: usize) -> f32 {
+        let half = axes(self.half_size);
+        4.0 * half[(axis + 1) % 3] * half[(axis + 2) % 3]
+    }
+
+    /// The face a point on the surface lies on: whichever axis it is furthest out along, relative to the size.
+    fn face_at(&self, local: Vec3) -> Face {
+        let half = axes(self.half_size);
+        let local = axes(local);
+        let axis = (0..3)
+            .max_by(|&a, &b| {
+                let ra = local[a].abs() / half[a].max(f32::EPSILON);
+                let rb = local[b].abs() / half[b].max(f32::EPSILON);
+                ra.total_cmp(&rb)
+            })
+            .unwrap();
+        Face {
+            axis,
+            sign: 1f32.copysign(local[axis]),
+        }
+    }
+
+    /// The faces whose outsides can be seen from `from`. There is at most one per axis and none from inside.
+    fn faces_facing(&self, position: Vec3, from: Vec3) -> Vec<Face> {
+        let half = axes(self.half_size);
+        let local = axes(from - position);
+        (0..3)
+            .filter(|&axis| local[axis].abs() > half[axis])
+            .map(|axis| Face {
+                axis,
+                sign: 1f32.copysign(local[axis]),
+            })
+            .collect()
+    }
+}
+
+impl Traceable for Cuboid {
+    fn bounds(&self, position: Vec3) -> Result<(Vec3, Vec3), &'static str> {
+        Ok((position - self.half_size, position + self.half_size))
+    }
+
+    fn intersect(&self, ray: Ray, position: Vec3) -> Option<(f32, Vec3)> {
+        let origin = ray.origin - position;
+        let t0 = (self.half_size * -1.0 - origin) / ray.direction;
+        let t1 = (self.half_size - origin) / ray.direction;
+        let near = t0.min(t1);
+        let far = t0.max(t1);
+        let t_near = near.x.max(near.y).max(near.z);
+        let t_far = far.x.min(far.y).min(far.z);
+
+        if t_near > t_far || t_far < 0.001 {
+            return None;
+        }
+        let t = if t_near >= 0.001 { t_near } else { t_far };
+
+        let face = self.face_at(origin + ray.direction * t);
+        Some((t, unit(face.axis) * face.sign))
+    }
+
+    /// Each face gets the whole of UV space, across its other two axes in order.
+    fn uv(&self, point: Vec3, position: Vec3) -> Vec2 {
+        let local = point - position;
+        let face = self.face_at(local);
+        let (a, b) = ((face.axis + 1) % 3, (face.axis + 2) % 3);
+        let (local, half) = (axes(local), axes(self.half_size));
+        let along = |axis: usize| 0.5 + local[axis] / (2.0 * half[axis].max(f32::EPSILON));
+        Vec2::new(along(a), along(b))
+    }
+
+    fn tangent(&self, point: Vec3, position: Vec3) -> Vec3 {
+        unit((self.face_at(point - position).axis + 1) % 3)
+    }
+
+    fn area(&self) -> f32 {
+        2.0 * (0..3).map(|axis| self.face_area(axis)).sum::<f32>()
+    }
+
+    /// Picks a point uniformly by area over the faces that can be seen from `from`.
+    fn sample_towards(&self, position: Vec3, from: Vec3, u1: f32, u2: f32) -> Option<(Vec3, f32)> {
+        let faces = self.faces_facing(position, from);
+        let total: f32 = faces.iter().map(|f| self.face_area(f.axis)).sum();
+        if total <= 0.0 {
+            return None;
+        }
+
+        let mut remaining = u1 * total;
+        let mut picked = *faces.last()?;
+        for face in &faces {
+            let area = self.face_area(face.axis);
+            if remaining < area {
+                picked = *face;
+                break;
+            }
+            remaining -= area;
+        }
+        let u1 = (remaining / self.face_area(picked.axis)).clamp(0.0, 1.0);
+
+        let half = axes(self.half_size);
+        let (a, b) = ((picked.axis + 1) % 3, (picked.axis + 2) % 3);
+        let point = position
+            + unit(picked.axis) * (picked.sign * half[picked.axis])
+            + unit(a) * ((2.0 * u1 - 1.0) * half[a])
+            + unit(b) * ((2.0 * u2 - 1.0) * half[b]);
+
+        // The face is known here, whereas working it out from the point is ambiguous along the edges
+        let pdf = Self::solid_angle_pdf(picked, total, from, point);
+        if pdf > 0.0 {
+            Some((point, pdf))
+        } else {
+            None
+        }
+    }
+
+    fn pdf_towards(&self, position: Vec3, from: Vec3, point: Vec3) -> f32 {
+        let faces = self.faces_facing(position, from);
+        let face = self.face_at(point - position);
+        if !faces.contains(&face) {
+            return 0.0;
+        }
+        let total: f32 = faces.iter().map(|f| self.face_area(f.axis)).sum();
+        Self::solid_angle_pdf(face, total, from, point)
+    }
+}
+
+impl Cuboid {
+    /// Converts a uniform density over `area` of visible faces to solid angle at `point` on `face`.
+    fn solid_angle_pdf(face: Face, area: f32, from: Vec3, point: Vec3) -> f32 {
+        let to_point = point - from;
+        let dist2 = to_point.mag_squared();
+        let cos_light = unit(face.axis).dot(to_point).abs() / dist2.sqrt();
+        if cos_light < 1e-6 {
+            return 0.0;
+        }
+        dist2 / (area * cos_light)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cuboid_intersection() {
+        let cuboid = Cuboid::new(Vec3::new(2.0, 4.0, 6.0));
+        let position = Vec3::new(0.0, 0.0, 10.0);
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+        assert_eq!(cuboid.intersect(ray, position), Some((7.0, Vec3::new(0.0, 0.0, -1.0))));
+
+        // From inside, the ray leaves through the far side
+        let inside = Ray {
+            origin: position,
+            direction: Vec3::new(0.0, 1.0, 0.0),
+        };
+        assert_eq!(
+            cuboid.intersect(inside, position),
+            Some((2.0, Vec3::new(0.0, 1.0, 0.0)))
+        );
+
+        let miss = Ray {
+            origin: Vec3::new(2.0, 0.0, 0.0),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+        assert!(cuboid.intersect(miss, position).is_none());
+    }
+
+    #[test]
+    fn test_cuboid_bounds_and_area() {
+        let cuboid = Cuboid::new(Vec3::new(1.0, 2.0, 3.0));
+        let (min, max) = cuboid.bounds(Vec3::new(1.0, 1.0, 1.0)).unwrap();
+        assert_eq!(min, Vec3::new(0.5, 0.0, -0.5));
+        assert_eq!(max, Vec3::new(1.5, 2.0, 2.5));
+        assert_eq!(cuboid.area(), 22.0);
+    }
+
+    #[test]
+    fn test_cuboid_uv() {
+        let cuboid = Cuboid::new(Vec3::new(2.0, 2.0, 2.0));
+        let uv = cuboid.uv(Vec3::new(0.5, -0.5, -1.0), Vec3::zero());
+        assert!((uv.x - 0.75).abs() < 1e-6 && (uv.y - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_cuboid_samples_only_facing_sides() {
+        let cuboid = Cuboid::new(Vec3::new(2.0, 2.0, 2.0));
+        let position = Vec3::new(0.0, 0.0, 10.0);
+        let from = Vec3::new(3.0, 0.0, 0.0);
+        for i in 0..50 {
+            let (point, pdf) = cuboid.sample_towards(position, from, i as f32 / 50.0, 0.37).unwrap();
+            let local = point - position;
+            // Only the -z and +x faces can be seen from here
+            assert!(
+                (local.z + 1.0).abs() < 1e-5 || (local.x - 1.0).abs() < 1e-5,
+                "{}",
+                local
+            );
+            assert!(pdf > 0.0);
+            // Away from the edges, where the face a point is on is unambiguous, the densities agree
+            if i > 0 && i < 49 {
+                assert!((pdf - cuboid.pdf_towards(position, from, point)).abs() < pdf * 1e-5);
+            }
+        }
+        assert_eq!(cuboid.pdf_towards(position, from, Vec3::new(0.0, 0.0, 11.0)), 0.0);
+        assert!(cuboid.sample_towards(position, position, 0.5, 0.5).is_none());
+    }
+}
@@ -0,0 +1,162 @@
+/// Piecewise-constant distribution over `[0, 1)`, sampled by inverting its CDF.
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].max(0.0) / n as f32;
+        }
+
+        let integral = cdf[n];
+        if integral > 0.0 {
+            cdf.iter_mut().for_each(|c| *c /= integral);
+        } else {
+            // Nothing to importance sample, fall back to uniform
+            cdf.iter_mut().enumerate().for_each(|(i, c)| *c = i as f32 / n as f32);
+        }
+
+        Self { func, cdf, integral }
+    }
+
+    pub fn len(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Returns the sampled position in `[0, 1)`, its density and the bucket it fell in.
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let offset = self.cdf.partition_point(|&c| c <= u).clamp(1, self.len()) - 1;
+
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0.0 {
+            (u - self.cdf[offset]) / width
+        } else {
+            0.0
+        };
+        let x = ((offset as f32 + du) / self.len() as f32).min(1.0 - f32::EPSILON);
+
+        (x, self.pdf(offset), offset)
+    }
+
+    pub fn pdf(&self, offset: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.func[offset].max(0.0) / self.integral
+        } else {
+            1.0
+        }
+    }
+
+    pub fn offset(&self, x: f32) -> usize {
+        ((x * self.len() as f32) as usize).min(self.len() - 1)
+    }
+}
+
+/// Piecewise-constant distribution over the unit square, stored as rows of `func[v][u]`.
+#[derive(Clone, Debug)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f32], width: usize, height: usize) -> Self {
+        let conditional: Vec<Distribution1D> = func
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|c| c.integral()).collect());
+
+        Self { conditional, marginal }
+    }
+
+    /// Returns the sampled `(u, v)` and its density with respect to the unit square.
+    pub fn sample(&self, u1: f32, u2: f32) -> ((f32, f32), f32) {
+        let (v, pdf_v, row) = self.marginal.sample(u2);
+        let (u, pdf_u, _) = self.conditional[row].sample(u1);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f32, v: f32) -> f32 {
+        let row = self.marginal.offset(v);
+        let column = self.conditional[row].offset(u);
+        self.conditional[row].pdf(column) * self.marginal.pdf(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_1d_sample_follows_weights() {
+        let dist = Distribution1D::new(vec![0.0, 3.0, 1.0, 0.0]);
+        assert_eq!(dist.integral(), 1.0);
+
+        let (x, pdf, offset) = dist.sample(0.5);
+        assert_eq!(offset, 1);
+        assert!((0.25..0.5).contains(&x));
+        assert_eq!(pdf, 3.0);
+
+        let (_, pdf, offset) = dist.sample(0.9);
+        assert_eq!(offset, 2);
+        assert_eq!(pdf, 1.0);
+    }
+
+    #[test]
+    fn test_1d_never_samples_empty_buckets() {
+        let dist = Distribution1D::new(vec![0.0, 1.0, 0.0, 1.0]);
+        for i in 0..100 {
+            let (_, pdf, offset) = dist.sample(i as f32 / 100.0);
+            assert!(offset == 1 || offset == 3);
+            assert!(pdf > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_1d_all_zero_is_uniform() {
+        let dist = Distribution1D::new(vec![0.0; 4]);
+        let (x, pdf, offset) = dist.sample(0.6);
+        assert_eq!(offset, 2);
+        assert_eq!(pdf, 1.0);
+        assert!((0.5..0.75).contains(&x));
+    }
+
+    #[test]
+    fn test_2d_pdf_matches_sample() {
+        let func = vec![1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 8.0];
+        let dist = Distribution2D::new(&func, 4, 2);
+        let ((u, v), pdf) = dist.sample(0.3, 0.7);
+        assert!(u >= 0.75 && v >= 0.5);
+        assert_eq!(pdf, dist.pdf(u, v));
+        assert_eq!(dist.pdf(0.1, 0.1), 8.0 / 9.0);
+        assert_eq!(dist.pdf(0.3, 0.1), 0.0);
+    }
+
+    #[test]
+    fn test_2d_pdf_integrates_to_one() {
+        let func: Vec<f32> = (0..12).map(|i| (i % 5) as f32).collect();
+        let dist = Distribution2D::new(&func, 4, 3);
+        let steps = 120;
+        let mut total = 0.0;
+        for j in 0..steps {
+            for i in 0..steps {
+                let u = (i as f32 + 0.5) / steps as f32;
+                let v = (j as f32 + 0.5) / steps as f32;
+                total += dist.pdf(u, v);
+            }
+        }
+        total /= (steps * steps) as f32;
+        assert!((total - 1.0).abs() < 1e-3, "integral was {}", total);
+    }
+}
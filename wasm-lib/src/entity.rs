@@ -1,33 +1,68 @@
 use wasm_bindgen::prelude::*;
 
+use crate::cuboid::Cuboid;
 use crate::plane::Plane;
 use crate::sphere::Sphere;
 use crate::traceable::Traceable;
 use crate::triangle::Triangle;
+use crate::vec2::Vec2;
 use crate::{intersection::Intersection, material::Material, ray::Ray, vec3::Vec3};
 
 #[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Shape {
     Sphere(Sphere),
     Plane(Plane),
     Triangle(Triangle),
+    Cuboid(Cuboid),
 }
 
 #[wasm_bindgen()]
 #[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Entity {
     shape: Shape,
     material: Material,
     position: Vec3,
     rotation: Vec3,
+    id: u32,
+    material_id: u32,
 }
 
 impl Entity {
+    fn new(shape: Shape, position: Vec3, material: Material) -> Self {
+        Self {
+            shape,
+            material,
+            position,
+            rotation: Vec3::zero(),
+            id: 0,
+            material_id: 0,
+        }
+    }
+
+    /// Gives a material with an emitted power the radiance that spreads it over the entity's surface.
+    pub fn with_spread_emission(self) -> Self {
+        Self {
+            material: self.material.spread_over(self.emitting_area()),
+            ..self
+        }
+    }
+
+    /// The area light leaves from: both faces of a triangle, but only the outside of a closed shape.
+    fn emitting_area(self) -> f32 {
+        match self.shape {
+            Shape::Triangle(_) => 2.0 * self.area(),
+            _ => self.area(),
+        }
+    }
+
     pub fn bounds(self) -> Result<(Vec3, Vec3), &'static str> {
         match self.shape {
             Shape::Sphere(s) => s.bounds(self.position),
             Shape::Plane(p) => p.bounds(self.position),
             Shape::Triangle(t) => t.bounds(self.position),
+            Shape::Cuboid(c) => c.bounds(self.position),
         }
     }
 
@@ -36,16 +71,61 @@ impl Entity {
             Shape::Sphere(s) => s.intersect(ray, self.position)?,
             Shape::Plane(p) => p.intersect(ray, self.position)?,
             Shape::Triangle(t) => t.intersect(ray, self.position)?,
+            Shape::Cuboid(c) => c.intersect(ray, self.position)?,
+        };
+
+        let point = ray.origin + (ray.direction * t);
+        let uv = match self.shape {
+            Shape::Sphere(s) => s.uv(point, self.position),
+            Shape::Plane(p) => p.uv(point, self.position),
+            Shape::Triangle(t) => t.uv(point, self.position),
+            Shape::Cuboid(c) => c.uv(point, self.position),
+        };
+        let tangent = match self.shape {
+            Shape::Sphere(s) => s.tangent(point, self.position),
+            Shape::Plane(p) => p.tangent(point, self.position),
+            Shape::Triangle(t) => t.tangent(point, self.position),
+            Shape::Cuboid(c) => c.tangent(point, self.position),
         };
 
         Some(Intersection {
             dist: t,
-            point: ray.origin + (ray.direction * t),
+            point,
             normal,
+            tangent,
+            uv,
             entity: Some(self),
         })
     }
 
+    pub fn area(self) -> f32 {
+        match self.shape {
+            Shape::Sphere(s) => s.area(),
+            Shape::Plane(p) => p.area(),
+            Shape::Triangle(t) => t.area(),
+            Shape::Cuboid(c) => c.area(),
+        }
+    }
+
+    /// A point on the entity for light sampling, with the solid angle density of its direction from `from`.
+    pub fn sample_towards(self, from: Vec3, u1: f32, u2: f32) -> Option<(Vec3, f32)> {
+        match self.shape {
+            Shape::Sphere(s) => s.sample_towards(self.position, from, u1, u2),
+            Shape::Plane(p) => p.sample_towards(self.position, from, u1, u2),
+            Shape::Triangle(t) => t.sample_towards(self.position, from, u1, u2),
+            Shape::Cuboid(c) => c.sample_towards(self.position, from, u1, u2),
+        }
+    }
+
+    pub fn pdf_towards(self, from: Vec3, point: Vec3) -> f32 {
+        match self.shape {
+            Shape::Sphere(s) => s.pdf_towards(self.position, from, point),
+            Shape::Plane(p) => p.pdf_towards(self.position, from, point),
+            Shape::Triangle(t) => t.pdf_towards(self.position, from, point),
+            Shape::Cuboid(c) => c.pdf_towards(self.position, from, point),
+        }
+    }
+
     pub fn material(self) -> Material {
         self.material
     }
@@ -57,35 +137,53 @@ impl Entity {
     pub fn shape(self) -> Shape {
         self.shape
     }
+
+    pub fn id(self) -> u32 {
+        self.id
+    }
+
+    pub fn material_id(self) -> u32 {
+        self.material_id
+    }
+
+    /// Sets per-vertex texture coordinates; only triangles have any.
+    pub fn with_uvs(self, uvs: [Vec2; 3]) -> Self {
+        match self.shape {
+            Shape::Triangle(t) => Self {
+                shape: Shape::Triangle(t.with_uvs(uvs)),
+                ..self
+            },
+            _ => self,
+        }
+    }
+
+    /// Tags the entity with its index in the scene and the index of its material among the scene's materials.
+    pub fn with_ids(self, id: u32, material_id: u32) -> Self {
+        Self {
+            id,
+            material_id,
+            ..self
+        }
+    }
 }
 
 #[wasm_bindgen]
 impl Entity {
     pub fn new_sphere(position: Vec3, material: Material, radius: f32) -> Self {
-        Self {
-            shape: Shape::Sphere(Sphere::new(radius)),
-            material,
-            position,
-            rotation: Vec3::zero(),
-        }
+        Self::new(Shape::Sphere(Sphere::new(radius)), position, material)
     }
 
     pub fn new_plane(position: Vec3, material: Material, normal: Vec3) -> Self {
-        Self {
-            shape: Shape::Plane(Plane::new(normal)),
-            material,
-            position,
-            rotation: Vec3::zero(),
-        }
+        Self::new(Shape::Plane(Plane::new(normal)), position, material)
+    }
+
+    /// An axis-aligned box of the given `size`, centred on `position`.
+    pub fn new_cuboid(position: Vec3, material: Material, size: Vec3) -> Self {
+        Self::new(Shape::Cuboid(Cuboid::new(size)), position, material)
     }
 
     pub fn new_triangle(position: Vec3, a: Vec3, b: Vec3, c: Vec3, material: Material) -> Self {
-        Self {
-            shape: Shape::Triangle(Triangle::new(a, b, c)),
-            material,
-            position,
-            rotation: Vec3::zero(),
-        }
+        Self::new(Shape::Triangle(Triangle::new(a, b, c)), position, material)
     }
 }
 
@@ -145,6 +243,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_emitted_power_is_spread_over_the_surface() {
+        let glow = test_material().with_emission_power(Rgb::new(1.0, 0.5, 0.0), 100.0);
+        let small = Entity::new_sphere(Vec3::zero(), glow, 1.0).with_spread_emission();
+        let large = Entity::new_sphere(Vec3::zero(), glow, 2.0).with_spread_emission();
+        let power = |e: Entity| Vec3::from(e.material().emission) * e.area();
+        assert!((power(small) - power(large)).mag() < 1e-2);
+        assert_eq!(large.material().emission.r, small.material().emission.r / 4.0);
+        // Spreading twice changes nothing
+        assert!(small.with_spread_emission() == small);
+        // A triangle gives out its power from both faces together
+        let triangle = |size: f32| {
+            let corner = |x: f32, y: f32| Vec3::new(x, y, 0.0) * size;
+            Entity::new_triangle(Vec3::zero(), corner(0.0, 0.0), corner(1.0, 0.0), corner(0.0, 1.0), glow)
+                .with_spread_emission()
+        };
+        let both_faces = |e: Entity| Vec3::from(e.material().emission) * (2.0 * e.area());
+        assert!((both_faces(triangle(1.0)) - power(small)).mag() < 1e-2);
+        assert!((both_faces(triangle(3.0)) - power(small)).mag() < 1e-2);
+        let plane = Entity::new_plane(Vec3::zero(), glow, Vec3::new(0.0, 1.0, 0.0)).with_spread_emission();
+        assert_eq!(Vec3::from(plane.material().emission), Vec3::zero());
+    }
+
     #[test]
     fn test_entity_bounds() {
         let entity = Entity::new_sphere(Vec3::zero(), test_material(), 1.0);
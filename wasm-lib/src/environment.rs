@@ -0,0 +1,344 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use rand::Rng;
+use wasm_bindgen::prelude::*;
+
+use crate::distribution::Distribution2D;
+use crate::hdr::HdrImage;
+use crate::rgb::Rgb;
+use crate::sky::Sky;
+use crate::vec3::Vec3;
+
+/// Radiance scale that maps an HDR value of 1.0 to display white.
+pub const HDR_SCALE: f32 = 255.0;
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "EnvironmentMapData", into = "EnvironmentMapData"))]
+pub struct EnvironmentMap {
+    image: Arc<HdrImage>,
+    distribution: Arc<Distribution2D>,
+    rotation: f32,
+    intensity: f32,
+}
+
+/// The parts of an `EnvironmentMap` that are saved; the sampling distribution is rebuilt on load.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct EnvironmentMapData {
+    image: Arc<HdrImage>,
+    rotation: f32,
+    intensity: f32,
+}
+
+#[cfg(feature = "serde")]
+impl From<EnvironmentMapData> for EnvironmentMap {
+    fn from(data: EnvironmentMapData) -> Self {
+        EnvironmentMap::new(Arc::unwrap_or_clone(data.image), data.rotation, data.intensity)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<EnvironmentMap> for EnvironmentMapData {
+    fn from(map: EnvironmentMap) -> Self {
+        EnvironmentMapData {
+            image: map.image,
+            rotation: map.rotation,
+            intensity: map.intensity,
+        }
+    }
+}
+
+impl EnvironmentMap {
+    fn new(image: HdrImage, rotation: f32, intensity: f32) -> Self {
+        // Weight each texel by sin(theta) so the poles, which cover less solid angle, are sampled less
+        let func: Vec<f32> = image
+            .pixels
+            .iter()
+            .enumerate()
+            .map(|(i, pixel)| {
+                let theta = ((i / image.width) as f32 + 0.5) / image.height as f32 * PI;
+                pixel.luminance() * theta.sin()
+            })
+            .collect();
+        let distribution = Distribution2D::new(&func, image.width, image.height);
+
+        Self {
+            image: Arc::new(image),
+            distribution: Arc::new(distribution),
+            rotation,
+            intensity,
+        }
+    }
+
+    /// Maps a direction to equirectangular texture coordinates, with -y as up.
+    fn uv(&self, direction: Vec3) -> (f32, f32) {
+        let phi = direction.x.atan2(direction.z) + self.rotation;
+        let theta = (-direction.y).clamp(-1.0, 1.0).acos();
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        (u, theta / PI)
+    }
+
+    fn direction(&self, u: f32, v: f32) -> Vec3 {
+        let phi = (u - 0.5) * 2.0 * PI - self.rotation;
+        let (sin_theta, cos_theta) = (v * PI).sin_cos();
+        Vec3::new(sin_theta * phi.sin(), -cos_theta, sin_theta * phi.cos())
+    }
+
+    /// Converts a density over the unit square to one over solid angle.
+    fn solid_angle_pdf(pdf: f32, v: f32) -> f32 {
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        pdf / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn sample(&self, rng: &mut impl Rng) -> Option<EnvironmentSample> {
+        let ((u, v), pdf) = self.distribution.sample(rng.gen(), rng.gen());
+        let pdf = Self::solid_angle_pdf(pdf, v);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let direction = self.direction(u, v);
+        Some(EnvironmentSample {
+            direction,
+            radiance: self.radiance(direction),
+            pdf,
+        })
+    }
+
+    fn pdf(&self, direction: Vec3) -> f32 {
+        let (u, v) = self.uv(direction);
+        Self::solid_angle_pdf(self.distribution.pdf(u, v), v)
+    }
+
+    fn radiance(&self, direction: Vec3) -> Vec3 {
+        let (u, v) = self.uv(direction);
+        let x = ((u * self.image.width as f32) as usize).min(self.image.width - 1);
+        let y = ((v * self.image.height as f32) as usize).min(self.image.height - 1);
+        self.image.get(x, y) * (self.intensity * HDR_SCALE)
+    }
+}
+
+pub struct EnvironmentSample {
+    pub direction: Vec3,
+    pub radiance: Vec3,
+    pub pdf: f32,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Background {
+    Constant(Vec3),
+    Gradient,
+    Image(EnvironmentMap),
+    Sky(Sky),
+}
+
+#[wasm_bindgen]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Environment {
+    background: Background,
+}
+
+impl Environment {
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        match &self.background {
+            Background::Constant(colour) => *colour,
+            Background::Gradient => {
+                let t = (0.5 * (direction.y + direction.x + 1.0)).clamp(0.0, 1.0);
+                (Vec3::new(1.0, 1.0, 1.0) * (1.0 - t) + Vec3::new(0.5, 0.7, 1.0) * t) * 175.0
+            }
+            Background::Image(map) => map.radiance(direction),
+            Background::Sky(sky) => sky.radiance(direction),
+        }
+    }
+
+    /// Picks a direction towards the bright parts of the background, if it has any worth sampling.
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<EnvironmentSample> {
+        match &self.background {
+            Background::Image(map) => map.sample(rng),
+            Background::Sky(sky) => sky.sample(rng),
+            _ => None,
+        }
+    }
+
+    /// Solid angle density that `sample` would produce `direction` with.
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        match &self.background {
+            Background::Image(map) => map.pdf(direction),
+            Background::Sky(sky) => sky.pdf(direction),
+            _ => 0.0,
+        }
+    }
+
+    pub fn image(image: HdrImage, rotation: f32, intensity: f32) -> Self {
+        Self {
+            background: Background::Image(EnvironmentMap::new(image, rotation, intensity)),
+        }
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::gradient()
+    }
+}
+
+#[wasm_bindgen]
+impl Environment {
+    pub fn constant(colour: Rgb) -> Self {
+        Self {
+            background: Background::Constant(Vec3::from(colour)),
+        }
+    }
+
+    pub fn gradient() -> Self {
+        Self {
+            background: Background::Gradient,
+        }
+    }
+
+    /// Loads an equirectangular Radiance `.hdr` image. `rotation` spins the map about the vertical axis in radians.
+    pub fn from_hdr(bytes: &[u8], rotation: f32, intensity: f32) -> Result<Environment, JsError> {
+        let image = HdrImage::decode(bytes).map_err(JsError::new)?;
+        Ok(Self::image(image, rotation, intensity))
+    }
+
+    /// Physically based daylight. Angles are in radians, with elevation measured up from the horizon.
+    pub fn sky(sun_elevation: f32, sun_azimuth: f32, turbidity: f32, intensity: f32) -> Self {
+        Self {
+            background: Background::Sky(Sky::new(sun_elevation, sun_azimuth, turbidity, intensity)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    fn two_tone_image() -> HdrImage {
+        // top half red, bottom half blue
+        HdrImage {
+            width: 2,
+            height: 2,
+            pixels: vec![
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+                Vec3::new(0.0, 0.0, 1.0),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_constant() {
+        let env = Environment::constant(Rgb::new(1.0, 2.0, 3.0));
+        assert_eq!(env.radiance(Vec3::new(0.0, 0.0, 1.0)), Vec3::new(1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn test_default_is_gradient() {
+        let direction = Vec3::new(0.0, -1.0, 0.0);
+        assert_eq!(
+            Environment::default().radiance(direction),
+            Environment::gradient().radiance(direction)
+        );
+        assert_eq!(
+            Environment::gradient().radiance(direction),
+            Vec3::new(175.0, 175.0, 175.0)
+        );
+    }
+
+    #[test]
+    fn test_image_up_and_down() {
+        let env = Environment::image(two_tone_image(), 0.0, 1.0);
+        assert_eq!(env.radiance(Vec3::new(0.0, -1.0, 0.0)), Vec3::new(255.0, 0.0, 0.0));
+        assert_eq!(env.radiance(Vec3::new(0.0, 1.0, 0.0)), Vec3::new(0.0, 0.0, 255.0));
+    }
+
+    #[test]
+    fn test_image_intensity() {
+        let env = Environment::image(two_tone_image(), 0.0, 0.5);
+        assert_eq!(env.radiance(Vec3::new(0.0, -1.0, 0.0)), Vec3::new(127.5, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_image_rotation() {
+        let image = HdrImage {
+            width: 2,
+            height: 1,
+            pixels: vec![Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)],
+        };
+        let forward = Vec3::new(0.0, 0.0, 1.0);
+        let unrotated = Environment::image(image.clone(), 0.0, 1.0);
+        let rotated = Environment::image(image, PI, 1.0);
+        assert_eq!(unrotated.radiance(forward), Vec3::new(0.0, 255.0, 0.0));
+        assert_eq!(rotated.radiance(forward), Vec3::new(255.0, 0.0, 0.0));
+    }
+
+    fn sun_image() -> HdrImage {
+        let mut pixels = vec![Vec3::new(0.1, 0.1, 0.1); 16 * 8];
+        pixels[3 * 16 + 5] = Vec3::new(1000.0, 1000.0, 1000.0);
+        HdrImage {
+            width: 16,
+            height: 8,
+            pixels,
+        }
+    }
+
+    #[test]
+    fn test_sample_favours_bright_texels() {
+        let env = Environment::image(sun_image(), 0.3, 1.0);
+        let sun = Vec3::new(1000.0, 1000.0, 1000.0) * HDR_SCALE;
+        let mut rng = SmallRng::seed_from_u64(7);
+        let hits = (0..1000)
+            .filter(|_| env.sample(&mut rng).unwrap().radiance == sun)
+            .count();
+        assert!(hits > 900, "only {} samples hit the sun", hits);
+    }
+
+    #[test]
+    fn test_sample_pdf_matches_pdf() {
+        let env = Environment::image(sun_image(), 1.0, 1.0);
+        let mut rng = SmallRng::seed_from_u64(3);
+        for _ in 0..100 {
+            let sample = env.sample(&mut rng).unwrap();
+            let pdf = env.pdf(sample.direction);
+            assert!((sample.pdf - pdf).abs() <= pdf * 1e-3, "{} != {}", sample.pdf, pdf);
+        }
+    }
+
+    #[test]
+    fn test_pdf_integrates_to_one_over_sphere() {
+        let env = Environment::image(sun_image(), 0.0, 1.0);
+        let mut rng = SmallRng::seed_from_u64(11);
+        let count = 200_000;
+        let total: f32 = (0..count)
+            .map(|_| env.pdf(Vec3::rng_normal(&mut rng).normalize()) * 4.0 * PI)
+            .sum();
+        let mean = total / count as f32;
+        assert!((mean - 1.0).abs() < 0.05, "integral was {}", mean);
+    }
+
+    #[test]
+    fn test_sky_samples_the_sun() {
+        let env = Environment::sky(0.6, 0.0, 3.0, 1.0);
+        let mut rng = SmallRng::seed_from_u64(0);
+        let sample = env.sample(&mut rng).unwrap();
+        assert!(sample.direction.y < 0.0);
+        assert_eq!(env.pdf(sample.direction), sample.pdf);
+    }
+
+    #[test]
+    fn test_non_image_backgrounds_are_not_sampled() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert!(Environment::gradient().sample(&mut rng).is_none());
+        assert_eq!(Environment::gradient().pdf(Vec3::new(0.0, 0.0, 1.0)), 0.0);
+    }
+}
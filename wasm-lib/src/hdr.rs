@@ -0,0 +1,240 @@
+use crate::vec3::Vec3;
+
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "HdrImageData"))]
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+/// An image as saved, checked on loading like one decoded from a file.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct HdrImageData {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<HdrImageData> for HdrImage {
+    type Error = &'static str;
+
+    fn try_from(data: HdrImageData) -> Result<Self, Self::Error> {
+        HdrImage::new(data.width, data.height, data.pixels)
+    }
+}
+
+/// A run-length encoded scanline packs at most 127 pixels into every 8 bytes, so a file can't hold more than this
+/// many pixels per byte left after the header.
+const MAX_PIXELS_PER_BYTE: usize = 16;
+
+impl HdrImage {
+    fn new(width: usize, height: usize, pixels: Vec<Vec3>) -> Result<Self, &'static str> {
+        if pixels.len() != pixel_count(width, height)? {
+            return Err("pixel data does not match the image size");
+        }
+        Ok(Self { width, height, pixels })
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Decodes a Radiance RGBE (`.hdr`) file with the standard `-Y height +X width` orientation.
+    pub fn decode(bytes: &[u8]) -> Result<Self, &'static str> {
+        let mut cursor = 0;
+        let magic = read_line(bytes, &mut cursor).ok_or("missing header")?;
+        if !magic.starts_with("#?") {
+            return Err("not a radiance hdr file");
+        }
+
+        loop {
+            let line = read_line(bytes, &mut cursor).ok_or("unterminated header")?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err("unsupported pixel format");
+                }
+            }
+        }
+
+        let resolution = read_line(bytes, &mut cursor).ok_or("missing resolution")?;
+        let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["-Y", h, "+X", w] => (
+                h.parse::<usize>().map_err(|_| "invalid height")?,
+                w.parse::<usize>().map_err(|_| "invalid width")?,
+            ),
+            _ => return Err("unsupported resolution orientation"),
+        };
+
+        let count = pixel_count(width, height)?;
+        if count / MAX_PIXELS_PER_BYTE > bytes.len() - cursor {
+            return Err("unexpected end of pixel data");
+        }
+
+        let mut pixels = Vec::with_capacity(count);
+        let mut scanline = vec![[0u8; 4]; width];
+        for _ in 0..height {
+            read_scanline(bytes, &mut cursor, &mut scanline)?;
+            pixels.extend(scanline.iter().map(|rgbe| rgbe_to_vec3(*rgbe)));
+        }
+
+        Self::new(width, height, pixels)
+    }
+}
+
+fn pixel_count(width: usize, height: usize) -> Result<usize, &'static str> {
+    if width == 0 || height == 0 {
+        return Err("image must not be empty");
+    }
+    width.checked_mul(height).ok_or("image is too large")
+}
+
+fn read_line<'a>(bytes: &'a [u8], cursor: &mut usize) -> Option<&'a str> {
+    let rest = bytes.get(*cursor..)?;
+    let end = rest.iter().position(|&b| b == b'\n')?;
+    *cursor += end + 1;
+    std::str::from_utf8(&rest[..end]).ok().map(|l| l.trim_end_matches('\r'))
+}
+
+fn next_byte(bytes: &[u8], cursor: &mut usize) -> Result<u8, &'static str> {
+    let byte = *bytes.get(*cursor).ok_or("unexpected end of pixel data")?;
+    *cursor += 1;
+    Ok(byte)
+}
+
+fn read_scanline(bytes: &[u8], cursor: &mut usize, scanline: &mut [[u8; 4]]) -> Result<(), &'static str> {
+    let width = scanline.len();
+    let header = bytes.get(*cursor..*cursor + 4).ok_or("unexpected end of pixel data")?;
+    let is_rle = (8..0x8000).contains(&width) && header[0] == 2 && header[1] == 2 && header[2] & 0x80 == 0;
+
+    if !is_rle {
+        for pixel in scanline.iter_mut() {
+            for channel in pixel.iter_mut() {
+                *channel = next_byte(bytes, cursor)?;
+            }
+        }
+        return Ok(());
+    }
+
+    if ((header[2] as usize) << 8 | header[3] as usize) != width {
+        return Err("scanline width mismatch");
+    }
+    *cursor += 4;
+
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = next_byte(bytes, cursor)? as usize;
+            if count > 128 {
+                let run = count - 128;
+                let value = next_byte(bytes, cursor)?;
+                if x + run > width {
+                    return Err("run overflows scanline");
+                }
+                for pixel in &mut scanline[x..x + run] {
+                    pixel[channel] = value;
+                }
+                x += run;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err("invalid literal run");
+                }
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = next_byte(bytes, cursor)?;
+                }
+                x += count;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn rgbe_to_vec3([r, g, b, e]: [u8; 4]) -> Vec3 {
+    if e == 0 {
+        return Vec3::zero();
+    }
+    let scale = 2f32.powi(e as i32 - (128 + 8));
+    Vec3::new(r as f32 * scale, g as f32 * scale, b as f32 * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(width: usize, height: usize) -> Vec<u8> {
+        format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width).into_bytes()
+    }
+
+    #[test]
+    fn test_rgbe_to_vec3() {
+        assert_eq!(rgbe_to_vec3([128, 64, 0, 129]), Vec3::new(1.0, 0.5, 0.0));
+        assert_eq!(rgbe_to_vec3([255, 255, 255, 0]), Vec3::zero());
+    }
+
+    #[test]
+    fn test_decode_flat() {
+        let mut bytes = header(2, 1);
+        bytes.extend([128, 0, 0, 129, 0, 128, 0, 130]);
+
+        let image = HdrImage::decode(&bytes).unwrap();
+        assert_eq!(image.width, 2);
+        assert_eq!(image.height, 1);
+        assert_eq!(image.get(0, 0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(image.get(1, 0), Vec3::new(0.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn test_decode_rle() {
+        let mut bytes = header(8, 1);
+        bytes.extend([2, 2, 0, 8]);
+        // red: a run of 8, green: 8 literals, blue: a run of 8, exponent: a run of 8
+        bytes.extend([128 + 8, 128]);
+        bytes.extend([8, 0, 16, 32, 48, 64, 80, 96, 112]);
+        bytes.extend([128 + 8, 0]);
+        bytes.extend([128 + 8, 129]);
+
+        let image = HdrImage::decode(&bytes).unwrap();
+        assert_eq!(image.pixels.len(), 8);
+        assert_eq!(image.get(0, 0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(image.get(7, 0), Vec3::new(1.0, 0.875, 0.0));
+    }
+
+    #[test]
+    fn test_decode_rejects_garbage() {
+        assert!(HdrImage::decode(b"P6\n1 1\n").is_err());
+        assert!(HdrImage::decode(&header(4, 4)).is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_bad_sizes() {
+        let mut empty = header(0, 0);
+        empty.extend([0; 16]);
+        assert_eq!(HdrImage::decode(&empty), Err("image must not be empty"));
+        // Far more pixels than the file could hold, which mustn't be allocated up front
+        let mut huge = header(100_000, 100_000);
+        huge.extend([0; 16]);
+        assert_eq!(HdrImage::decode(&huge), Err("unexpected end of pixel data"));
+        let overflow = format!("#?RADIANCE\n\n-Y {} +X 2\n", usize::MAX).into_bytes();
+        assert_eq!(HdrImage::decode(&overflow), Err("image is too large"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_loaded_image_is_checked() {
+        let image = HdrImage::new(2, 1, vec![Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0)]).unwrap();
+        let json = serde_json::to_string(&image).unwrap();
+        assert_eq!(serde_json::from_str::<HdrImage>(&json).unwrap(), image);
+        let pixel = r#"{"x":1.0,"y":1.0,"z":1.0}"#;
+        let empty = format!(r#"{{"width":0,"height":1,"pixels":[{}]}}"#, pixel);
+        assert!(serde_json::from_str::<HdrImage>(&empty).is_err());
+        let short = format!(r#"{{"width":2,"height":2,"pixels":[{}]}}"#, pixel);
+        assert!(serde_json::from_str::<HdrImage>(&short).is_err());
+    }
+}
@@ -1,10 +1,18 @@
-use crate::{entity::Entity, vec3::Vec3};
+use rand::Rng;
+
+use crate::bsdf::Frame;
+use crate::material::Surface;
+use crate::texture::{TexCoord, Texture};
+use crate::{entity::Entity, vec2::Vec2, vec3::Vec3};
 
 #[derive(Copy, Clone)]
 pub struct Intersection {
     pub dist: f32,
     pub point: Vec3,
     pub normal: Vec3,
+    /// The direction of increasing `u` at the hit, for anisotropic materials.
+    pub tangent: Vec3,
+    pub uv: Vec2,
     pub entity: Option<Entity>,
 }
 
@@ -14,10 +22,44 @@ impl Intersection {
             point: Vec3::zero(),
             dist: f32::INFINITY,
             normal: Vec3::zero(),
+            tangent: Vec3::zero(),
+            uv: Vec2::new(0.0, 0.0),
             entity: None,
         }
     }
 
+    fn tex_coord(&self, entity: Entity) -> TexCoord {
+        TexCoord {
+            uv: self.uv,
+            point: self.point,
+            local: self.point - entity.position(),
+        }
+    }
+
+    /// The hit entity's material with any textures looked up at the hit point.
+    pub fn surface(&self, textures: &[Texture]) -> Surface {
+        let entity = self.entity.unwrap();
+        entity.material().surface(textures, self.tex_coord(entity))
+    }
+
+    /// Whether the ray stops here or passes through a partly transparent surface, which it does a fraction of
+    /// `1 - opacity` of the time.
+    pub fn is_opaque(&self, textures: &[Texture], rng: &mut impl Rng) -> bool {
+        let entity = self.entity.unwrap();
+        let opacity = entity.material().opacity(textures, self.tex_coord(entity));
+        opacity >= 1.0 || (opacity > 0.0 && rng.gen::<f32>() < opacity)
+    }
+
+    /// The normal the hit entity's material shades with, which points the same way as the geometric `normal`
+    /// but may be tilted from it by a normal or bump map.
+    pub fn shading_normal(&self, textures: &[Texture]) -> Vec3 {
+        let entity = self.entity.unwrap();
+        let frame = Frame::from_normal_and_tangent(self.normal, self.tangent);
+        entity
+            .material()
+            .shading_normal(textures, self.tex_coord(entity), frame)
+    }
+
     pub fn closest(a: Self, b: Self) -> Self {
         match a.dist < b.dist {
             true => a,
@@ -36,12 +78,16 @@ mod tests {
             dist: 10.0,
             point: Vec3::zero(),
             normal: Vec3::zero(),
+            tangent: Vec3::zero(),
+            uv: Vec2::new(0.0, 0.0),
             entity: None,
         };
         let b = Intersection {
             dist: 5.0,
             point: Vec3::zero(),
             normal: Vec3::zero(),
+            tangent: Vec3::zero(),
+            uv: Vec2::new(0.0, 0.0),
             entity: None,
         };
 
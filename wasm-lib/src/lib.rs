@@ -1,21 +1,36 @@
+mod aov;
+mod bsdf;
 mod bvh;
 mod camera;
+mod cuboid;
+mod distribution;
 mod entity;
+mod environment;
+mod hdr;
 mod intersection;
+mod light;
+mod lights;
 mod material;
+mod media;
 mod model;
+mod noise;
 mod plane;
 mod post_processing;
 mod ray;
 mod renderer;
 mod rgb;
 mod scene;
+mod sky;
+mod spectrum;
 mod sphere;
+mod texture;
 mod traceable;
 mod tracer;
 mod triangle;
 mod vec2;
 mod vec3;
+mod volume;
+mod world;
 
 use wasm_bindgen::prelude::*;
 pub use wasm_bindgen_rayon::init_thread_pool;
@@ -0,0 +1,340 @@
+use std::f32::consts::PI;
+
+use rand::Rng;
+use wasm_bindgen::prelude::*;
+
+use crate::environment::HDR_SCALE;
+use crate::rgb::Rgb;
+use crate::sphere::Sphere;
+use crate::traceable::Traceable;
+use crate::vec3::Vec3;
+
+/// Light arriving at a point from one sampled direction on a light, already divided by the sampling density.
+pub struct Illumination {
+    pub direction: Vec3,
+    /// How far the shadow ray has to reach, infinite for directional lights.
+    pub distance: f32,
+    pub radiance: Vec3,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Shape {
+    /// A sphere of uniform radiance, or a true point when `radius` is zero.
+    Point { position: Vec3, radius: f32 },
+    /// A point light masked to a cone around `direction`, fading from full strength at `cos_inner` to nothing at
+    /// `cos_outer`.
+    Spot {
+        position: Vec3,
+        radius: f32,
+        direction: Vec3,
+        cos_inner: f32,
+        cos_outer: f32,
+    },
+    /// Parallel light travelling along `direction` from a disc `cos_half_angle` across, like the sun.
+    Directional { direction: Vec3, cos_half_angle: f32 },
+    /// A one-sided rectangle emitting towards `edge_u × edge_v`.
+    Rect { corner: Vec3, edge_u: Vec3, edge_v: Vec3 },
+}
+
+/// A light that only exists to illuminate: it is sampled directly but never hit by camera or BSDF rays.
+///
+/// Strengths are physical: watts for point, spot and area lights and watts per square metre for directional
+/// lights, on the same scale as HDR environments so that a radiance of 1 displays as white. `colour` tints the
+/// light and should be in 0-1.
+#[wasm_bindgen]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Light {
+    shape: Shape,
+    colour: Vec3,
+    strength: f32,
+}
+
+impl Light {
+    fn new(shape: Shape, colour: Rgb, strength: f32) -> Self {
+        Self {
+            shape,
+            colour: Vec3::from(colour),
+            strength: strength.max(0.0),
+        }
+    }
+
+    /// Samples a direction from `from` towards the light, or `None` if the light can't reach `from`.
+    pub fn sample(&self, from: Vec3, rng: &mut impl Rng) -> Option<Illumination> {
+        let tint = self.colour * HDR_SCALE;
+        match self.shape {
+            Shape::Point { position, radius } => {
+                // A point light spreads its power evenly over the sphere of directions
+                let intensity = self.strength / (4.0 * PI);
+                Self::sample_bulb(position, radius, intensity, from, rng).map(|mut illumination| {
+                    illumination.radiance = illumination.radiance * tint;
+                    illumination
+                })
+            }
+            Shape::Spot {
+                position,
+                radius,
+                direction,
+                cos_inner,
+                cos_outer,
+            } => {
+                // Like a point light with shutters, so narrowing the cone doesn't make it brighter
+                let intensity = self.strength / (4.0 * PI);
+                let cos_theta = (from - position).normalize().dot(direction);
+                let falloff = smoothstep(cos_outer, cos_inner, cos_theta);
+                if falloff <= 0.0 {
+                    return None;
+                }
+                Self::sample_bulb(position, radius, intensity, from, rng).map(|mut illumination| {
+                    illumination.radiance = illumination.radiance * tint * falloff;
+                    illumination
+                })
+            }
+            Shape::Directional {
+                direction,
+                cos_half_angle,
+            } => {
+                // Sampling the disc uniformly by solid angle makes radiance over density the irradiance itself
+                let towards = direction * -1.0;
+                let cos_theta = 1.0 - rng.gen::<f32>() * (1.0 - cos_half_angle);
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * rng.gen::<f32>();
+                let (tangent, bitangent) = towards.orthonormal_basis();
+                let direction =
+                    (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + towards * cos_theta)
+                        .normalize();
+                Some(Illumination {
+                    direction,
+                    distance: f32::INFINITY,
+                    radiance: tint * self.strength,
+                })
+            }
+            Shape::Rect { corner, edge_u, edge_v } => {
+                let cross = edge_u.cross(edge_v);
+                let area = cross.mag();
+                if area == 0.0 {
+                    return None;
+                }
+                let normal = cross / area;
+                let point = corner + edge_u * rng.gen::<f32>() + edge_v * rng.gen::<f32>();
+                let to_light = point - from;
+                let distance = to_light.mag();
+                let direction = to_light / distance;
+                let cos_light = -direction.dot(normal);
+                if cos_light <= 0.0 {
+                    return None;
+                }
+                // A Lambertian emitter's radiance is its power over pi times its area, and the area sample's
+                // solid angle density is distance squared over area times cosine
+                let radiance = self.strength / (PI * area);
+                Some(Illumination {
+                    direction,
+                    distance,
+                    radiance: tint * (radiance * area * cos_light / (distance * distance)),
+                })
+            }
+        }
+    }
+
+    /// Samples a spherical bulb with the given radiant intensity, shrinking it to a point when `from` is inside it
+    /// or it has no radius.
+    fn sample_bulb(
+        position: Vec3,
+        radius: f32,
+        intensity: f32,
+        from: Vec3,
+        rng: &mut impl Rng,
+    ) -> Option<Illumination> {
+        let to_centre = position - from;
+        let centre_distance = to_centre.mag();
+        if centre_distance == 0.0 {
+            return None;
+        }
+
+        if radius > 0.0 {
+            if let Some((point, pdf)) = Sphere::new(radius).sample_towards(position, from, rng.gen(), rng.gen()) {
+                // A sphere's projected area is the same from everywhere, so its radiance is the intensity over pi r^2
+                let radiance = intensity / (PI * radius * radius);
+                let to_light = point - from;
+                let distance = to_light.mag();
+                return Some(Illumination {
+                    direction: to_light / distance,
+                    distance,
+                    radiance: Vec3::new(1.0, 1.0, 1.0) * (radiance / pdf),
+                });
+            }
+        }
+
+        Some(Illumination {
+            direction: to_centre / centre_distance,
+            distance: centre_distance,
+            radiance: Vec3::new(1.0, 1.0, 1.0) * (intensity / (centre_distance * centre_distance)),
+        })
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x >= edge0 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[wasm_bindgen]
+impl Light {
+    /// An omnidirectional light of `power` watts. A non-zero `radius` gives soft shadows.
+    pub fn point(position: Vec3, colour: Rgb, power: f32, radius: f32) -> Self {
+        Self::new(
+            Shape::Point {
+                position,
+                radius: radius.max(0.0),
+            },
+            colour,
+            power,
+        )
+    }
+
+    /// A point light shining along `direction`, at full strength within `inner_angle` of it and fading out by
+    /// `outer_angle`. Angles are in radians from the axis.
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        colour: Rgb,
+        power: f32,
+        radius: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        let outer_angle = outer_angle.clamp(0.0, PI);
+        Self::new(
+            Shape::Spot {
+                position,
+                radius: radius.max(0.0),
+                direction: direction.normalize(),
+                cos_inner: inner_angle.clamp(0.0, outer_angle).cos(),
+                cos_outer: outer_angle.cos(),
+            },
+            colour,
+            power,
+        )
+    }
+
+    /// Light travelling along `direction` with `irradiance` watts per square metre, from a source `angular_diameter`
+    /// radians across. The sun is about 0.0093.
+    pub fn directional(direction: Vec3, colour: Rgb, irradiance: f32, angular_diameter: f32) -> Self {
+        Self::new(
+            Shape::Directional {
+                direction: direction.normalize(),
+                cos_half_angle: (angular_diameter.clamp(0.0, PI) / 2.0).cos(),
+            },
+            colour,
+            irradiance,
+        )
+    }
+
+    /// A rectangle of `power` watts with one corner at `corner`, spanned by `edge_u` and `edge_v`. It emits from
+    /// the side `edge_u × edge_v` points to.
+    pub fn area(corner: Vec3, edge_u: Vec3, edge_v: Vec3, colour: Rgb, power: f32) -> Self {
+        Self::new(Shape::Rect { corner, edge_u, edge_v }, colour, power)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    fn white() -> Rgb {
+        Rgb::new(1.0, 1.0, 1.0)
+    }
+
+    /// Average irradiance at `from` on a surface facing `normal`, in units of HDR_SCALE.
+    fn irradiance(light: &Light, from: Vec3, normal: Vec3) -> f32 {
+        let mut rng = SmallRng::seed_from_u64(5);
+        let n = 20_000;
+        let mut total = 0.0;
+        for _ in 0..n {
+            if let Some(sample) = light.sample(from, &mut rng) {
+                total += sample.radiance.x * sample.direction.dot(normal).max(0.0);
+            }
+        }
+        total / n as f32 / HDR_SCALE
+    }
+
+    #[test]
+    fn test_point_light_inverse_square() {
+        let up = Vec3::new(0.0, -1.0, 0.0);
+        let light = Light::point(Vec3::new(0.0, -2.0, 0.0), white(), 4.0 * PI * 8.0, 0.0);
+        assert!((irradiance(&light, Vec3::zero(), up) - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_point_light_radius_keeps_power() {
+        // Far from a sphere light it looks like a point of the same power
+        let up = Vec3::new(0.0, -1.0, 0.0);
+        let point = Light::point(Vec3::new(0.0, -10.0, 0.0), white(), 1000.0, 0.0);
+        let bulb = Light::point(Vec3::new(0.0, -10.0, 0.0), white(), 1000.0, 0.5);
+        let expected = irradiance(&point, Vec3::zero(), up);
+        let actual = irradiance(&bulb, Vec3::zero(), up);
+        assert!(
+            (actual - expected).abs() < expected * 0.01,
+            "{} vs {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_spot_light_cone() {
+        let mut rng = SmallRng::seed_from_u64(5);
+        let down = Vec3::new(0.0, 1.0, 0.0);
+        let spot = Light::spot(Vec3::zero(), down, white(), 100.0, 0.0, 0.2, 0.4);
+        let point = Light::point(Vec3::zero(), white(), 100.0, 0.0);
+
+        let centre = Vec3::new(0.0, 5.0, 0.0);
+        let inside = spot.sample(centre, &mut rng).unwrap();
+        assert_eq!(inside.radiance, point.sample(centre, &mut rng).unwrap().radiance);
+
+        let edge = Vec3::new(5.0 * 0.3f32.tan(), 5.0, 0.0);
+        let fading = spot.sample(edge, &mut rng).unwrap();
+        assert!(fading.radiance.x > 0.0 && fading.radiance.x < point.sample(edge, &mut rng).unwrap().radiance.x);
+
+        assert!(spot.sample(Vec3::new(5.0, 1.0, 0.0), &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_directional_light_irradiance() {
+        let down = Vec3::new(0.0, 1.0, 0.0);
+        let sun = Light::directional(down, white(), 3.0, 0.0093);
+        let actual = irradiance(&sun, Vec3::zero(), down * -1.0);
+        assert!((actual - 3.0).abs() < 1e-3, "{}", actual);
+
+        let mut rng = SmallRng::seed_from_u64(5);
+        let sample = sun.sample(Vec3::zero(), &mut rng).unwrap();
+        assert!(sample.distance.is_infinite());
+        assert!(sample.direction.dot(down * -1.0) >= (0.0093f32 / 2.0).cos() - 1e-6);
+    }
+
+    #[test]
+    fn test_area_light_is_one_sided() {
+        let mut rng = SmallRng::seed_from_u64(5);
+        // A 1x1 panel at y = -2 emitting down towards +y
+        let panel = Light::area(
+            Vec3::new(-0.5, -2.0, -0.5),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            white(),
+            PI,
+        );
+        assert!(panel.sample(Vec3::zero(), &mut rng).is_some());
+        assert!(panel.sample(Vec3::new(0.0, -4.0, 0.0), &mut rng).is_none());
+
+        // Unit radiance, so the irradiance is the panel's cosine-weighted solid angle
+        let up = Vec3::new(0.0, -1.0, 0.0);
+        let actual = irradiance(&panel, Vec3::zero(), up);
+        assert!((actual - 0.2308).abs() < 0.005, "{}", actual);
+    }
+}
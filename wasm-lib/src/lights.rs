@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use rand::Rng;
+
+use crate::distribution::Distribution1D;
+use crate::entity::Entity;
+use crate::light::{Illumination, Light};
+use crate::vec3::Vec3;
+
+pub struct LightSample {
+    pub entity_id: u32,
+    pub point: Vec3,
+    /// Solid angle density of the direction towards `point`, including the chance of picking this light.
+    pub pdf: f32,
+}
+
+/// The emissive entities and analytic lights in a scene, for sampling them directly. Each entity is picked in
+/// proportion to its emitted power, so a large dim panel and a small bright bulb both get their share.
+pub struct Lights {
+    emitters: Vec<Entity>,
+    distribution: Option<Distribution1D>,
+    /// Entity id to index in `emitters`.
+    lookup: HashMap<u32, usize>,
+    analytic: Vec<Light>,
+}
+
+impl Lights {
+    /// Collects entities with a non-zero emission. Unbounded shapes like planes can't be sampled and are left to
+    /// be found by BSDF sampling alone.
+    pub fn build(entities: &[Entity]) -> Self {
+        let emitters: Vec<Entity> = entities
+            .iter()
+            .filter(|e| Vec3::from(e.material().emission).luminance() > 0.0 && e.area().is_finite())
+            .copied()
+            .collect();
+
+        let power: Vec<f32> = emitters
+            .iter()
+            .map(|e| Vec3::from(e.material().emission).luminance() * e.area())
+            .collect();
+        let distribution = if emitters.is_empty() {
+            None
+        } else {
+            Some(Distribution1D::new(power))
+        };
+        let lookup = emitters.iter().enumerate().map(|(i, e)| (e.id(), i)).collect();
+
+        Self {
+            emitters,
+            distribution,
+            lookup,
+            analytic: vec![],
+        }
+    }
+
+    pub fn with_analytic(mut self, lights: Vec<Light>) -> Self {
+        self.analytic = lights;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.emitters.is_empty() && self.analytic.is_empty()
+    }
+
+    fn selection_probability(&self, index: usize) -> f32 {
+        match &self.distribution {
+            Some(distribution) => distribution.pdf(index) / distribution.len() as f32,
+            None => 0.0,
+        }
+    }
+
+    pub fn sample(&self, from: Vec3, rng: &mut impl Rng) -> Option<LightSample> {
+        let distribution = self.distribution.as_ref()?;
+        let (_, _, index) = distribution.sample(rng.gen());
+        let entity = self.emitters[index];
+        let (point, pdf) = entity.sample_towards(from, rng.gen(), rng.gen())?;
+
+        Some(LightSample {
+            entity_id: entity.id(),
+            point,
+            pdf: pdf * self.selection_probability(index),
+        })
+    }
+
+    /// Density of `sample` picking `point` on the entity with `entity_id`, or zero if it isn't a sampled light.
+    pub fn pdf(&self, entity_id: u32, from: Vec3, point: Vec3) -> f32 {
+        match self.lookup.get(&entity_id) {
+            Some(&index) => self.emitters[index].pdf_towards(from, point) * self.selection_probability(index),
+            None => 0.0,
+        }
+    }
+
+    /// Picks one analytic light uniformly and samples it. They can't be hit by BSDF rays, so this is the only way
+    /// their light is found and needs no MIS weight.
+    pub fn illuminate(&self, from: Vec3, rng: &mut impl Rng) -> Option<Illumination> {
+        if self.analytic.is_empty() {
+            return None;
+        }
+        let count = self.analytic.len();
+        let index = ((rng.gen::<f32>() * count as f32) as usize).min(count - 1);
+        let mut illumination = self.analytic[index].sample(from, rng)?;
+        illumination.radiance = illumination.radiance * count as f32;
+        Some(illumination)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::rgb::Rgb;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    fn emissive(strength: f32) -> Material {
+        Material::new(
+            Rgb::new(strength, strength, strength),
+            Rgb::new(1.0, 1.0, 1.0),
+            0.0,
+            1.0,
+            0.0,
+            1.5,
+        )
+    }
+
+    #[test]
+    fn test_only_bounded_emitters_are_lights() {
+        let entities = [
+            Entity::new_sphere(Vec3::new(0.0, 0.0, 10.0), emissive(0.0), 1.0).with_ids(0, 0),
+            Entity::new_plane(Vec3::zero(), emissive(5.0), Vec3::new(0.0, -1.0, 0.0)).with_ids(1, 1),
+            Entity::new_sphere(Vec3::new(0.0, 0.0, 10.0), emissive(5.0), 1.0).with_ids(2, 1),
+        ];
+        let lights = Lights::build(&entities);
+        assert_eq!(lights.emitters.len(), 1);
+        assert_eq!(lights.pdf(0, Vec3::zero(), Vec3::new(0.0, 0.0, 9.0)), 0.0);
+        assert!(lights.pdf(2, Vec3::zero(), Vec3::new(0.0, 0.0, 9.0)) > 0.0);
+    }
+
+    #[test]
+    fn test_picks_lights_by_power() {
+        let entities = [
+            Entity::new_sphere(Vec3::new(-5.0, 0.0, 10.0), emissive(1.0), 1.0).with_ids(0, 0),
+            Entity::new_sphere(Vec3::new(5.0, 0.0, 10.0), emissive(3.0), 1.0).with_ids(1, 1),
+        ];
+        let lights = Lights::build(&entities);
+        assert_eq!(lights.selection_probability(0), 0.25);
+        assert_eq!(lights.selection_probability(1), 0.75);
+
+        let mut rng = SmallRng::seed_from_u64(7);
+        for _ in 0..20 {
+            let sample = lights.sample(Vec3::zero(), &mut rng).unwrap();
+            assert_eq!(sample.pdf, lights.pdf(sample.entity_id, Vec3::zero(), sample.point));
+        }
+    }
+
+    #[test]
+    fn test_no_lights() {
+        let lights = Lights::build(&[]);
+        let mut rng = SmallRng::seed_from_u64(7);
+        assert!(lights.is_empty());
+        assert!(lights.sample(Vec3::zero(), &mut rng).is_none());
+        assert!(lights.illuminate(Vec3::zero(), &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_analytic_lights_share_samples() {
+        let bulb = |x: f32| Light::point(Vec3::new(x, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 100.0, 0.0);
+        let single = Lights::build(&[]).with_analytic(vec![bulb(-3.0)]);
+        let pair = Lights::build(&[]).with_analytic(vec![bulb(-3.0), bulb(3.0)]);
+        assert!(!pair.is_empty());
+
+        let mut rng = SmallRng::seed_from_u64(7);
+        let one = single.illuminate(Vec3::zero(), &mut rng).unwrap().radiance;
+        // Symmetric lights, so each sample stands in for both of them
+        for _ in 0..10 {
+            let sample = pair.illuminate(Vec3::zero(), &mut rng).unwrap();
+            assert_eq!(sample.radiance, one * 2.0);
+        }
+    }
+}
@@ -1,9 +1,19 @@
+use std::f32::consts::PI;
+
 use wasm_bindgen::prelude::*;
 
+use crate::bsdf::{Frame, Ggx};
+use crate::environment::HDR_SCALE;
+use crate::media::Medium;
 use crate::rgb::Rgb;
+use crate::spectrum::Wavelengths;
+use crate::texture::{TexCoord, Texture};
+use crate::vec2::Vec2;
+use crate::vec3::Vec3;
 
 #[wasm_bindgen()]
 #[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Material {
     pub emission: Rgb,
     pub albedo: Rgb,
@@ -11,6 +21,398 @@ pub struct Material {
     pub roughness: f32,
     pub transmission: f32,
     pub ior: f32,
+    /// Texture handles from `Scene::add_texture`, multiplied with the constant of the same name.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub emission_texture: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub albedo_texture: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub metallic_texture: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub roughness_texture: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub transmission_texture: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    absorption: Option<Absorption>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    medium: Option<Medium>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    subsurface: Option<Subsurface>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    dispersion: Option<Dispersion>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    clearcoat: Option<Clearcoat>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    sheen: Option<Sheen>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    conductor: Option<Conductor>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    anisotropy: Option<Anisotropy>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    relief: Option<Relief>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    opacity: Option<Opacity>,
+    /// Watts to spread over the surface of whatever the material is put on, with `emission` as the colour.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    power: Option<f32>,
+    /// Where transmissive objects overlap, the one with the higher priority owns the shared volume, e.g. a glass
+    /// above the liquid modelled slightly into its walls.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_zero"))]
+    pub priority: u32,
+}
+
+#[cfg(feature = "serde")]
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+/// Beer-Lambert absorption inside a transmissive material: light that travels `distance` through it is left
+/// with `colour`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Absorption {
+    pub colour: Vec3,
+    pub distance: f32,
+}
+
+impl Absorption {
+    /// The absorption coefficient per unit length for each channel.
+    pub fn coefficient(&self) -> Vec3 {
+        let channel = |c: f32| -c.clamp(1e-6, 1.0).ln() / self.distance.max(1e-6);
+        Vec3::new(channel(self.colour.x), channel(self.colour.y), channel(self.colour.z))
+    }
+}
+
+/// Light that gets into a translucent material like skin, wax or marble and scatters about inside before coming
+/// back out, usually somewhere else.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Subsurface {
+    /// Average distance light travels between scattering events, per channel.
+    pub mean_free_path: Vec3,
+    /// Chance of light scattering rather than being absorbed at each event, per channel.
+    pub albedo: Vec3,
+}
+
+/// A clear lacquer over the rest of the material with a fixed IOR of 1.5, like the top coat of car paint.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Clearcoat {
+    /// How much of the surface is coated, from 0 to 1.
+    pub weight: f32,
+    pub roughness: f32,
+}
+
+/// Fibres standing up from the surface, which catch light at grazing angles like velvet or felt.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sheen {
+    pub colour: Vec3,
+    pub roughness: f32,
+}
+
+/// Roughness stretched along one direction on the surface, as on brushed metal or the grooves of a record.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Anisotropy {
+    /// From 0 for isotropic up to 1 for highlights drawn out into lines.
+    pub strength: f32,
+    /// Radians to turn the direction of the stretch from the surface's tangent, which follows increasing `u`.
+    pub rotation: f32,
+}
+
+/// How much of a surface is really there, for cutting leaves, fences or decals out of simple shapes.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Opacity {
+    pub value: f32,
+    /// Texture handle whose red channel is multiplied with `value`.
+    pub texture: Option<u32>,
+}
+
+/// Surface detail too fine to model, drawn by tilting the normal the material shades with.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Relief {
+    /// A tangent-space normal map: red along the tangent, green along the bitangent and blue out of the surface,
+    /// each mapped from `[-1, 1]` onto `[0, 1]`. `strength` scales the tilt.
+    Normal { texture: u32, strength: f32 },
+    /// A height map in the red channel, where going from 0 to 1 rises by `height` in UV units.
+    Bump { texture: u32, height: f32 },
+}
+
+/// Luminous efficacy of light at 555 nm, where the eye is most sensitive.
+const LUMENS_PER_WATT: f32 = 683.0;
+
+/// How far apart a bump map is sampled to find its slope, in UV units or in world units for spatial textures.
+const BUMP_DELTA: f32 = 1e-3;
+
+/// The least a tilted normal may lean out of the surface, so it never lies flat along it.
+const MIN_RELIEF_Z: f32 = 1e-3;
+
+impl Relief {
+    /// The tilted normal in `frame`'s tangent space, or straight up if the texture is missing.
+    fn local_normal(&self, textures: &[Texture], at: TexCoord, frame: Frame) -> Vec3 {
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let normal = match *self {
+            Relief::Normal { texture, strength } => match textures.get(texture as usize) {
+                Some(texture) => {
+                    let n = texture.sample(at) * 2.0 - 1.0;
+                    Vec3::new(n.x * strength, n.y * strength, n.z)
+                }
+                None => up,
+            },
+            Relief::Bump { texture, height } => match textures.get(texture as usize) {
+                Some(texture) => {
+                    // Steps along u and v on the surface, with spatial textures stepping along the tangent frame
+                    let step = |du: f32, dv: f32| {
+                        let offset = frame.tangent * du + frame.bitangent * dv;
+                        TexCoord {
+                            uv: at.uv + Vec2::new(du, dv),
+                            point: at.point + offset,
+                            local: at.local + offset,
+                        }
+                    };
+                    let base = texture.sample_scalar(at);
+                    let slope_u = (texture.sample_scalar(step(BUMP_DELTA, 0.0)) - base) / BUMP_DELTA;
+                    let slope_v = (texture.sample_scalar(step(0.0, BUMP_DELTA)) - base) / BUMP_DELTA;
+                    Vec3::new(-slope_u * height, -slope_v * height, 1.0)
+                }
+                None => up,
+            },
+        };
+        Vec3::new(normal.x, normal.y, normal.z.max(MIN_RELIEF_Z)).normalize()
+    }
+}
+
+/// The complex IOR `eta + ik` of a metal, per channel, which gives its reflectance at every angle.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Conductor {
+    pub eta: Vec3,
+    pub k: Vec3,
+}
+
+impl Conductor {
+    /// The reflectance looking straight on, which is the colour the metal appears.
+    pub fn normal_reflectance(&self) -> Vec3 {
+        let channel = |eta: f32, k: f32| ((eta - 1.0).powi(2) + k * k) / ((eta + 1.0).powi(2) + k * k);
+        Vec3::new(
+            channel(self.eta.x, self.k.x),
+            channel(self.eta.y, self.k.y),
+            channel(self.eta.z, self.k.z),
+        )
+    }
+}
+
+/// Metals with measured optical constants, for `Material::metal`.
+#[wasm_bindgen]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Metal {
+    Gold,
+    Silver,
+    Copper,
+    Aluminium,
+    Chromium,
+    Titanium,
+}
+
+impl Metal {
+    /// Complex IOR at the red, green and blue primaries, from measured spectral data.
+    pub fn conductor(self) -> Conductor {
+        let (eta, k) = match self {
+            Metal::Gold => (Vec3::new(0.143, 0.374, 1.442), Vec3::new(3.983, 2.386, 1.603)),
+            Metal::Silver => (Vec3::new(0.155, 0.117, 0.138), Vec3::new(4.828, 3.122, 2.147)),
+            Metal::Copper => (Vec3::new(0.200, 0.924, 1.102), Vec3::new(3.912, 2.452, 2.142)),
+            Metal::Aluminium => (Vec3::new(1.657, 0.880, 0.521), Vec3::new(9.224, 6.270, 4.837)),
+            Metal::Chromium => (Vec3::new(3.107, 3.181, 2.323), Vec3::new(3.331, 3.329, 3.135)),
+            Metal::Titanium => (Vec3::new(2.160, 1.948, 1.862), Vec3::new(2.930, 2.798, 2.684)),
+        };
+        Conductor { eta, k }
+    }
+}
+
+/// The sodium d line in micrometres, where a material's nominal IOR is usually measured.
+const D_LINE: f32 = 0.5876;
+
+/// How the IOR of a transmissive material changes with wavelength, splitting white light into colours when
+/// rendering spectrally. Coefficients are for wavelengths in micrometres, as they are usually quoted.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Dispersion {
+    /// Cauchy's equation, `n = A + B / λ²`, with `A` chosen to keep the material's IOR at the d line.
+    Cauchy { b: f32 },
+    /// Sellmeier's equation, `n² = 1 + Σ Bᵢ λ² / (λ² - Cᵢ)`, which glass catalogues list coefficients for.
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    /// The IOR at `lambda` nanometres for a material whose IOR at the d line is `ior`.
+    pub fn ior(&self, ior: f32, lambda: f32) -> f32 {
+        let lambda = lambda / 1000.0;
+        match self {
+            Dispersion::Cauchy { b } => ior + b * (1.0 / (lambda * lambda) - 1.0 / (D_LINE * D_LINE)),
+            Dispersion::Sellmeier { b, c } => {
+                let l2 = lambda * lambda;
+                let sum: f32 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1.0 + sum).max(1.0).sqrt()
+            }
+        }
+    }
+}
+
+/// A material's parameters resolved at one point on a surface.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Surface {
+    pub emission: Vec3,
+    pub albedo: Vec3,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub transmission: f32,
+    pub ior: f32,
+    /// What fills the inside of a transmissive material, if it attenuates or scatters light by distance rather
+    /// than tinting it at the surface.
+    pub medium: Option<Medium>,
+    /// Whether light crossing the surface random walks through `medium` instead of refracting.
+    pub subsurface: bool,
+    pub dispersion: Option<Dispersion>,
+    pub clearcoat: Option<Clearcoat>,
+    pub sheen: Option<Sheen>,
+    /// Replaces the Schlick approximation from `albedo` with exact conductor Fresnel.
+    pub conductor: Option<Conductor>,
+    pub anisotropy: Option<Anisotropy>,
+    pub priority: u32,
+}
+
+impl Surface {
+    /// The microfacet distribution of the surface's specular reflection and refraction.
+    pub fn specular_distribution(&self) -> Ggx {
+        Ggx::anisotropic(self.roughness, self.anisotropy.map_or(0.0, |a| a.strength))
+    }
+
+    /// The colour light picks up crossing into a transmissive material. One with an interior medium is coloured
+    /// by what happens along the way instead.
+    pub fn transmission_tint(&self) -> Vec3 {
+        match self.medium {
+            Some(_) => Vec3::new(1.0, 1.0, 1.0),
+            None => self.albedo,
+        }
+    }
+
+    /// Whether paths can cross into the material, by refraction or for a subsurface random walk.
+    pub fn is_transmissive(&self) -> bool {
+        self.transmission > 0.0 || self.subsurface
+    }
+
+    /// Whether refraction through the surface depends on wavelength, so only one can be followed through it.
+    pub fn is_dispersive(&self) -> bool {
+        self.dispersion.is_some() && self.transmission > 0.0 && !self.subsurface
+    }
+
+    /// The surface as a path carrying `wavelengths` sees it: colours upsampled to spectra and the IOR at the hero
+    /// wavelength.
+    pub fn at_wavelengths(self, wavelengths: &Wavelengths) -> Surface {
+        Surface {
+            emission: wavelengths.radiance(self.emission),
+            albedo: wavelengths.reflectance(self.albedo),
+            ior: self
+                .dispersion
+                .map_or(self.ior, |d| d.ior(self.ior, wavelengths.hero())),
+            medium: self.medium.map(|m| m.at_wavelengths(wavelengths)),
+            sheen: self.sheen.map(|s| Sheen {
+                colour: wavelengths.reflectance(s.colour),
+                ..s
+            }),
+            conductor: self.conductor.map(|c| Conductor {
+                eta: wavelengths.radiance(c.eta),
+                k: wavelengths.radiance(c.k),
+            }),
+            ..self
+        }
+    }
+
+    /// Whether crossing this surface with IOR ratio `eta` leaves light untouched, as at the edge of a volume of
+    /// smoke, so paths and shadow rays can pass straight through.
+    pub fn is_invisible_boundary(&self, eta: f32) -> bool {
+        self.transmission > 0.0
+            && !self.subsurface
+            && eta == 1.0
+            && self.transmission_tint() == Vec3::new(1.0, 1.0, 1.0)
+            && self.emission == Vec3::zero()
+    }
+}
+
+impl Material {
+    pub fn surface(&self, textures: &[Texture], at: TexCoord) -> Surface {
+        let texture = |handle: Option<u32>| handle.and_then(|h| textures.get(h as usize));
+        let colour = |constant: Rgb, handle: Option<u32>| match texture(handle) {
+            Some(t) => Vec3::from(constant) * t.sample(at),
+            None => Vec3::from(constant),
+        };
+        let scalar = |constant: f32, handle: Option<u32>| match texture(handle) {
+            Some(t) => constant * t.sample_scalar(at),
+            None => constant,
+        };
+
+        Surface {
+            emission: colour(self.emission, self.emission_texture),
+            albedo: colour(self.albedo, self.albedo_texture),
+            metallic: scalar(self.metallic, self.metallic_texture),
+            roughness: scalar(self.roughness, self.roughness_texture),
+            transmission: scalar(self.transmission, self.transmission_texture),
+            ior: self.ior,
+            medium: self
+                .subsurface
+                .map(|s| Medium::from_mean_free_path(s.mean_free_path, s.albedo))
+                .or(self.medium)
+                .or_else(|| self.absorption.map(|a| Medium::absorbing(a.coefficient()))),
+            subsurface: self.subsurface.is_some(),
+            dispersion: self.dispersion,
+            clearcoat: self.clearcoat,
+            sheen: self.sheen,
+            conductor: self.conductor,
+            anisotropy: self.anisotropy,
+            priority: self.priority,
+        }
+    }
+
+    /// Turns an emitted power into the radiance that gives out that much from `area`, as a Lambertian emitter
+    /// does with `power / (pi * area)`. Materials without one, or already spread, are left as they are.
+    pub fn spread_over(self, area: f32) -> Material {
+        let Some(power) = self.power else {
+            return self;
+        };
+        let radiance = if area > 0.0 && area.is_finite() {
+            power * HDR_SCALE / (PI * area)
+        } else {
+            0.0
+        };
+        let colour = Vec3::from(self.emission) * radiance;
+        Material {
+            emission: Rgb::from(colour),
+            power: None,
+            ..self
+        }
+    }
+
+    /// The chance from 0 to 1 that a ray meeting the surface at `at` stops there rather than passing through.
+    pub fn opacity(&self, textures: &[Texture], at: TexCoord) -> f32 {
+        match self.opacity {
+            Some(Opacity { value, texture }) => match texture.and_then(|h| textures.get(h as usize)) {
+                Some(t) => value * t.sample_scalar(at),
+                None => value,
+            },
+            None => 1.0,
+        }
+    }
+
+    /// The normal to shade with at `at`, which is `frame`'s own unless a normal or bump map tilts it.
+    pub fn shading_normal(&self, textures: &[Texture], at: TexCoord, frame: Frame) -> Vec3 {
+        match &self.relief {
+            Some(relief) => frame.to_world(relief.local_normal(textures, at, frame)),
+            None => frame.normal,
+        }
+    }
 }
 
 #[wasm_bindgen()]
@@ -24,6 +426,395 @@ impl Material {
             roughness,
             transmission,
             ior,
+            emission_texture: None,
+            albedo_texture: None,
+            metallic_texture: None,
+            roughness_texture: None,
+            transmission_texture: None,
+            absorption: None,
+            medium: None,
+            subsurface: None,
+            dispersion: None,
+            clearcoat: None,
+            sheen: None,
+            conductor: None,
+            anisotropy: None,
+            relief: None,
+            opacity: None,
+            power: None,
+            priority: 0,
         }
     }
+
+    /// Makes the surface glow with `watts` of power spread over whatever it's put on, in the units of `Light`, so
+    /// a light keeps its brightness when resized. Meshes share it out across all their triangles, and planes are
+    /// endless so don't emit at all. Triangles shine from both faces, which share the power. `colour` only sets
+    /// the tint: it's scaled to a luminance of one, like `Rgb::blackbody`, so the same power looks as bright
+    /// whatever the colour.
+    pub fn with_emission_power(self, colour: Rgb, watts: f32) -> Material {
+        let colour = Vec3::from(colour).max(Vec3::zero());
+        let luminance = colour.luminance();
+        let tint = if luminance > 0.0 {
+            colour / luminance
+        } else {
+            Vec3::zero()
+        };
+        Material {
+            emission: Rgb::from(tint),
+            power: Some(watts.max(0.0)),
+            ..self
+        }
+    }
+
+    /// As `with_emission_power`, but giving the brightness in lumens, using the 683 lumens per watt that light at
+    /// the eye's most sensitive wavelength has. A 60 W-equivalent household bulb gives out about 800.
+    pub fn with_emission_lumens(self, colour: Rgb, lumens: f32) -> Material {
+        self.with_emission_power(colour, lumens / LUMENS_PER_WATT)
+    }
+
+    /// Emits `watts` with the colour of a blackbody at `kelvin`, as with `Rgb::blackbody`.
+    pub fn with_blackbody(self, kelvin: f32, watts: f32) -> Material {
+        self.with_emission_power(Rgb::blackbody(kelvin), watts)
+    }
+
+    /// Makes the surface partly or wholly see-through without refracting, by `opacity` times the red channel of
+    /// `texture` if there is one. Rays pass through a fraction of `1 - opacity` of the time, so anything between 0
+    /// and 1 comes out as a noisy blend while an alpha mask from `Texture::alpha_image` cuts shapes out cleanly.
+    pub fn with_opacity(self, opacity: f32, texture: Option<u32>) -> Material {
+        Material {
+            opacity: Some(Opacity {
+                value: opacity.clamp(0.0, 1.0),
+                texture,
+            }),
+            ..self
+        }
+    }
+
+    /// Tilts the shading normal by a tangent-space normal map from `Texture::data_image`, with `strength`
+    /// scaling how far it leans from the surface. Replaces any bump map.
+    pub fn with_normal_map(self, texture: u32, strength: f32) -> Material {
+        Material {
+            relief: Some(Relief::Normal { texture, strength }),
+            ..self
+        }
+    }
+
+    /// Tilts the shading normal by the slope of a height map, which rises by `height` in UV units as the
+    /// texture's red channel goes from 0 to 1. Replaces any normal map.
+    pub fn with_bump_map(self, texture: u32, height: f32) -> Material {
+        Material {
+            relief: Some(Relief::Bump { texture, height }),
+            ..self
+        }
+    }
+
+    /// Stretches the specular highlight by `strength` from 0 to 1 along the surface's tangent turned by `rotation`
+    /// radians. Tangents follow increasing `u`, so on a sphere the stretch runs around it like lines of latitude,
+    /// and on a mesh it follows the texture layout.
+    pub fn with_anisotropy(self, strength: f32, rotation: f32) -> Material {
+        Material {
+            anisotropy: Some(Anisotropy {
+                strength: strength.clamp(0.0, 1.0),
+                rotation,
+            }),
+            ..self
+        }
+    }
+
+    /// A metal with the measured optical constants of `preset`, so its tint and the way it brightens towards
+    /// grazing angles are physically right.
+    pub fn metal(preset: Metal, roughness: f32) -> Material {
+        let conductor = preset.conductor();
+        Material::conductor(Rgb::from(conductor.eta), Rgb::from(conductor.k), roughness)
+    }
+
+    /// A metal with complex IOR `eta + ik` per channel, for ones without a preset. Values are usually quoted at
+    /// wavelengths near 650, 550 and 450 nm for red, green and blue.
+    pub fn conductor(eta: Rgb, k: Rgb, roughness: f32) -> Material {
+        let conductor = Conductor {
+            eta: Vec3::from(eta),
+            k: Vec3::from(k),
+        };
+        // The albedo is what the metal looks like straight on, for the AOVs and anything else that wants a colour
+        let albedo = Rgb::from(conductor.normal_reflectance());
+        Material {
+            conductor: Some(conductor),
+            ..Material::new(Rgb::new(0.0, 0.0, 0.0), albedo, 1.0, roughness, 0.0, 1.5)
+        }
+    }
+
+    /// Replaces the per-refraction `albedo` tint with absorption along the path inside the material, so thick
+    /// glass comes out darker than thin glass.
+    pub fn with_absorption(self, colour: Rgb, distance: f32) -> Material {
+        Material {
+            absorption: Some(Absorption {
+                colour: Vec3::from(colour),
+                distance,
+            }),
+            ..self
+        }
+    }
+
+    /// Fills the inside of a transmissive material with a scattering medium. With an `ior` of 1 the surface itself
+    /// disappears, leaving a volume of smoke or fog.
+    pub fn with_medium(self, medium: Medium) -> Material {
+        Material {
+            medium: Some(medium),
+            ..self
+        }
+    }
+
+    /// Renders the material as translucent: light enters through the surface, random walks through the inside,
+    /// scattering every `mean_free_path` on average with probability `albedo`, and leaves wherever it reaches the
+    /// surface again. A shorter path keeps light closer to where it went in; lowering `albedo` in a channel
+    /// absorbs more of that colour the deeper light goes. The surface keeps its own `roughness` for specular
+    /// reflection.
+    pub fn with_subsurface(self, mean_free_path: Rgb, albedo: Rgb) -> Material {
+        Material {
+            subsurface: Some(Subsurface {
+                mean_free_path: Vec3::from(mean_free_path),
+                albedo: Vec3::from(albedo),
+            }),
+            ..self
+        }
+    }
+
+    /// Covers the material in a clear coat with its own `roughness`, which reflects up to `weight` of the light on
+    /// top of whatever the material does underneath.
+    pub fn with_clearcoat(self, weight: f32, roughness: f32) -> Material {
+        Material {
+            clearcoat: Some(Clearcoat {
+                weight: weight.clamp(0.0, 1.0),
+                roughness,
+            }),
+            ..self
+        }
+    }
+
+    /// Adds a soft `colour`ed sheen that picks out silhouettes and grazing light, as on cloth. Higher `roughness`
+    /// spreads it further from the edges.
+    pub fn with_sheen(self, colour: Rgb, roughness: f32) -> Material {
+        Material {
+            sheen: Some(Sheen {
+                colour: Vec3::from(colour),
+                roughness: roughness.clamp(0.0, 1.0),
+            }),
+            ..self
+        }
+    }
+
+    /// Spreads the IOR of a transmissive material across wavelengths by Cauchy's equation, keeping `ior` at the d
+    /// line. `b` is in square micrometres: about 0.004 for crown glass and 0.01 or more for flint.
+    pub fn with_cauchy(self, b: f32) -> Material {
+        Material {
+            dispersion: Some(Dispersion::Cauchy { b }),
+            ..self
+        }
+    }
+
+    /// Spreads the IOR of a transmissive material across wavelengths by Sellmeier's equation, with `c1` to `c3` in
+    /// square micrometres. This also sets `ior` to the value at the d line, which is used outside spectral mode.
+    pub fn with_sellmeier(self, b1: f32, b2: f32, b3: f32, c1: f32, c2: f32, c3: f32) -> Material {
+        let dispersion = Dispersion::Sellmeier {
+            b: [b1, b2, b3],
+            c: [c1, c2, c3],
+        };
+        Material {
+            ior: dispersion.ior(self.ior, D_LINE * 1000.0),
+            dispersion: Some(dispersion),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(u: f32, v: f32) -> TexCoord {
+        TexCoord {
+            uv: Vec2::new(u, v),
+            point: Vec3::zero(),
+            local: Vec3::zero(),
+        }
+    }
+
+    #[test]
+    fn test_untextured_surface_uses_constants() {
+        let material = Material::new(Rgb::new(1.0, 0.0, 0.0), Rgb::new(0.5, 0.5, 0.5), 0.2, 0.3, 0.0, 1.5);
+        let surface = material.surface(&[], at(0.5, 0.5));
+        assert_eq!(surface.emission, Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(surface.albedo, Vec3::new(0.5, 0.5, 0.5));
+        assert_eq!((surface.metallic, surface.roughness, surface.ior), (0.2, 0.3, 1.5));
+    }
+
+    #[test]
+    fn test_textures_scale_constants() {
+        let checker = Texture::checker(Rgb::new(1.0, 1.0, 1.0), Rgb::new(0.0, 0.5, 0.0), 1.0);
+        let mut material = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(0.5, 1.0, 1.0), 0.0, 0.8, 0.0, 1.5);
+        material.albedo_texture = Some(0);
+        material.roughness_texture = Some(0);
+
+        let even = material.surface(&[checker.clone()], at(0.5, 0.5));
+        assert_eq!(even.albedo, Vec3::new(0.5, 1.0, 1.0));
+        assert_eq!(even.roughness, 0.8);
+
+        let odd = material.surface(&[checker], at(1.5, 0.5));
+        assert_eq!(odd.albedo, Vec3::new(0.0, 0.5, 0.0));
+        assert_eq!(odd.roughness, 0.0);
+    }
+
+    #[test]
+    fn test_missing_texture_is_ignored() {
+        let mut material = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(0.5, 0.5, 0.5), 0.0, 0.0, 0.0, 1.5);
+        material.albedo_texture = Some(7);
+        assert_eq!(material.surface(&[], at(0.0, 0.0)).albedo, Vec3::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn test_normal_and_bump_maps_tilt_the_shading_normal() {
+        let frame = Frame::from_normal_and_tangent(Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let textures = [
+            Texture::constant(Rgb::new(0.75, 0.5, 1.0)),
+            Texture::gradient(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0)),
+        ];
+        let plain = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(0.5, 0.5, 0.5), 0.0, 0.5, 0.0, 1.5);
+        assert_eq!(plain.shading_normal(&textures, at(0.5, 0.5), frame), frame.normal);
+
+        let mapped = plain
+            .with_normal_map(0, 1.0)
+            .shading_normal(&textures, at(0.5, 0.5), frame);
+        assert!((mapped - frame.to_world(Vec3::new(0.5, 0.0, 1.0).normalize())).mag() < 1e-5);
+
+        // The gradient rises along v, so the normal leans back against it
+        let bumped = plain
+            .with_bump_map(1, 0.5)
+            .shading_normal(&textures, at(0.5, 0.5), frame);
+        assert!((bumped - frame.to_world(Vec3::new(0.0, -0.5, 1.0).normalize())).mag() < 1e-3);
+
+        let missing = plain
+            .with_bump_map(7, 0.5)
+            .shading_normal(&textures, at(0.5, 0.5), frame);
+        assert_eq!(missing, frame.normal);
+    }
+
+    #[test]
+    fn test_opacity_is_scaled_by_its_texture() {
+        let material = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(0.5, 0.5, 0.5), 0.0, 0.5, 0.0, 1.5);
+        let textures = [Texture::checker(Rgb::new(1.0, 1.0, 1.0), Rgb::new(0.0, 0.0, 0.0), 1.0)];
+        assert_eq!(material.opacity(&textures, at(0.5, 0.5)), 1.0);
+        assert_eq!(material.with_opacity(0.5, None).opacity(&textures, at(0.5, 0.5)), 0.5);
+        let masked = material.with_opacity(0.8, Some(0));
+        assert_eq!(masked.opacity(&textures, at(0.5, 0.5)), 0.8);
+        assert_eq!(masked.opacity(&textures, at(1.5, 0.5)), 0.0);
+        assert_eq!(
+            material.with_opacity(2.0, Some(4)).opacity(&textures, at(0.5, 0.5)),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_emission_power_and_lumens() {
+        let material = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(0.5, 0.5, 0.5), 0.0, 0.5, 0.0, 1.5);
+        let white = Rgb::new(1.0, 1.0, 1.0);
+        let lit = material.with_emission_power(white, PI).spread_over(2.0);
+        assert_eq!(Vec3::from(lit.emission), Vec3::new(1.0, 1.0, 1.0) * (HDR_SCALE / 2.0));
+        let bulb = material.with_emission_lumens(white, 683.0).spread_over(1.0);
+        let watt = material.with_emission_power(white, 1.0).spread_over(1.0);
+        assert!((Vec3::from(bulb.emission) - Vec3::from(watt.emission)).mag() < 1e-3);
+        // The colour's brightness doesn't change the power, only its tint does
+        for colour in [
+            Rgb::new(2.0, 2.0, 2.0),
+            Rgb::new(1.0, 0.0, 0.0),
+            Rgb::new(0.1, 0.2, 0.9),
+        ] {
+            let tinted = material.with_emission_power(colour, 1.0).spread_over(1.0);
+            let luminance = Vec3::from(tinted.emission).luminance();
+            assert!((luminance - Vec3::from(watt.emission).luminance()).abs() < 1e-3);
+        }
+        let red = material
+            .with_emission_power(Rgb::new(1.0, 0.0, 0.0), 1.0)
+            .spread_over(1.0);
+        assert_eq!((red.emission.g, red.emission.b), (0.0, 0.0));
+        // A warm blackbody looks as bright as white for the same power, only redder
+        let warm = material.with_blackbody(2700.0, 1.0).spread_over(1.0);
+        assert!((Vec3::from(warm.emission).luminance() - Vec3::from(watt.emission).luminance()).abs() < 1e-2);
+        assert!(warm.emission.r > warm.emission.b);
+    }
+
+    #[test]
+    fn test_absorption_coefficient() {
+        let material = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 0.0, 1.0, 1.5)
+            .with_absorption(Rgb::new(0.5, 1.0, 0.25), 2.0);
+        let medium = material.surface(&[], at(0.0, 0.0)).medium.unwrap();
+        let transmittance = medium.transmittance(2.0);
+        assert!((transmittance - Vec3::new(0.5, 1.0, 0.25)).mag() < 1e-5);
+    }
+
+    #[test]
+    fn test_subsurface_fills_interior() {
+        let material = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 0.3, 0.0, 1.4)
+            .with_subsurface(Rgb::new(0.5, 0.25, 0.1), Rgb::new(0.9, 0.9, 0.5));
+        let surface = material.surface(&[], at(0.0, 0.0));
+        assert!(surface.subsurface && surface.is_transmissive());
+        let medium = surface.medium.unwrap();
+        assert!((medium.extinction() - Vec3::new(2.0, 4.0, 10.0)).mag() < 1e-5);
+        assert!((medium.transmittance(0.5).x - (-1f32).exp()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_layers_reach_the_surface() {
+        let material = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(0.5, 0.0, 0.0), 0.0, 0.5, 0.0, 1.5)
+            .with_clearcoat(1.5, 0.1)
+            .with_sheen(Rgb::new(0.2, 0.3, 0.4), 0.6);
+        let surface = material.surface(&[], at(0.0, 0.0));
+        assert_eq!(
+            surface.clearcoat,
+            Some(Clearcoat {
+                weight: 1.0,
+                roughness: 0.1
+            })
+        );
+        assert_eq!(surface.sheen.unwrap().colour, Vec3::new(0.2, 0.3, 0.4));
+    }
+
+    #[test]
+    fn test_metal_presets_have_their_colours() {
+        let gold = Material::metal(Metal::Gold, 0.2);
+        assert!(gold.albedo.r > gold.albedo.g && gold.albedo.g > gold.albedo.b);
+        let copper = Metal::Copper.conductor().normal_reflectance();
+        assert!(copper.x > 0.9 && copper.z < 0.7);
+        let silver = Metal::Silver.conductor().normal_reflectance();
+        assert!(silver.x > 0.9 && silver.z > 0.9);
+        let surface = gold.surface(&[], at(0.0, 0.0));
+        assert_eq!(surface.conductor, Some(Metal::Gold.conductor()));
+        assert_eq!((surface.metallic, surface.roughness), (1.0, 0.2));
+    }
+
+    #[test]
+    fn test_cauchy_dispersion_keeps_d_line_ior() {
+        let dispersion = Dispersion::Cauchy { b: 0.01 };
+        assert!((dispersion.ior(1.5, 587.6) - 1.5).abs() < 1e-5);
+        // Blue bends more than red
+        assert!(dispersion.ior(1.5, 450.0) > dispersion.ior(1.5, 650.0));
+    }
+
+    #[test]
+    fn test_sellmeier_matches_catalogue_glass() {
+        // Schott N-BK7
+        let material = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 0.0, 1.0, 1.0)
+            .with_sellmeier(
+                1.039_612,
+                0.231_792_34,
+                1.010_469_5,
+                0.006_000_699,
+                0.020_017_914,
+                103.560_65,
+            );
+        assert!((material.ior - 1.5168).abs() < 1e-3, "{}", material.ior);
+        let surface = material.surface(&[], at(0.0, 0.0));
+        assert!(surface.is_dispersive());
+        let blue = surface.dispersion.unwrap().ior(material.ior, 486.1);
+        assert!((blue - 1.5224).abs() < 1e-3, "{}", blue);
+    }
 }
@@ -0,0 +1,584 @@
+use std::f32::consts::PI;
+
+use rand::Rng;
+use wasm_bindgen::prelude::*;
+
+use crate::material::Surface;
+use crate::ray::Ray;
+use crate::rgb::Rgb;
+use crate::spectrum::Wavelengths;
+use crate::vec3::Vec3;
+use crate::volume::DensityField;
+
+/// How many overlapping transmissive objects a path can be inside at once.
+const MAX_DEPTH: usize = 8;
+
+/// A homogeneous participating medium like fog, smoke or murky water. Coefficients are per unit of scene distance.
+#[wasm_bindgen]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Medium {
+    absorption: Vec3,
+    scattering: Vec3,
+    /// Henyey-Greenstein asymmetry, from -1 for back scattering through 0 for even to 1 for forward scattering.
+    anisotropy: f32,
+    /// Handle of a `DensityField` scaling the coefficients from place to place. Without one the medium is
+    /// homogeneous.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    density: Option<u32>,
+}
+
+impl Medium {
+    /// A medium that only absorbs, as inside tinted glass.
+    pub fn absorbing(absorption: Vec3) -> Self {
+        Self {
+            absorption,
+            scattering: Vec3::zero(),
+            anisotropy: 0.0,
+            density: None,
+        }
+    }
+
+    /// A medium light crosses `mean_free_path` of on average between interactions, scattering at each with
+    /// probability `albedo` and otherwise being absorbed. Both are per channel.
+    pub fn from_mean_free_path(mean_free_path: Vec3, albedo: Vec3) -> Self {
+        let channel = |d: f32| 1.0 / d.max(1e-6);
+        let extinction = Vec3::new(
+            channel(mean_free_path.x),
+            channel(mean_free_path.y),
+            channel(mean_free_path.z),
+        );
+        let albedo = albedo.max(Vec3::zero()).min(Vec3::new(1.0, 1.0, 1.0));
+        Self {
+            absorption: extinction * (Vec3::new(1.0, 1.0, 1.0) - albedo),
+            scattering: extinction * albedo,
+            anisotropy: 0.0,
+            density: None,
+        }
+    }
+
+    /// The medium's coefficients upsampled to spectra and taken at the path's wavelengths.
+    pub fn at_wavelengths(self, wavelengths: &Wavelengths) -> Self {
+        Self {
+            absorption: wavelengths.radiance(self.absorption),
+            scattering: wavelengths.radiance(self.scattering),
+            ..self
+        }
+    }
+
+    pub fn with_density(self, handle: u32) -> Self {
+        Self {
+            density: Some(handle),
+            ..self
+        }
+    }
+
+    pub fn density(&self) -> Option<u32> {
+        self.density
+    }
+
+    pub fn extinction(&self) -> Vec3 {
+        self.absorption + self.scattering
+    }
+
+    pub fn phase(&self) -> HenyeyGreenstein {
+        HenyeyGreenstein { g: self.anisotropy }
+    }
+
+    pub fn transmittance(&self, distance: f32) -> Vec3 {
+        (self.extinction() * -distance).exp()
+    }
+
+    /// Samples how far light gets through the medium before scattering, up to `max`. The distance is sampled from
+    /// one channel's extinction and the weight averages the density over all three, so coloured media stay
+    /// unbiased.
+    pub fn sample_distance(&self, max: f32, rng: &mut impl Rng) -> FreeFlight {
+        // Nothing to scatter off, so the transmittance can be applied exactly
+        if self.scattering == Vec3::zero() {
+            return FreeFlight {
+                distance: None,
+                weight: self.transmittance(max),
+            };
+        }
+
+        let extinction = self.extinction();
+        let channels = [extinction.x, extinction.y, extinction.z];
+        let sigma = channels[((rng.gen::<f32>() * 3.0) as usize).min(2)];
+        let distance = if sigma > 0.0 {
+            -(1.0 - rng.gen::<f32>()).ln() / sigma
+        } else {
+            f32::INFINITY
+        };
+
+        if distance < max {
+            let transmittance = self.transmittance(distance);
+            let density = average(extinction * transmittance);
+            FreeFlight {
+                distance: Some(distance),
+                weight: if density > 0.0 {
+                    transmittance * self.scattering / density
+                } else {
+                    Vec3::zero()
+                },
+            }
+        } else {
+            let transmittance = self.transmittance(max);
+            let probability = average(transmittance);
+            FreeFlight {
+                distance: None,
+                weight: if probability > 0.0 {
+                    transmittance / probability
+                } else {
+                    Vec3::zero()
+                },
+            }
+        }
+    }
+}
+
+impl Medium {
+    /// The largest extinction anywhere in `field`, which tracking takes steps against.
+    fn majorant(&self, field: &DensityField) -> f32 {
+        let extinction = self.extinction();
+        extinction.x.max(extinction.y).max(extinction.z) * field.max_density()
+    }
+
+    /// Delta tracking through a medium whose density varies by `field`: steps are taken as if it were uniformly as
+    /// thick as its thickest point, and each tentative collision is randomly a real scatter, an absorption or a
+    /// null collision that carries on. Covers `length` along the unit `direction` from `start`.
+    pub fn track(
+        &self,
+        field: &DensityField,
+        start: Vec3,
+        direction: Vec3,
+        length: f32,
+        rng: &mut impl Rng,
+    ) -> FreeFlight {
+        let majorant = self.majorant(field);
+        let mut weight = Vec3::new(1.0, 1.0, 1.0);
+        if majorant <= 0.0 {
+            return FreeFlight { distance: None, weight };
+        }
+
+        let mut t = 0.0;
+        loop {
+            t -= (1.0 - rng.gen::<f32>()).ln() / majorant;
+            if t >= length {
+                return FreeFlight { distance: None, weight };
+            }
+
+            let density = field.density(start + direction * t);
+            let absorption = self.absorption * density;
+            let scattering = self.scattering * density;
+            let null = Vec3::new(majorant, majorant, majorant) - absorption - scattering;
+            let p_absorb = average(absorption) / majorant;
+            let p_scatter = average(scattering) / majorant;
+
+            let u = rng.gen::<f32>();
+            if u < p_absorb {
+                return FreeFlight {
+                    distance: None,
+                    weight: Vec3::zero(),
+                };
+            }
+            if u < p_absorb + p_scatter {
+                return FreeFlight {
+                    distance: Some(t),
+                    weight: weight * scattering / (majorant * p_scatter),
+                };
+            }
+            // Coloured media can have different null densities per channel, which the weight makes up for
+            weight = weight * null / (majorant * (1.0 - p_absorb - p_scatter));
+        }
+    }
+
+    /// Ratio tracking: an unbiased estimate of the transmittance over `length` along the unit `direction` from
+    /// `start`, through a medium whose density varies by `field`.
+    pub fn track_transmittance(
+        &self,
+        field: &DensityField,
+        start: Vec3,
+        direction: Vec3,
+        length: f32,
+        rng: &mut impl Rng,
+    ) -> Vec3 {
+        let majorant = self.majorant(field);
+        let mut transmittance = Vec3::new(1.0, 1.0, 1.0);
+        if majorant <= 0.0 {
+            return transmittance;
+        }
+
+        let mut t = 0.0;
+        loop {
+            t -= (1.0 - rng.gen::<f32>()).ln() / majorant;
+            if t >= length || transmittance == Vec3::zero() {
+                return transmittance;
+            }
+            let extinction = self.extinction() * field.density(start + direction * t);
+            transmittance = transmittance * (Vec3::new(1.0, 1.0, 1.0) - extinction / majorant);
+        }
+    }
+}
+
+fn average(v: Vec3) -> f32 {
+    (v.x + v.y + v.z) / 3.0
+}
+
+#[wasm_bindgen]
+impl Medium {
+    #[wasm_bindgen(constructor)]
+    pub fn new(absorption: Rgb, scattering: Rgb, anisotropy: f32) -> Medium {
+        Medium {
+            absorption: Vec3::from(absorption).max(Vec3::zero()),
+            scattering: Vec3::from(scattering).max(Vec3::zero()),
+            anisotropy: anisotropy.clamp(-0.99, 0.99),
+            density: None,
+        }
+    }
+}
+
+/// The outcome of sampling a distance through a medium.
+pub struct FreeFlight {
+    /// How far along the ray it scattered, or `None` if it got all the way through.
+    pub distance: Option<f32>,
+    /// Throughput weight for whichever of the two happened.
+    pub weight: Vec3,
+}
+
+/// The Henyey-Greenstein phase function. Directions follow the BSDF convention: `wo` points back the way the light
+/// is going and `wi` towards where it came from.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct HenyeyGreenstein {
+    g: f32,
+}
+
+impl HenyeyGreenstein {
+    /// The phase function's value, which is also its sampling density.
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let cos_theta = wo.dot(wi);
+        let denominator = 1.0 + self.g * self.g + 2.0 * self.g * cos_theta;
+        (1.0 - self.g * self.g) / (4.0 * PI * denominator * denominator.max(1e-8).sqrt())
+    }
+
+    pub fn sample(&self, wo: Vec3, u1: f32, u2: f32) -> Vec3 {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u1
+        } else {
+            let square = (1.0 - g * g) / (1.0 + g - 2.0 * g * u1);
+            -(1.0 + g * g - square * square) / (2.0 * g)
+        }
+        .clamp(-1.0, 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        let (tangent, bitangent) = wo.orthonormal_basis();
+        (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + wo * cos_theta).normalize()
+    }
+}
+
+/// A global fog filling a sphere around the scene. Bounding it means rays that escape to the environment still see
+/// the sky through a finite amount of haze, rather than an infinite fog swallowing it.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Fog {
+    pub medium: Medium,
+    pub centre: Vec3,
+    pub radius: f32,
+}
+
+impl Fog {
+    /// The stretch of `ray` between 0 and `max` that is inside the fog, in distances along the ray.
+    pub fn span(&self, ray: Ray, max: f32) -> Option<(f32, f32)> {
+        let direction = ray.direction.normalize();
+        let to_origin = ray.origin - self.centre;
+        let b = to_origin.dot(direction);
+        let c = to_origin.mag_squared() - self.radius * self.radius;
+        let discriminant = b * b - c;
+        if discriminant <= 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        let (near, far) = ((-b - root).max(0.0), (-b + root).min(max));
+        if near < far {
+            Some((near, far))
+        } else {
+            None
+        }
+    }
+}
+
+/// The inside of a transmissive entity, as far as a path travelling through it is concerned.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Interior {
+    pub entity_id: u32,
+    pub ior: f32,
+    pub priority: u32,
+    pub medium: Option<Medium>,
+    /// Whether paths inside are on a subsurface random walk, which has its own budget of steps.
+    pub subsurface: bool,
+}
+
+impl Interior {
+    pub fn new(entity_id: u32, surface: &Surface) -> Self {
+        Self {
+            entity_id,
+            ior: surface.ior,
+            priority: surface.priority,
+            medium: surface.medium,
+            subsurface: surface.subsurface,
+        }
+    }
+}
+
+/// The interiors a path is currently inside, innermost last. Where objects overlap, the one with the highest
+/// priority decides the medium, after Schmidt and Budge, "Simple Nested Dielectrics in Ray Traced Images" (2002).
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MediumStack {
+    entries: [Option<Interior>; MAX_DEPTH],
+    len: usize,
+}
+
+impl MediumStack {
+    /// Paths start out in air.
+    pub fn empty() -> Self {
+        Self {
+            entries: [None; MAX_DEPTH],
+            len: 0,
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Interior> {
+        self.entries[..self.len].iter().flatten()
+    }
+
+    pub fn contains(&self, entity_id: u32) -> bool {
+        self.iter().any(|i| i.entity_id == entity_id)
+    }
+
+    /// The medium the path is travelling through: the highest priority interior, most recently entered on ties.
+    pub fn current(&self) -> Option<&Interior> {
+        self.iter().fold(None, |best, i| match best {
+            Some(b) if b.priority > i.priority => Some(b),
+            _ => Some(i),
+        })
+    }
+
+    pub fn ior(&self) -> f32 {
+        self.current().map_or(1.0, |i| i.ior)
+    }
+
+    /// The participating medium the path is in, if the interior it is inside has one.
+    pub fn medium(&self) -> Option<Medium> {
+        self.current().and_then(|i| i.medium)
+    }
+
+    pub fn push(&self, interior: Interior) -> Self {
+        let mut next = *self;
+        if next.len < MAX_DEPTH {
+            next.entries[next.len] = Some(interior);
+            next.len += 1;
+        }
+        next
+    }
+
+    pub fn remove(&self, entity_id: u32) -> Self {
+        let mut next = Self::empty();
+        for interior in self.iter().filter(|i| i.entity_id != entity_id) {
+            next = next.push(*interior);
+        }
+        next
+    }
+
+    /// The stack after crossing into or out of `interior`.
+    pub fn cross(&self, interior: Interior, entering: bool) -> Self {
+        if entering {
+            self.push(interior)
+        } else {
+            self.remove(interior.entity_id)
+        }
+    }
+
+    /// Whether crossing `interior`'s boundary is hidden by a higher priority medium the path is already in, in
+    /// which case the ray should carry on as if the surface wasn't there.
+    pub fn is_false_interface(&self, interior: &Interior) -> bool {
+        self.iter()
+            .any(|i| i.entity_id != interior.entity_id && i.priority > interior.priority)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    fn interior(entity_id: u32, ior: f32, priority: u32) -> Interior {
+        Interior {
+            entity_id,
+            ior,
+            priority,
+            medium: None,
+            subsurface: false,
+        }
+    }
+
+    #[test]
+    fn test_empty_stack_is_air() {
+        let stack = MediumStack::empty();
+        assert_eq!(stack.ior(), 1.0);
+        assert!(stack.current().is_none());
+    }
+
+    #[test]
+    fn test_innermost_wins_on_ties() {
+        let water = interior(1, 1.33, 0);
+        let ice = interior(2, 1.31, 0);
+        let stack = MediumStack::empty().push(water).push(ice);
+        assert_eq!(stack.ior(), 1.31);
+        assert_eq!(stack.remove(2).ior(), 1.33);
+        assert!(!stack.is_false_interface(&ice));
+    }
+
+    #[test]
+    fn test_higher_priority_hides_lower() {
+        let glass = interior(1, 1.5, 2);
+        let liquid = interior(2, 1.33, 1);
+        let stack = MediumStack::empty().push(glass);
+        // Liquid overlapping the glass wall is ignored until the path leaves the glass
+        assert!(stack.is_false_interface(&liquid));
+        let stack = stack.push(liquid);
+        assert_eq!(stack.ior(), 1.5);
+        let stack = stack.remove(1);
+        assert_eq!(stack.ior(), 1.33);
+        assert!(!stack.is_false_interface(&liquid));
+    }
+
+    #[test]
+    fn test_overflow_is_ignored() {
+        let mut stack = MediumStack::empty();
+        for id in 0..(MAX_DEPTH as u32 + 2) {
+            stack = stack.push(interior(id, 1.5, 0));
+        }
+        assert!(stack.contains(0));
+        assert!(!stack.contains(MAX_DEPTH as u32));
+    }
+
+    #[test]
+    fn test_phase_mean_cosine_is_anisotropy() {
+        let mut rng = SmallRng::seed_from_u64(2);
+        let wo = Vec3::new(0.0, 0.0, 1.0);
+        for g in [-0.6, 0.0, 0.3, 0.8] {
+            let phase = HenyeyGreenstein { g };
+            let n = 50_000;
+            // Forward scattering carries on along -wo
+            let mean = (0..n)
+                .map(|_| -phase.sample(wo, rng.gen(), rng.gen()).dot(wo))
+                .sum::<f32>()
+                / n as f32;
+            assert!((mean - g).abs() < 0.01, "g {} gave mean cosine {}", g, mean);
+        }
+    }
+
+    #[test]
+    fn test_phase_integrates_to_one() {
+        let phase = HenyeyGreenstein { g: 0.7 };
+        let wo = Vec3::new(0.0, 0.6, 0.8);
+        let n = 200_000;
+        let mut rng = SmallRng::seed_from_u64(4);
+        // Uniform sphere samples have density 1 / 4 pi
+        let total = (0..n)
+            .map(|_| phase.eval(wo, Vec3::rng_normal(&mut rng).normalize()) * 4.0 * PI)
+            .sum::<f32>()
+            / n as f32;
+        assert!((total - 1.0).abs() < 0.02, "{}", total);
+    }
+
+    #[test]
+    fn test_free_flight_is_unbiased() {
+        // The expected weight of getting through equals the transmittance, even for coloured media
+        let medium = Medium::new(Rgb::new(0.1, 0.3, 0.0), Rgb::new(0.2, 0.0, 0.5), 0.0);
+        let mut rng = SmallRng::seed_from_u64(8);
+        let n = 100_000;
+        let mut through = Vec3::zero();
+        for _ in 0..n {
+            let flight = medium.sample_distance(2.0, &mut rng);
+            if flight.distance.is_none() {
+                through += flight.weight;
+            }
+        }
+        let expected = medium.transmittance(2.0);
+        assert!(
+            (through / n as f32 - expected).mag() < 0.01,
+            "{} vs {}",
+            through / n as f32,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_fog_span() {
+        let fog = Fog {
+            medium: Medium::absorbing(Vec3::new(1.0, 1.0, 1.0)),
+            centre: Vec3::zero(),
+            radius: 10.0,
+        };
+        let ray = Ray {
+            origin: Vec3::new(0.0, 0.0, -20.0),
+            direction: Vec3::new(0.0, 0.0, 2.0),
+        };
+        assert_eq!(fog.span(ray, f32::INFINITY), Some((10.0, 30.0)));
+        assert_eq!(fog.span(ray, 15.0), Some((10.0, 15.0)));
+        assert_eq!(fog.span(ray, 5.0), None);
+
+        let inside = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(1.0, 0.0, 0.0),
+        };
+        assert_eq!(fog.span(inside, f32::INFINITY), Some((0.0, 10.0)));
+    }
+
+    fn constant_field(density: f32) -> DensityField {
+        let mut bytes = vec![];
+        for dimension in [1u32, 1, 1] {
+            bytes.extend_from_slice(&dimension.to_le_bytes());
+        }
+        bytes.extend_from_slice(&density.to_le_bytes());
+        DensityField::grid(&bytes)
+            .unwrap()
+            .placed(Vec3::zero(), Vec3::new(10.0, 10.0, 10.0))
+    }
+
+    #[test]
+    fn test_tracking_matches_homogeneous_medium() {
+        // A constant field at half its majorant behaves like a homogeneous medium of half the coefficients
+        let medium = Medium::new(Rgb::new(0.2, 0.1, 0.4), Rgb::new(0.4, 0.3, 0.0), 0.0);
+        let thinned = Medium::new(Rgb::new(0.1, 0.05, 0.2), Rgb::new(0.2, 0.15, 0.0), 0.0);
+        let field = constant_field(0.5);
+        let (start, direction) = (Vec3::new(0.0, 5.0, 5.0), Vec3::new(1.0, 0.0, 0.0));
+        let expected = thinned.transmittance(3.0);
+
+        let mut rng = SmallRng::seed_from_u64(12);
+        let n = 100_000;
+        let mut ratio = Vec3::zero();
+        let mut through = Vec3::zero();
+        for _ in 0..n {
+            ratio += medium.track_transmittance(&field, start, direction, 3.0, &mut rng);
+            let flight = medium.track(&field, start, direction, 3.0, &mut rng);
+            if flight.distance.is_none() {
+                through += flight.weight;
+            }
+        }
+        assert!(
+            (ratio / n as f32 - expected).mag() < 0.01,
+            "{} vs {}",
+            ratio / n as f32,
+            expected
+        );
+        assert!(
+            (through / n as f32 - expected).mag() < 0.01,
+            "{} vs {}",
+            through / n as f32,
+            expected
+        );
+    }
+}
@@ -1,7 +1,9 @@
+use crate::vec2::Vec2;
 use crate::vec3::Vec3;
 
 struct Face {
     vertices: Vec<Vec3>,
+    uvs: Option<Vec<Vec2>>,
 }
 
 impl Face {
@@ -12,6 +14,13 @@ impl Face {
             .map(|w| (self.vertices[0], w[0], w[1]))
             .collect()
     }
+
+    pub fn triangle_uvs(&self) -> Vec<Option<[Vec2; 3]>> {
+        match &self.uvs {
+            Some(uvs) => uvs.windows(2).skip(1).map(|w| Some([uvs[0], w[0], w[1]])).collect(),
+            None => vec![None; self.vertices.len().saturating_sub(2)],
+        }
+    }
 }
 
 pub struct Model {
@@ -22,6 +31,7 @@ pub struct Model {
 impl Model {
     pub fn parse(data: &str) -> Self {
         let mut vertices: Vec<Vec3> = Vec::new();
+        let mut uvs: Vec<Vec2> = Vec::new();
         let mut faces: Vec<Face> = Vec::new();
         for line in data.lines() {
             let parts: Vec<&str> = line.split_whitespace().collect();
@@ -32,14 +42,25 @@ impl Model {
                     let z: f32 = z.parse().unwrap();
                     vertices.push(Vec3 { x, y, z });
                 }
+                ["vt", u, v, ..] => {
+                    let u: f32 = u.parse().unwrap();
+                    let v: f32 = v.parse().unwrap();
+                    // OBJ puts v = 0 at the bottom, textures here put it at the top
+                    uvs.push(Vec2::new(u, 1.0 - v));
+                }
                 ["f", rest @ ..] => {
                     let indices: Vec<usize> = rest
                         .iter()
                         .map(|s| s.split('/').next().unwrap().parse::<usize>().unwrap())
                         .collect();
+                    let uv_indices: Option<Vec<usize>> = rest
+                        .iter()
+                        .map(|s| s.split('/').nth(1).and_then(|i| i.parse::<usize>().ok()))
+                        .collect();
 
                     let face = Face {
                         vertices: indices.iter().map(|i| vertices[i - 1]).collect(),
+                        uvs: uv_indices.map(|indices| indices.iter().map(|i| uvs[i - 1]).collect()),
                     };
                     faces.push(face);
                 }
@@ -56,4 +77,33 @@ impl Model {
     pub fn triangles(&self) -> Vec<(Vec3, Vec3, Vec3)> {
         self.faces.iter().flat_map(|f| f.triangles()).collect()
     }
+
+    /// Texture coordinates for each of `triangles()`, where the file provided them.
+    pub fn triangle_uvs(&self) -> Vec<Option<[Vec2; 3]>> {
+        self.faces.iter().flat_map(|f| f.triangle_uvs()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quad() {
+        let model = Model::parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n");
+        let triangles = model.triangles();
+        assert_eq!(triangles.len(), 2);
+        assert_eq!(triangles[1].2, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(model.triangle_uvs(), vec![None, None]);
+    }
+
+    #[test]
+    fn test_parse_texture_coordinates() {
+        let model = Model::parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nf 1/1 2/2 3/3\n");
+        let uvs = model.triangle_uvs();
+        assert_eq!(
+            uvs,
+            vec![Some([Vec2::new(0.0, 1.0), Vec2::new(1.0, 1.0), Vec2::new(1.0, 0.0)])]
+        );
+    }
 }
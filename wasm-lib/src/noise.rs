@@ -0,0 +1,216 @@
+use crate::vec3::Vec3;
+
+/// Hashes an integer lattice point to 32 well-mixed bits.
+fn hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    h
+}
+
+/// Hashes an integer lattice point to a value in `[0, 1)`.
+fn lattice_value(x: i32, y: i32, z: i32) -> f32 {
+    (hash(x, y, z) & 0x00ff_ffff) as f32 / 16_777_216.0
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Interpolates the eight corners of the lattice cell containing `p`, after easing the offsets with `fade`.
+fn interpolate_cell(p: Vec3, fade: impl Fn(f32) -> f32, corner: impl Fn(i32, i32, i32, Vec3) -> f32) -> f32 {
+    let (x0, y0, z0) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let offset = Vec3::new(p.x - x0, p.y - y0, p.z - z0);
+    let (fx, fy, fz) = (fade(offset.x), fade(offset.y), fade(offset.z));
+    let (x0, y0, z0) = (x0 as i32, y0 as i32, z0 as i32);
+
+    let at = |dx: i32, dy: i32, dz: i32| {
+        corner(
+            x0 + dx,
+            y0 + dy,
+            z0 + dz,
+            offset - Vec3::new(dx as f32, dy as f32, dz as f32),
+        )
+    };
+    let face = |dz: i32| {
+        lerp(
+            lerp(at(0, 0, dz), at(1, 0, dz), fx),
+            lerp(at(0, 1, dz), at(1, 1, dz), fx),
+            fy,
+        )
+    };
+    lerp(face(0), face(1), fz)
+}
+
+/// Trilinearly interpolated value noise in `[0, 1)`.
+pub fn value(p: Vec3) -> f32 {
+    interpolate_cell(p, |t| t * t * (3.0 - 2.0 * t), |x, y, z, _| lattice_value(x, y, z))
+}
+
+/// Improved Perlin gradient noise, roughly in `[-1, 1]` and zero at every lattice point.
+pub fn perlin(p: Vec3) -> f32 {
+    // The twelve edge directions of a cube, plus four repeats so a hash can pick one with a mask
+    const GRADIENTS: [(f32, f32, f32); 16] = [
+        (1.0, 1.0, 0.0),
+        (-1.0, 1.0, 0.0),
+        (1.0, -1.0, 0.0),
+        (-1.0, -1.0, 0.0),
+        (1.0, 0.0, 1.0),
+        (-1.0, 0.0, 1.0),
+        (1.0, 0.0, -1.0),
+        (-1.0, 0.0, -1.0),
+        (0.0, 1.0, 1.0),
+        (0.0, -1.0, 1.0),
+        (0.0, 1.0, -1.0),
+        (0.0, -1.0, -1.0),
+        (1.0, 1.0, 0.0),
+        (-1.0, 1.0, 0.0),
+        (0.0, -1.0, 1.0),
+        (0.0, -1.0, -1.0),
+    ];
+
+    interpolate_cell(
+        p,
+        |t| t * t * t * (t * (t * 6.0 - 15.0) + 10.0),
+        |x, y, z, offset| {
+            let (gx, gy, gz) = GRADIENTS[(hash(x, y, z) >> 28) as usize];
+            gx * offset.x + gy * offset.y + gz * offset.z
+        },
+    )
+}
+
+/// Fractal Brownian motion: octaves of Perlin noise, each at twice the frequency and half the amplitude of the
+/// last. Normalised back to roughly `[-1, 1]`.
+pub fn fbm(p: Vec3, octaves: u32) -> f32 {
+    octave_sum(p, octaves, perlin)
+}
+
+/// Like `fbm` but summing the absolute value of each octave, which gives creases instead of smooth hills. In
+/// `[0, 1]`.
+pub fn turbulence(p: Vec3, octaves: u32) -> f32 {
+    octave_sum(p, octaves, |p| perlin(p).abs())
+}
+
+fn octave_sum(p: Vec3, octaves: u32, noise: impl Fn(Vec3) -> f32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+    let mut frequency = 1.0;
+    for _ in 0..octaves.max(1) {
+        sum += noise(p * frequency) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total
+}
+
+/// Cellular noise: the distances from `p` to the nearest and second nearest of one random feature point per
+/// lattice cell.
+pub fn worley(p: Vec3) -> (f32, f32) {
+    let (cx, cy, cz) = (p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32);
+    let mut nearest = (f32::INFINITY, f32::INFINITY);
+
+    for dz in -1..=1 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (x, y, z) = (cx + dx, cy + dy, cz + dz);
+                let feature = Vec3::new(
+                    x as f32 + lattice_value(x, y, z),
+                    y as f32 + lattice_value(z, x, y),
+                    z as f32 + lattice_value(y, z, x),
+                );
+                let dist = (feature - p).mag();
+                if dist < nearest.0 {
+                    nearest = (dist, nearest.0);
+                } else if dist < nearest.1 {
+                    nearest.1 = dist;
+                }
+            }
+        }
+    }
+
+    nearest
+}
+
+/// Veined stripes across x, bent by turbulence. In `[0, 1]`.
+pub fn marble(p: Vec3) -> f32 {
+    0.5 + 0.5 * (p.x * 4.0 + turbulence(p, 6) * 12.0).sin()
+}
+
+/// Concentric growth rings around the y axis, wobbled by low-frequency noise. In `[0, 1)`, with the sharp edge
+/// at the start of each ring.
+pub fn wood(p: Vec3) -> f32 {
+    let radius = (p.x * p.x + p.z * p.z).sqrt() + fbm(p * 0.5, 3) * 0.6;
+    let ring = radius * 4.0;
+    ring - ring.floor()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> impl Iterator<Item = Vec3> {
+        (0..500).map(|i| {
+            let i = i as f32;
+            Vec3::new(i * 0.137 - 20.0, i * 0.071 - 10.0, i * 0.293 + 3.0)
+        })
+    }
+
+    #[test]
+    fn test_value_is_bounded() {
+        assert!(points().all(|p| (0.0..1.0).contains(&value(p))));
+    }
+
+    #[test]
+    fn test_perlin_is_zero_on_lattice() {
+        assert_eq!(perlin(Vec3::new(3.0, -2.0, 7.0)), 0.0);
+        assert!(points().all(|p| perlin(p).abs() <= 1.5));
+        assert!(points().any(|p| perlin(p).abs() > 0.1));
+    }
+
+    #[test]
+    fn test_perlin_is_continuous() {
+        let mut previous = perlin(Vec3::new(0.0, 0.3, 0.7));
+        for i in 1..500 {
+            let value = perlin(Vec3::new(i as f32 * 0.01, 0.3, 0.7));
+            assert!(
+                (value - previous).abs() < 0.05,
+                "jumped from {} to {} at {}",
+                previous,
+                value,
+                i
+            );
+            previous = value;
+        }
+    }
+
+    #[test]
+    fn test_fractal_sums_are_bounded() {
+        for p in points() {
+            assert!(fbm(p, 5).abs() <= 1.5);
+            assert!((0.0..=1.5).contains(&turbulence(p, 5)));
+        }
+    }
+
+    #[test]
+    fn test_worley_orders_distances() {
+        for p in points() {
+            let (f1, f2) = worley(p);
+            assert!(f1 <= f2);
+            // Every cell has a feature point, so the nearest one is never further than the cell diagonal
+            assert!(f1 < 3f32.sqrt());
+        }
+    }
+
+    #[test]
+    fn test_presets_are_bounded() {
+        for p in points() {
+            assert!((0.0..=1.0).contains(&marble(p)));
+            assert!((0.0..1.0).contains(&wood(p)));
+        }
+    }
+}
@@ -1,8 +1,10 @@
 use crate::ray::Ray;
 use crate::traceable::Traceable;
+use crate::vec2::Vec2;
 use crate::vec3::Vec3;
 
 #[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Plane {
     pub normal: Vec3,
 }
@@ -35,6 +37,29 @@ impl Traceable for Plane {
 
         Some((t, normal))
     }
+
+    /// Planar projection in world units, so textures repeat once per unit.
+    fn uv(&self, point: Vec3, position: Vec3) -> Vec2 {
+        let (tangent, bitangent) = self.normal.orthonormal_basis();
+        let offset = point - position;
+        Vec2::new(offset.dot(tangent), offset.dot(bitangent))
+    }
+
+    fn tangent(&self, _point: Vec3, _position: Vec3) -> Vec3 {
+        self.normal.orthonormal_basis().0
+    }
+
+    fn area(&self) -> f32 {
+        f32::INFINITY
+    }
+
+    fn sample_towards(&self, _position: Vec3, _from: Vec3, _u1: f32, _u2: f32) -> Option<(Vec3, f32)> {
+        None
+    }
+
+    fn pdf_towards(&self, _position: Vec3, _from: Vec3, _point: Vec3) -> f32 {
+        0.0
+    }
 }
 
 #[cfg(test)]
@@ -66,4 +91,14 @@ mod tests {
 
         assert!(plane.intersect(ray, position).is_none());
     }
+
+    #[test]
+    fn test_plane_uv_is_in_world_units() {
+        let plane = Plane::new(Vec3::new(0.0, 1.0, 0.0));
+        let position = Vec3::new(3.0, -2.0, 1.0);
+        assert_eq!(plane.uv(position, position), Vec2::new(0.0, 0.0));
+
+        let uv = plane.uv(position + Vec3::new(3.0, 0.0, 4.0), position);
+        assert!((uv.mag() - 5.0).abs() < 1e-5);
+    }
 }
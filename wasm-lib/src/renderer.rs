@@ -9,13 +9,14 @@ use web_sys::OffscreenCanvasRenderingContext2d;
 use rand::rngs::SmallRng;
 use rand::SeedableRng;
 
-use crate::bvh::Tree;
+use crate::aov::{Aov, AovBuffers, AovPixel};
 use crate::log;
 use crate::post_processing::PostProcess;
 use crate::ray::Ray;
 use crate::scene::Scene;
 use crate::tracer;
 use crate::vec3::Vec3;
+use crate::world::World;
 
 #[wasm_bindgen]
 extern "C" {
@@ -45,8 +46,11 @@ fn avg_samples_into(samples: &[Vec<Vec3>], count: u32, out: &mut [Vec<Vec3>]) {
 
 fn samples_to_pixel_map_into(samples: &[Vec<Vec3>], out: &mut Vec<u8>) {
     let height = samples.len();
-    let width = samples[0].len();
+    let width = samples.first().map_or(0, Vec::len);
     out.resize(height * width * 4, 0);
+    if out.is_empty() {
+        return;
+    }
 
     out.par_chunks_mut(width * 4)
         .zip(samples.par_iter())
@@ -60,78 +64,148 @@ fn samples_to_pixel_map_into(samples: &[Vec<Vec3>], out: &mut Vec<u8>) {
         });
 }
 
-pub fn render(scene: &Scene, ctx: &OffscreenCanvasRenderingContext2d, on_sample: js_sys::Function) {
-    let half_width = (scene.width / 2) as i32;
-    let half_height = (scene.height / 2) as i32;
-
-    let width = scene.width;
-    let height = scene.height;
-    let camera = scene.camera();
-    let focal_length = camera.focal_length;
-    let focal_distance = camera.focal_distance as f32;
-    let aperture = camera.aperture;
-    let camera_origin = camera.position;
-    let camera_rotation = camera.rotation;
-    let bounces = scene.bounces;
-    let bvh = Tree::build(scene.entities());
-    let sample_count = scene.samples;
-    let post_processors: Vec<Rc<dyn PostProcess>> = scene.post_processors().iter().map(Rc::clone).collect();
-
-    let local_context = ctx.clone();
-    let on_sample = on_sample.clone();
-
-    let origin = camera_origin;
-    let mut samples: Vec<Vec<Vec3>> = vec![vec![Vec3::new(0.0, 0.0, 0.0); width as usize]; height as usize];
-    let mut avg_buf: Vec<Vec<Vec3>> = vec![vec![Vec3::new(0.0, 0.0, 0.0); width as usize]; height as usize];
-    let mut pixel_buf: Vec<u8> = Vec::with_capacity((width * height * 4) as usize);
+fn put_pixels(ctx: &OffscreenCanvasRenderingContext2d, width: u32, height: u32, pixel_buf: &[u8]) {
+    match ctx.create_image_data_with_sw_and_sh(width as f64, height as f64) {
+        Ok(image_data) => {
+            let array: js_sys::Uint8ClampedArray = js_sys::Reflect::get(&image_data, &"data".into())
+                .unwrap()
+                .unchecked_into();
+            array.copy_from(pixel_buf);
+            if let Err(e) = ctx.put_image_data(&image_data, 0.0, 0.0) {
+                log(&format!("Error putting image data: {:?}", e));
+            }
+        }
+        Err(e) => {
+            log(&format!("Error creating ImageData: {:?}", e));
+        }
+    }
+}
 
-    let f = Rc::new(RefCell::new(None));
-    let g = f.clone();
-    let mut s = 0;
+/// The camera's fixed parameters, in the pixel units the renderer shoots rays in.
+struct View {
+    origin: Vec3,
+    rotation: Vec3,
+    focal_length: f32,
+    focal_distance: f32,
+    aperture: f32,
+    half_width: i32,
+    half_height: i32,
+}
 
-    *g.borrow_mut() = Some(Closure::wrap(Box::new(move || {
-        if s >= sample_count {
-            let _ = f.borrow_mut().take();
-            return;
+impl View {
+    fn new(scene: &Scene) -> Self {
+        let camera = scene.camera();
+        Self {
+            origin: camera.position,
+            rotation: camera.rotation,
+            focal_length: camera.focal_length as f32,
+            focal_distance: camera.focal_distance as f32,
+            aperture: camera.aperture,
+            half_width: (scene.width / 2) as i32,
+            half_height: (scene.height / 2) as i32,
         }
-        let start = Date::now();
+    }
+}
 
-        samples.par_iter_mut().enumerate().for_each(|(j, row)| {
-            let mut rng = SmallRng::seed_from_u64((s as u64) << 32 | (j as u64));
+/// Adds one sample per pixel to `samples`. Each row's rng is seeded from the pass and row alone, so a pass renders
+/// the same image no matter how rayon schedules the rows.
+fn render_pass(
+    view: &View,
+    world: &World,
+    bounces: u32,
+    pass: u32,
+    samples: &mut [Vec<Vec3>],
+    aov_rows: &mut [Vec<AovPixel>],
+) {
+    samples
+        .par_iter_mut()
+        .zip(aov_rows.par_iter_mut())
+        .enumerate()
+        .for_each(|(j, (row, aov_row))| {
+            let mut rng = SmallRng::seed_from_u64((pass as u64) << 32 | (j as u64));
             for (i, sample) in row.iter_mut().enumerate() {
                 use rand::Rng;
-                let x = (i as i32 - half_width) as f32 + rng.gen_range(-0.5..0.5);
-                let y = (j as i32 - half_height) as f32 + rng.gen_range(-0.5..0.5);
+                let x = (i as i32 - view.half_width) as f32 + rng.gen_range(-0.5..0.5);
+                let y = (j as i32 - view.half_height) as f32 + rng.gen_range(-0.5..0.5);
                 let direction = (Vec3 {
                     x,
                     y,
-                    z: focal_length as f32,
+                    z: view.focal_length,
                 })
                 .normalize()
-                .rotate_vec(camera_rotation);
+                .rotate_vec(view.rotation);
 
-                let focus_point = origin + direction * focal_distance;
+                let focus_point = view.origin + direction * view.focal_distance;
 
                 let (jitter_x, jitter_y) = random_in_unit_disc(&mut rng);
                 let jittered_origin = Vec3 {
-                    x: origin.x + jitter_x * aperture * 0.5,
-                    y: origin.y + jitter_y * aperture * 0.5,
-                    z: origin.z,
+                    x: view.origin.x + jitter_x * view.aperture * 0.5,
+                    y: view.origin.y + jitter_y * view.aperture * 0.5,
+                    z: view.origin.z,
                 };
                 let jittered_direction = (focus_point - jittered_origin).normalize();
 
-                let res = tracer::trace(
+                let (res, first_hit) = tracer::trace(
                     Ray {
                         origin: jittered_origin,
                         direction: jittered_direction,
                     },
-                    &bvh,
+                    world,
                     bounces,
                     &mut rng,
                 );
                 *sample += res;
+                if let (Some(hit), Some(aov)) = (first_hit, aov_row.get_mut(i)) {
+                    aov.add(&hit);
+                }
             }
         });
+}
+
+pub fn draw_aov(scene: &Scene, ctx: &OffscreenCanvasRenderingContext2d, aov: Aov) {
+    let pixels = scene.aov_buffers().borrow().to_pixels(aov);
+    if pixels.len() != scene.height as usize || pixels.first().map_or(0, Vec::len) != scene.width as usize {
+        log("Error: AOVs were not recorded for the last render");
+        return;
+    }
+
+    let mut pixel_buf = Vec::with_capacity((scene.width * scene.height * 4) as usize);
+    samples_to_pixel_map_into(&pixels, &mut pixel_buf);
+    put_pixels(ctx, scene.width, scene.height, &pixel_buf);
+}
+
+pub fn render(scene: &Scene, ctx: &OffscreenCanvasRenderingContext2d, on_sample: js_sys::Function) {
+    let width = scene.width;
+    let height = scene.height;
+    let view = View::new(scene);
+    let bounces = scene.bounces;
+    let world = World::build(scene);
+    let sample_count = scene.samples;
+    let post_processors: Vec<Rc<dyn PostProcess>> = scene.post_processors().iter().map(Rc::clone).collect();
+    let aov_buffers = Rc::clone(scene.aov_buffers());
+    *aov_buffers.borrow_mut() = AovBuffers::new(width, height, scene.aovs);
+
+    let local_context = ctx.clone();
+    let on_sample = on_sample.clone();
+
+    let mut samples: Vec<Vec<Vec3>> = vec![vec![Vec3::new(0.0, 0.0, 0.0); width as usize]; height as usize];
+    let mut avg_buf: Vec<Vec<Vec3>> = vec![vec![Vec3::new(0.0, 0.0, 0.0); width as usize]; height as usize];
+    let mut pixel_buf: Vec<u8> = Vec::with_capacity((width * height * 4) as usize);
+
+    let f = Rc::new(RefCell::new(None));
+    let g = f.clone();
+    let mut s = 0;
+
+    *g.borrow_mut() = Some(Closure::wrap(Box::new(move || {
+        if s >= sample_count {
+            let _ = f.borrow_mut().take();
+            return;
+        }
+        let start = Date::now();
+
+        let mut aovs = aov_buffers.borrow_mut();
+        render_pass(&view, &world, bounces, s, &mut samples, aovs.rows_mut());
+        drop(aovs);
 
         s += 1;
         avg_samples_into(&samples, s, &mut avg_buf);
@@ -154,20 +228,7 @@ pub fn render(scene: &Scene, ctx: &OffscreenCanvasRenderingContext2d, on_sample:
             return;
         }
 
-        match local_context.create_image_data_with_sw_and_sh(width as f64, height as f64) {
-            Ok(image_data) => {
-                let array: js_sys::Uint8ClampedArray = js_sys::Reflect::get(&image_data, &"data".into())
-                    .unwrap()
-                    .unchecked_into();
-                array.copy_from(&pixel_buf);
-                if let Err(e) = local_context.put_image_data(&image_data, 0.0, 0.0) {
-                    log(&format!("Error putting image data: {:?}", e));
-                }
-            }
-            Err(e) => {
-                log(&format!("Error creating ImageData: {:?}", e));
-            }
-        }
+        put_pixels(&local_context, width, height, &pixel_buf);
 
         avg_buf = pixels;
 
@@ -183,6 +244,11 @@ pub fn render(scene: &Scene, ctx: &OffscreenCanvasRenderingContext2d, on_sample:
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::camera::Camera;
+    use crate::entity::Entity;
+    use crate::environment::Environment;
+    use crate::material::Material;
+    use crate::rgb::Rgb;
 
     // --- avg_samples_into tests ---
 
@@ -271,6 +337,12 @@ mod tests {
         assert_eq!(result.len(), 24);
     }
 
+    #[test]
+    fn pixel_map_empty_image() {
+        let result = samples_to_pixel_map(&[]);
+        assert!(result.is_empty());
+    }
+
     // --- round-trip: avg_samples -> samples_to_pixel_map ---
 
     #[test]
@@ -281,4 +353,34 @@ mod tests {
         let pixels = samples_to_pixel_map(&averaged);
         assert_eq!(pixels, vec![100, 150, 200, 255]);
     }
+
+    // --- render_pass tests ---
+
+    fn render_image(pass: u32) -> Vec<u8> {
+        let camera = Camera::new(Vec3::new(0.0, 0.0, -10.0), Vec3::zero(), 8, 10, 0.5);
+        let mut scene = Scene::new(8, 6, camera, 1, 4);
+        let matte = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(0.8, 0.4, 0.2), 0.0, 1.0, 0.0, 1.0);
+        scene.add_entity(Entity::new_sphere(Vec3::zero(), matte, 3.0));
+        scene.set_environment(Environment::sky(0.4, 1.2, 3.0, 1.0));
+        let world = World::build(&scene);
+
+        let mut samples = vec![vec![Vec3::zero(); 8]; 6];
+        let mut aovs = AovBuffers::new(8, 6, true);
+        render_pass(
+            &View::new(&scene),
+            &world,
+            scene.bounces,
+            pass,
+            &mut samples,
+            aovs.rows_mut(),
+        );
+        samples_to_pixel_map(&samples)
+    }
+
+    #[test]
+    fn render_pass_is_deterministic_for_a_seed() {
+        let first = render_image(3);
+        assert_eq!(first, render_image(3));
+        assert_ne!(first, render_image(4));
+    }
 }
@@ -1,9 +1,11 @@
 use wasm_bindgen::prelude::*;
 
+use crate::spectrum;
 use crate::vec3::Vec3;
 
 #[wasm_bindgen()]
 #[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rgb {
     pub r: f32,
     pub g: f32,
@@ -16,6 +18,11 @@ impl Rgb {
     pub fn new(r: f32, g: f32, b: f32) -> Rgb {
         Rgb { r, g, b }
     }
+
+    /// The colour of a blackbody at `kelvin`, with a luminance of one, for lights given by colour temperature.
+    pub fn blackbody(kelvin: f32) -> Rgb {
+        Rgb::from(spectrum::blackbody(kelvin))
+    }
 }
 
 impl From<Vec3> for Rgb {
@@ -27,3 +34,38 @@ impl From<Vec3> for Rgb {
         }
     }
 }
+
+/// Converts CIE XYZ to linear sRGB primaries, leaving out-of-gamut components negative.
+pub fn xyz_to_rgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
+
+/// Converts CIE XYZ to linear sRGB primaries, clamping out-of-gamut components to zero.
+pub fn xyz_to_linear_rgb(xyz: Vec3) -> Vec3 {
+    xyz_to_rgb(xyz).max(Vec3::zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xyz_to_linear_rgb_white_point() {
+        // D65 white maps to equal RGB
+        let rgb = xyz_to_linear_rgb(Vec3::new(0.9505, 1.0, 1.089));
+        assert!((rgb.x - 1.0).abs() < 1e-2, "{}", rgb);
+        assert!((rgb.y - 1.0).abs() < 1e-2, "{}", rgb);
+        assert!((rgb.z - 1.0).abs() < 1e-2, "{}", rgb);
+    }
+
+    #[test]
+    fn test_xyz_to_linear_rgb_clamps_negative() {
+        let rgb = xyz_to_linear_rgb(Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(rgb.x, 0.0);
+        assert!(rgb.y > 0.0);
+    }
+}
@@ -1,24 +1,55 @@
+use std::cell::RefCell;
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use web_sys::OffscreenCanvasRenderingContext2d;
 
+use crate::aov::{Aov, AovBuffers};
 use crate::camera::Camera;
 use crate::entity::Entity;
+use crate::environment::Environment;
+use crate::light::Light;
 use crate::material::Material;
+use crate::media::{Fog, Medium};
 use crate::model::Model;
 use crate::post_processing::{GammaCorrection, ImageFilter, PostProcess};
 use crate::renderer;
+use crate::rgb::Rgb;
+use crate::texture::Texture;
 use crate::vec3::Vec3;
+use crate::volume::DensityField;
 
 #[wasm_bindgen]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Scene {
     entities: Vec<Entity>,
+    /// Rebuilt from the entities when a scene is loaded, so it is not part of a saved scene.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    materials: Vec<Material>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    textures: Vec<Texture>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    lights: Vec<Light>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    fog: Option<Fog>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    volumes: Vec<DensityField>,
     camera: Camera,
+    environment: Environment,
     pub width: u32,
     pub height: u32,
     pub samples: u32,
     pub bounces: u32,
+    /// Record depth, normal, albedo, position and id buffers from each camera ray's first hit.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub aovs: bool,
+    /// Trace a few wavelengths per path instead of RGB, so dispersive glass splits light into colours.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub spectral: bool,
+    /// Post processors are behaviour rather than data, so they are not part of a saved scene.
+    #[cfg_attr(feature = "serde", serde(skip))]
     post_processors: Vec<Rc<dyn PostProcess>>,
+    #[cfg_attr(feature = "serde", serde(skip, default = "Scene::empty_aov_buffers"))]
+    aov_buffers: Rc<RefCell<AovBuffers>>,
 }
 
 impl Scene {
@@ -30,9 +61,38 @@ impl Scene {
         &self.camera
     }
 
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    pub fn textures(&self) -> &[Texture] {
+        &self.textures
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub fn fog(&self) -> Option<Fog> {
+        self.fog
+    }
+
+    pub fn volumes(&self) -> &[DensityField] {
+        &self.volumes
+    }
+
     pub fn post_processors(&self) -> &[Rc<dyn PostProcess>] {
         &self.post_processors
     }
+
+    /// Filled in by the renderer as samples complete, so it is shared rather than owned by a single render.
+    pub fn aov_buffers(&self) -> &Rc<RefCell<AovBuffers>> {
+        &self.aov_buffers
+    }
+
+    fn empty_aov_buffers() -> Rc<RefCell<AovBuffers>> {
+        Rc::new(RefCell::new(AovBuffers::new(0, 0, false)))
+    }
 }
 
 #[wasm_bindgen]
@@ -41,12 +101,21 @@ impl Scene {
     pub fn new(width: u32, height: u32, camera: Camera, samples: u32, bounces: u32) -> Self {
         Self {
             entities: vec![],
+            materials: vec![],
+            textures: vec![],
+            lights: vec![],
+            fog: None,
+            volumes: vec![],
             camera,
+            environment: Environment::default(),
             width,
             height,
             samples,
             bounces,
+            aovs: false,
+            spectral: false,
             post_processors: vec![],
+            aov_buffers: Self::empty_aov_buffers(),
         }
     }
 
@@ -55,7 +124,50 @@ impl Scene {
     }
 
     pub fn add_entity(&mut self, entity: Entity) {
-        self.entities.push(entity);
+        let material = entity.material();
+        let material_id = match self.materials.iter().position(|m| *m == material) {
+            Some(index) => index,
+            None => {
+                self.materials.push(material);
+                self.materials.len() - 1
+            }
+        };
+        let id = self.entities.len();
+        self.entities.push(entity.with_ids(id as u32, material_id as u32));
+    }
+
+    /// Returns the handle materials use to refer to the texture.
+    pub fn add_texture(&mut self, texture: Texture) -> u32 {
+        self.textures.push(texture);
+        (self.textures.len() - 1) as u32
+    }
+
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+
+    /// Fills a sphere around the scene with `medium`. Make it large enough to hold everything the camera sees;
+    /// rays leaving it reach the environment through however much fog they crossed.
+    pub fn set_fog(&mut self, medium: Medium, centre: Vec3, radius: f32) {
+        self.fog = Some(Fog { medium, centre, radius });
+    }
+
+    pub fn clear_fog(&mut self) {
+        self.fog = None;
+    }
+
+    /// Adds a box from `min` to `max` filled with `medium`, its thickness varying by `field` stretched across the
+    /// box. Clouds, smoke and explosions.
+    pub fn add_volume(&mut self, min: Vec3, max: Vec3, medium: Medium, field: DensityField) {
+        self.volumes.push(field.placed(min, max));
+        let handle = (self.volumes.len() - 1) as u32;
+        let boundary = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 0.0, 1.0, 1.0)
+            .with_medium(medium.with_density(handle));
+        self.add_entity(Entity::new_cuboid((min + max) * 0.5, boundary, max - min));
+    }
+
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.environment = environment;
     }
 
     pub fn set_gamma_correction(&mut self, gamma: f32) {
@@ -68,14 +180,202 @@ impl Scene {
         renderer::render(self, ctx, on_sample);
     }
 
+    /// The latest values of an AOV from the current render, with `aov.components()` floats per pixel.
+    pub fn aov(&self, aov: Aov) -> Vec<f32> {
+        self.aov_buffers.borrow().channel(aov)
+    }
+
+    /// Draws an AOV onto a canvas, remapped into a viewable range.
+    pub fn draw_aov(&self, ctx: &OffscreenCanvasRenderingContext2d, aov: Aov) {
+        renderer::draw_aov(self, ctx, aov);
+    }
+
     pub fn load_model(&mut self, text: &str, position: Vec3, rotation: Vec3, scale: f32, material: Material) {
         let model = Model::parse(text);
-        for (a, b, c) in model.triangles() {
-            let a = a.rotate_vec(rotation) * scale;
-            let b = b.rotate_vec(rotation) * scale;
-            let c = c.rotate_vec(rotation) * scale;
+        let triangles: Vec<_> = model
+            .triangles()
+            .into_iter()
+            .map(|(a, b, c)| {
+                (
+                    a.rotate_vec(rotation) * scale,
+                    b.rotate_vec(rotation) * scale,
+                    c.rotate_vec(rotation) * scale,
+                )
+            })
+            .collect();
+        // An emitted power is for the whole model, not each triangle, and each triangle shines from both faces
+        let area = triangles.iter().map(|&(a, b, c)| (b - a).cross(c - a).mag()).sum();
+        let material = material.spread_over(area);
+        for ((a, b, c), uvs) in triangles.into_iter().zip(model.triangle_uvs()) {
             let entity = Entity::new_triangle(position, a, b, c, material);
-            self.add_entity(entity);
+            self.add_entity(match uvs {
+                Some(uvs) => entity.with_uvs(uvs),
+                None => entity,
+            });
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+#[wasm_bindgen]
+impl Scene {
+    pub fn to_json(&self) -> Result<String, JsError> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Scene, JsError> {
+        let mut scene: Scene = serde_json::from_str(json)?;
+        for entity in std::mem::take(&mut scene.entities) {
+            scene.add_entity(entity);
         }
+        Ok(scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "serde")]
+    use crate::hdr::HdrImage;
+    use crate::rgb::Rgb;
+
+    fn glass() -> Material {
+        Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(0.9, 0.9, 0.9), 0.0, 0.0, 1.0, 1.5)
+    }
+
+    fn test_scene() -> Scene {
+        let camera = Camera::new(Vec3::new(0.0, 0.0, -10.0), Vec3::zero(), 500, 150, 0.5);
+        let mut scene = Scene::new(64, 48, camera, 16, 8);
+        scene.add_entity(Entity::new_sphere(Vec3::new(0.0, 0.0, 150.0), glass(), 25.0));
+        scene.add_entity(Entity::new_plane(
+            Vec3::new(0.0, 25.0, 0.0),
+            glass(),
+            Vec3::new(0.0, -1.0, 0.0),
+        ));
+        scene.add_entity(Entity::new_triangle(
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            glass(),
+        ));
+        scene.set_environment(Environment::sky(0.4, 1.2, 3.0, 1.0));
+        scene
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_vec3_format() {
+        let json = serde_json::to_string(&Vec3::new(1.0, 2.5, -3.0)).unwrap();
+        assert_eq!(json, r#"{"x":1.0,"y":2.5,"z":-3.0}"#);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_material_format() {
+        let json = serde_json::to_string(&glass()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"emission":{"r":0.0,"g":0.0,"b":0.0},"albedo":{"r":0.9,"g":0.9,"b":0.9},"metallic":0.0,"roughness":0.0,"transmission":1.0,"ior":1.5}"#
+        );
+        assert!(serde_json::from_str::<Material>(&json).unwrap() == glass());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_triangle_saves_only_vertices() {
+        let entity = Entity::new_triangle(
+            Vec3::zero(),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            glass(),
+        );
+        let json = serde_json::to_string(&entity.shape()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"Triangle":[{"x":0.0,"y":0.0,"z":0.0},{"x":1.0,"y":0.0,"z":0.0},{"x":0.0,"y":1.0,"z":0.0}]}"#
+        );
+        let loaded: Entity = serde_json::from_str(&serde_json::to_string(&entity).unwrap()).unwrap();
+        assert!(loaded == entity);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_scene_round_trip() {
+        let scene = test_scene();
+        let json = scene.to_json().unwrap();
+        let loaded = Scene::from_json(&json).unwrap();
+
+        assert!(loaded.entities() == scene.entities());
+        assert_eq!(loaded.camera().position, scene.camera().position);
+        assert_eq!((loaded.width, loaded.height), (64, 48));
+        assert_eq!((loaded.samples, loaded.bounces), (16, 8));
+
+        let up = Vec3::new(0.0, -1.0, 0.0);
+        assert_eq!(loaded.environment().radiance(up), scene.environment().radiance(up));
+        assert_eq!(loaded.to_json().unwrap(), json);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_scene_ids_are_rebuilt_on_loading() {
+        let mut scene = test_scene();
+        let matte = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(0.5, 0.5, 0.5), 0.0, 1.0, 0.0, 1.5);
+        scene.add_entity(Entity::new_sphere(Vec3::zero(), matte, 1.0));
+        let mut json: serde_json::Value = serde_json::from_str(&scene.to_json().unwrap()).unwrap();
+        // Saved before AOVs were added, then edited by hand
+        json.as_object_mut().unwrap().remove("aovs");
+        json["entities"][3]["material_id"] = 7.into();
+
+        let loaded = Scene::from_json(&json.to_string()).unwrap();
+        assert!(!loaded.aovs);
+        let ids: Vec<(u32, u32)> = loaded.entities().iter().map(|e| (e.id(), e.material_id())).collect();
+        assert_eq!(ids, vec![(0, 0), (1, 0), (2, 0), (3, 1)]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_environment_map_round_trip() {
+        let image = HdrImage {
+            width: 2,
+            height: 2,
+            pixels: vec![
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 4.0, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+                Vec3::new(1.0, 1.0, 1.0),
+            ],
+        };
+        let environment = Environment::image(image, 0.5, 2.0);
+        let json = serde_json::to_string(&environment).unwrap();
+        let loaded: Environment = serde_json::from_str(&json).unwrap();
+
+        let direction = Vec3::new(0.3, -0.4, 0.5).normalize();
+        assert_eq!(loaded.radiance(direction), environment.radiance(direction));
+        assert_eq!(loaded.pdf(direction), environment.pdf(direction));
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+    }
+
+    #[test]
+    fn test_add_entity_assigns_ids() {
+        let mut scene = test_scene();
+        let matte = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(0.5, 0.5, 0.5), 0.0, 1.0, 0.0, 1.5);
+        scene.add_entity(Entity::new_sphere(Vec3::zero(), matte, 1.0));
+        scene.add_entity(Entity::new_sphere(Vec3::zero(), glass(), 1.0));
+
+        let ids: Vec<(u32, u32)> = scene.entities().iter().map(|e| (e.id(), e.material_id())).collect();
+        assert_eq!(ids, vec![(0, 0), (1, 0), (2, 0), (3, 1), (4, 0)]);
+    }
+
+    #[test]
+    fn test_material_given_a_power_has_one_id() {
+        let mut scene = test_scene();
+        let glow = glass().with_emission_power(Rgb::new(1.0, 1.0, 1.0), 100.0);
+        scene.add_entity(Entity::new_sphere(Vec3::zero(), glow, 1.0));
+        scene.add_entity(Entity::new_sphere(Vec3::zero(), glow, 2.0));
+
+        let ids: Vec<u32> = scene.entities().iter().map(|e| e.material_id()).collect();
+        assert_eq!(ids[3..], [1, 1]);
     }
 }
@@ -0,0 +1,292 @@
+use std::f32::consts::PI;
+
+use rand::Rng;
+
+use crate::environment::EnvironmentSample;
+use crate::rgb::xyz_to_linear_rgb;
+use crate::vec3::Vec3;
+
+/// Maps luminance in kcd/m² to display units, so a sunlit white surface sits near white at an intensity of 1.
+const SKY_SCALE: f32 = 5.0;
+
+/// Luminance of the sun's disk before it passes through the atmosphere, in kcd/m².
+const SUN_LUMINANCE: f32 = 2.0e6;
+
+/// Apparent angular radius of the sun.
+const SUN_ANGULAR_RADIUS: f32 = 0.004_654;
+
+/// Wavelengths, in micrometres, used to tint the sun for the red, green and blue channels.
+const SUN_WAVELENGTHS: [f32; 3] = [0.68, 0.55, 0.44];
+
+/// Coefficients of the Perez sky luminance distribution.
+#[derive(Copy, Clone, Debug)]
+struct Perez {
+    a: f32,
+    b: f32,
+    c: f32,
+    d: f32,
+    e: f32,
+}
+
+impl Perez {
+    fn eval(&self, cos_theta: f32, gamma: f32) -> f32 {
+        let cos_gamma = gamma.cos();
+        (1.0 + self.a * (self.b / cos_theta).exp())
+            * (1.0 + self.c * (self.d * gamma).exp() + self.e * cos_gamma * cos_gamma)
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SkyParameters {
+    pub sun_elevation: f32,
+    pub sun_azimuth: f32,
+    pub turbidity: f32,
+    pub intensity: f32,
+}
+
+impl From<SkyParameters> for Sky {
+    fn from(p: SkyParameters) -> Self {
+        Sky::new(p.sun_elevation, p.sun_azimuth, p.turbidity, p.intensity)
+    }
+}
+
+impl From<Sky> for SkyParameters {
+    fn from(sky: Sky) -> Self {
+        sky.parameters
+    }
+}
+
+/// Analytic daylight from Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight" (1999).
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "SkyParameters", into = "SkyParameters"))]
+pub struct Sky {
+    parameters: SkyParameters,
+    sun_direction: Vec3,
+    sun_radiance: Vec3,
+    /// Zenith luminance and chromaticity, each already divided by the Perez value at the zenith.
+    zenith: Vec3,
+    perez: [Perez; 3],
+    cos_sun_radius: f32,
+}
+
+impl Sky {
+    /// `sun_elevation` is measured up from the horizon and `sun_azimuth` about the vertical axis from +z, both in
+    /// radians.
+    pub fn new(sun_elevation: f32, sun_azimuth: f32, turbidity: f32, intensity: f32) -> Self {
+        let t = turbidity.clamp(1.7, 10.0);
+        let (sin_el, cos_el) = sun_elevation.sin_cos();
+        let sun_direction = Vec3::new(cos_el * sun_azimuth.sin(), -sin_el, cos_el * sun_azimuth.cos());
+
+        // The model only covers a sun at or above the horizon
+        let theta_s = (PI / 2.0 - sun_elevation).clamp(0.0, PI / 2.0);
+
+        let perez = [
+            Perez {
+                a: 0.1787 * t - 1.4630,
+                b: -0.3554 * t + 0.4275,
+                c: -0.0227 * t + 5.3251,
+                d: 0.1206 * t - 2.5771,
+                e: -0.0670 * t + 0.3703,
+            },
+            Perez {
+                a: -0.0193 * t - 0.2592,
+                b: -0.0665 * t + 0.0008,
+                c: -0.0004 * t + 0.2125,
+                d: -0.0641 * t - 0.8989,
+                e: -0.0033 * t + 0.0452,
+            },
+            Perez {
+                a: -0.0167 * t - 0.2608,
+                b: -0.0950 * t + 0.0092,
+                c: -0.0079 * t + 0.2102,
+                d: -0.0441 * t - 1.6537,
+                e: -0.0109 * t + 0.0529,
+            },
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+
+        let thetas = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+        let turbidities = [t * t, t, 1.0];
+        let chromaticity = |m: [[f32; 4]; 3]| -> f32 {
+            (0..3)
+                .map(|i| turbidities[i] * (0..4).map(|j| m[i][j] * thetas[j]).sum::<f32>())
+                .sum()
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let zenith = Vec3::new(
+            zenith_luminance * intensity * SKY_SCALE / perez[0].eval(1.0, theta_s),
+            zenith_x / perez[1].eval(1.0, theta_s),
+            zenith_y / perez[2].eval(1.0, theta_s),
+        );
+
+        let sun_radiance = if sun_elevation > 0.0 {
+            Self::sun_transmittance(theta_s, t) * (SUN_LUMINANCE * intensity * SKY_SCALE)
+        } else {
+            Vec3::zero()
+        };
+
+        Self {
+            parameters: SkyParameters {
+                sun_elevation,
+                sun_azimuth,
+                turbidity,
+                intensity,
+            },
+            sun_direction,
+            sun_radiance,
+            zenith,
+            perez,
+            cos_sun_radius: SUN_ANGULAR_RADIUS.cos(),
+        }
+    }
+
+    /// Rayleigh and aerosol extinction along the path to the sun, per colour channel.
+    fn sun_transmittance(theta_s: f32, turbidity: f32) -> Vec3 {
+        let zenith_degrees = theta_s.to_degrees();
+        let optical_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - zenith_degrees).powf(-1.253));
+        let beta = 0.04608 * turbidity - 0.04586;
+
+        let [r, g, b] = SUN_WAVELENGTHS.map(|lambda| {
+            let rayleigh = -0.008735 * lambda.powf(-4.08) * optical_mass;
+            let aerosol = -beta * lambda.powf(-1.3) * optical_mass;
+            (rayleigh + aerosol).exp()
+        });
+        Vec3::new(r, g, b)
+    }
+
+    fn sky_radiance(&self, direction: Vec3) -> Vec3 {
+        // Directions below the horizon reuse the horizon colour
+        let cos_theta = (-direction.y).max(0.01);
+        let gamma = direction.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let luminance = self.zenith.x * self.perez[0].eval(cos_theta, gamma);
+        let x = self.zenith.y * self.perez[1].eval(cos_theta, gamma);
+        let y = self.zenith.z * self.perez[2].eval(cos_theta, gamma);
+        if luminance <= 0.0 || y <= 0.0 {
+            return Vec3::zero();
+        }
+
+        xyz_to_linear_rgb(Vec3::new(x / y * luminance, luminance, (1.0 - x - y) / y * luminance))
+    }
+
+    fn in_sun(&self, direction: Vec3) -> bool {
+        direction.dot(self.sun_direction) >= self.cos_sun_radius
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        if self.in_sun(direction) {
+            self.sky_radiance(direction) + self.sun_radiance
+        } else {
+            self.sky_radiance(direction)
+        }
+    }
+
+    /// Samples the sun's disk like a directional light with a small cone; the sky itself is left to the BSDF.
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<EnvironmentSample> {
+        if self.sun_radiance == Vec3::zero() {
+            return None;
+        }
+
+        let cos_theta = 1.0 - rng.gen::<f32>() * (1.0 - self.cos_sun_radius);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen::<f32>();
+        let (tangent, bitangent) = self.sun_direction.orthonormal_basis();
+        let direction =
+            (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + self.sun_direction * cos_theta)
+                .normalize();
+
+        Some(EnvironmentSample {
+            direction,
+            radiance: self.radiance(direction),
+            pdf: self.cone_pdf(),
+        })
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        if self.sun_radiance != Vec3::zero() && self.in_sun(direction) {
+            self.cone_pdf()
+        } else {
+            0.0
+        }
+    }
+
+    fn cone_pdf(&self) -> f32 {
+        1.0 / (2.0 * PI * (1.0 - self.cos_sun_radius))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    const UP: Vec3 = Vec3 {
+        x: 0.0,
+        y: -1.0,
+        z: 0.0,
+    };
+
+    #[test]
+    fn test_clear_sky_is_blue() {
+        let sky = Sky::new(1.0, 0.0, 2.5, 1.0);
+        let zenith = sky.radiance(UP);
+        assert!(zenith.z > zenith.x, "zenith was {}", zenith);
+    }
+
+    #[test]
+    fn test_sky_brightens_towards_sun() {
+        let sky = Sky::new(0.3, 0.0, 3.0, 1.0);
+        let towards = sky.radiance(Vec3::new(0.0, -0.5, 1.0).normalize());
+        let away = sky.radiance(Vec3::new(0.0, -0.5, -1.0).normalize());
+        assert!(towards.luminance() > away.luminance());
+    }
+
+    #[test]
+    fn test_sun_is_much_brighter_than_sky() {
+        let sky = Sky::new(0.8, 1.0, 2.0, 1.0);
+        let sun = sky.radiance(sky.sun_direction);
+        assert!(sun.luminance() > sky.radiance(UP).luminance() * 1000.0);
+    }
+
+    #[test]
+    fn test_low_sun_is_redder() {
+        let noon = Sky::new(1.4, 0.0, 3.0, 1.0).sun_radiance;
+        let dusk = Sky::new(0.05, 0.0, 3.0, 1.0).sun_radiance;
+        assert!(dusk.x / dusk.z > noon.x / noon.z);
+    }
+
+    #[test]
+    fn test_sun_samples_land_in_disk() {
+        let sky = Sky::new(0.5, 2.0, 3.0, 1.0);
+        let mut rng = SmallRng::seed_from_u64(5);
+        for _ in 0..100 {
+            let sample = sky.sample(&mut rng).unwrap();
+            assert!(sky.in_sun(sample.direction));
+            assert_eq!(sample.pdf, sky.pdf(sample.direction));
+        }
+        assert_eq!(sky.pdf(UP), 0.0);
+    }
+
+    #[test]
+    fn test_sun_below_horizon_is_not_sampled() {
+        let sky = Sky::new(-0.2, 0.0, 3.0, 1.0);
+        let mut rng = SmallRng::seed_from_u64(5);
+        assert!(sky.sample(&mut rng).is_none());
+        assert_eq!(sky.pdf(sky.sun_direction), 0.0);
+    }
+}
@@ -0,0 +1,241 @@
+use std::sync::OnceLock;
+
+use crate::rgb::xyz_to_rgb;
+use crate::vec3::Vec3;
+
+/// The range of wavelengths traced in spectral mode, in nanometres.
+pub const LAMBDA_MIN: f32 = 380.0;
+pub const LAMBDA_MAX: f32 = 780.0;
+
+/// Wavelengths traced together by each path: one hero and the others spaced evenly around the range from it.
+const WAVELENGTHS: usize = 3;
+
+fn lobe(lambda: f32, mean: f32, below: f32, above: f32) -> f32 {
+    let sigma = if lambda < mean { below } else { above };
+    let t = (lambda - mean) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+/// The CIE 1931 standard observer, using the multi-lobe Gaussian fit from Wyman, Sloan and Shirley, "Simple
+/// Analytic Approximations to the CIE XYZ Color Matching Functions" (2013).
+pub fn cie_xyz(lambda: f32) -> Vec3 {
+    Vec3::new(
+        1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    )
+}
+
+/// Planck's second radiation constant, in micrometre kelvins.
+const PLANCK_C2: f32 = 14_388.0;
+
+/// Spectral radiance of a blackbody at `kelvin`, up to a constant factor.
+fn planck(lambda: f32, kelvin: f32) -> f32 {
+    let micrometres = lambda / 1000.0;
+    1.0 / (micrometres.powi(5) * ((PLANCK_C2 / (micrometres * kelvin)).exp() - 1.0))
+}
+
+/// The linear RGB colour of a blackbody at `kelvin`, scaled to a luminance of one. Hot objects run from the deep
+/// red of embers at 1000 K through candlelight at 1900 K and tungsten bulbs around 2700 K to white near 6500 K
+/// and blue above it. Temperatures are kept to between 1000 K and 40000 K.
+pub fn blackbody(kelvin: f32) -> Vec3 {
+    let kelvin = kelvin.clamp(1000.0, 40_000.0);
+    let xyz = integrate(|lambda| cie_xyz(lambda) * planck(lambda, kelvin));
+    let rgb = xyz_to_rgb(xyz).max(Vec3::zero());
+    rgb / rgb.luminance()
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Three smooth spectra, blue, green and red, that sum to one everywhere. RGB colours are upsampled to a mix of
+/// them, so white always becomes a flat spectrum.
+fn basis(lambda: f32) -> Vec3 {
+    let blue = 1.0 - smoothstep(440.0, 520.0, lambda);
+    let red = smoothstep(560.0, 620.0, lambda);
+    Vec3::new(red, 1.0 - red - blue, blue)
+}
+
+/// Sums `f` over the traced range in 1 nm steps.
+fn integrate(f: impl Fn(f32) -> Vec3) -> Vec3 {
+    let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+    (0..steps)
+        .map(|i| f(LAMBDA_MIN + i as f32 + 0.5))
+        .fold(Vec3::zero(), |acc, v| acc + v)
+}
+
+/// Inverse of the matrix with columns `a`, `b` and `c`, as its rows.
+fn invert(a: Vec3, b: Vec3, c: Vec3) -> [Vec3; 3] {
+    let det = a.dot(b.cross(c));
+    [b.cross(c) / det, c.cross(a) / det, a.cross(b) / det]
+}
+
+struct Tables {
+    /// Divides the linear RGB response so each channel integrates to one, making a flat spectrum white.
+    response_scale: Vec3,
+    /// Takes an RGB colour to the weights of the basis spectra that reproduce it, as rows.
+    rgb_to_basis: [Vec3; 3],
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let response_scale = integrate(|lambda| xyz_to_rgb(cie_xyz(lambda)));
+        let response = |lambda: f32| xyz_to_rgb(cie_xyz(lambda)) / response_scale;
+        // Column j is the colour of basis spectrum j
+        let column = |j: usize| {
+            integrate(|lambda| {
+                let b = basis(lambda);
+                response(lambda) * [b.x, b.y, b.z][j]
+            })
+        };
+        Tables {
+            response_scale,
+            rgb_to_basis: invert(column(0), column(1), column(2)),
+        }
+    })
+}
+
+/// The linear RGB a unit of light at `lambda` contributes, scaled so a flat spectrum comes out white.
+pub fn rgb_response(lambda: f32) -> Vec3 {
+    xyz_to_rgb(cie_xyz(lambda)) / tables().response_scale
+}
+
+/// The wavelengths one path carries, with what it needs to move colours between RGB and spectral values at them.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Wavelengths {
+    lambda: [f32; WAVELENGTHS],
+    /// For each wavelength, the weights on an RGB colour that give its upsampled spectrum there.
+    upsample: [Vec3; WAVELENGTHS],
+    /// Set once something wavelength-dependent, like dispersion, leaves only the hero wavelength on the path.
+    secondary_terminated: bool,
+}
+
+impl Wavelengths {
+    /// Picks a hero wavelength uniformly across the range from `u` in `[0, 1)`, after Wilkie et al., "Hero
+    /// Wavelength Spectral Sampling" (2014).
+    pub fn sample(u: f32) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let lambda: [f32; WAVELENGTHS] =
+            std::array::from_fn(|k| LAMBDA_MIN + ((u + k as f32 / WAVELENGTHS as f32) % 1.0) * range);
+        let [r, g, b] = tables().rgb_to_basis;
+        let upsample = lambda.map(|l| {
+            let basis = basis(l);
+            r * basis.x + g * basis.y + b * basis.z
+        });
+        Self {
+            lambda,
+            upsample,
+            secondary_terminated: false,
+        }
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    /// The upsampled spectrum of an RGB colour at each wavelength, unbounded above as for light.
+    pub fn radiance(&self, rgb: Vec3) -> Vec3 {
+        let [a, b, c] = self.upsample;
+        Vec3::new(a.dot(rgb), b.dot(rgb), c.dot(rgb)).max(Vec3::zero())
+    }
+
+    /// As `radiance`, but capped at one so a surface never reflects more than it receives.
+    pub fn reflectance(&self, rgb: Vec3) -> Vec3 {
+        self.radiance(rgb).min(Vec3::new(1.0, 1.0, 1.0))
+    }
+
+    /// Drops the secondary wavelengths from `throughput`, weighting the hero to make up for them, the first time
+    /// the path meets something whose direction depends on wavelength.
+    pub fn terminate_secondary(&mut self, throughput: Vec3) -> Vec3 {
+        if self.secondary_terminated {
+            return throughput;
+        }
+        self.secondary_terminated = true;
+        Vec3::new(throughput.x * WAVELENGTHS as f32, 0.0, 0.0)
+    }
+
+    /// Converts radiance at each wavelength to linear RGB.
+    pub fn to_rgb(self, values: Vec3) -> Vec3 {
+        // Each wavelength is a uniform sample of the range, so the estimate is averaged and divided by the density
+        let scale = (LAMBDA_MAX - LAMBDA_MIN) / WAVELENGTHS as f32;
+        let [a, b, c] = self.lambda.map(rgb_response);
+        (a * values.x + b * values.y + c * values.z) * scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cie_luminance_peaks_near_555() {
+        assert!((cie_xyz(555.0).y - 1.0).abs() < 0.02);
+        assert!(cie_xyz(450.0).y < 0.1);
+        assert!(cie_xyz(450.0).z > 1.0);
+    }
+
+    #[test]
+    fn test_blackbody_colours() {
+        let ember = blackbody(1500.0);
+        let daylight = blackbody(6500.0);
+        let sky = blackbody(15_000.0);
+        for colour in [ember, daylight, sky] {
+            assert!((colour.luminance() - 1.0).abs() < 1e-4, "{}", colour);
+        }
+        assert!(ember.x > ember.y && ember.y > ember.z, "{}", ember);
+        assert!(sky.z > sky.x, "{}", sky);
+        // Close to the white point, which is D65 at 6504 K
+        assert!((daylight.x / daylight.z - 1.0).abs() < 0.1, "{}", daylight);
+        assert_eq!(blackbody(0.0), blackbody(1000.0));
+    }
+
+    #[test]
+    fn test_white_upsamples_to_flat_spectrum() {
+        for i in 0..20 {
+            let wavelengths = Wavelengths::sample(i as f32 / 20.0);
+            let white = wavelengths.reflectance(Vec3::new(1.0, 1.0, 1.0));
+            assert!((white - Vec3::new(1.0, 1.0, 1.0)).mag() < 1e-3, "{}", white);
+        }
+    }
+
+    #[test]
+    fn test_colours_survive_the_round_trip() {
+        for rgb in [
+            Vec3::new(0.8, 0.4, 0.2),
+            Vec3::new(0.2, 0.5, 0.7),
+            Vec3::new(1.0, 1.0, 1.0),
+        ] {
+            let count = 3000;
+            let mean = (0..count)
+                .map(|i| {
+                    let wavelengths = Wavelengths::sample((i as f32 + 0.5) / count as f32);
+                    wavelengths.to_rgb(wavelengths.radiance(rgb))
+                })
+                .fold(Vec3::zero(), |acc, v| acc + v)
+                / count as f32;
+            assert!((mean - rgb).mag() < 0.02, "{} came back as {}", rgb, mean);
+        }
+    }
+
+    #[test]
+    fn test_hero_wavelengths_are_spread_over_the_range() {
+        let wavelengths = Wavelengths::sample(0.9);
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        assert!((wavelengths.hero() - (LAMBDA_MIN + 0.9 * range)).abs() < 1e-3);
+        for lambda in wavelengths.lambda {
+            assert!((LAMBDA_MIN..LAMBDA_MAX).contains(&lambda));
+        }
+    }
+
+    #[test]
+    fn test_terminating_secondaries_keeps_the_hero() {
+        let mut wavelengths = Wavelengths::sample(0.3);
+        let throughput = wavelengths.terminate_secondary(Vec3::new(0.5, 0.2, 0.1));
+        assert_eq!(throughput, Vec3::new(1.5, 0.0, 0.0));
+        assert_eq!(wavelengths.terminate_secondary(throughput), throughput);
+    }
+}
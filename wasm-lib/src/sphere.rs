@@ -1,8 +1,12 @@
+use std::f32::consts::PI;
+
 use crate::ray::Ray;
 use crate::traceable::Traceable;
+use crate::vec2::Vec2;
 use crate::vec3::Vec3;
 
 #[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Sphere {
     pub radius: f32,
 }
@@ -11,6 +15,16 @@ impl Sphere {
     pub fn new(radius: f32) -> Self {
         Self { radius }
     }
+
+    /// `1 - cos` of the half-angle the sphere subtends from `from`, or `None` from inside it. Written so it stays
+    /// accurate for small, distant spheres.
+    fn one_minus_cos_subtended(&self, position: Vec3, from: Vec3) -> Option<f32> {
+        let ratio = self.radius * self.radius / (position - from).mag_squared();
+        if ratio >= 1.0 {
+            return None;
+        }
+        Some(ratio / (1.0 + (1.0 - ratio).sqrt()))
+    }
 }
 
 impl Traceable for Sphere {
@@ -46,6 +60,53 @@ impl Traceable for Sphere {
 
         Some((t, normal))
     }
+
+    /// Longitude and latitude, with `v = 0` at the top (-y) pole.
+    fn uv(&self, point: Vec3, position: Vec3) -> Vec2 {
+        let d = (point - position).normalize();
+        Vec2::new(0.5 + d.x.atan2(d.z) / (2.0 * PI), (-d.y).clamp(-1.0, 1.0).acos() / PI)
+    }
+
+    /// Around the sphere about the y axis, along lines of latitude.
+    fn tangent(&self, point: Vec3, position: Vec3) -> Vec3 {
+        let d = point - position;
+        Vec3::new(d.z, 0.0, -d.x)
+    }
+
+    fn area(&self) -> f32 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    /// Samples the cone of directions the sphere subtends, so every sample lands on the visible cap.
+    fn sample_towards(&self, position: Vec3, from: Vec3, u1: f32, u2: f32) -> Option<(Vec3, f32)> {
+        let one_minus_cos_max = self.one_minus_cos_subtended(position, from)?;
+        let cos_theta = 1.0 - u1 * one_minus_cos_max;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+
+        let axis = (position - from).normalize();
+        let (tangent, bitangent) = axis.orthonormal_basis();
+        let direction =
+            (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + axis * cos_theta).normalize();
+
+        // Directions right on the silhouette can just miss in f32, in which case fall back to the closest approach
+        let ray = Ray {
+            origin: from,
+            direction,
+        };
+        let t = match self.intersect(ray, position) {
+            Some((t, _)) => t,
+            None => (position - from).dot(direction),
+        };
+        Some((from + direction * t, 1.0 / (2.0 * PI * one_minus_cos_max)))
+    }
+
+    fn pdf_towards(&self, position: Vec3, from: Vec3, _point: Vec3) -> f32 {
+        match self.one_minus_cos_subtended(position, from) {
+            Some(one_minus_cos_max) => 1.0 / (2.0 * PI * one_minus_cos_max),
+            None => 0.0,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -77,4 +138,50 @@ mod tests {
 
         assert!(sphere.intersect(ray, position).is_none());
     }
+
+    #[test]
+    fn test_sphere_tangent_follows_u() {
+        let sphere = Sphere { radius: 2.0 };
+        let position = Vec3::new(0.0, 0.0, 10.0);
+        let point = Vec3::new(0.0, 0.0, 12.0);
+        let tangent = sphere.tangent(point, position).normalize();
+        let step = sphere.uv(point + tangent * 0.01, position).x - sphere.uv(point, position).x;
+        assert!(step > 0.0);
+        assert!(tangent.dot(point - position).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_sphere_uv() {
+        let sphere = Sphere::new(2.0);
+        let position = Vec3::new(0.0, 0.0, 10.0);
+        assert_eq!(sphere.uv(Vec3::new(0.0, -2.0, 10.0), position).y, 0.0);
+        assert!((sphere.uv(Vec3::new(0.0, 2.0, 10.0), position).y - 1.0).abs() < 1e-6);
+        let equator = sphere.uv(Vec3::new(0.0, 0.0, 12.0), position);
+        assert_eq!(equator.x, 0.5);
+        assert!((equator.y - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_sample_towards_lands_on_sphere() {
+        let sphere = Sphere::new(2.0);
+        let position = Vec3::new(1.0, 0.0, 10.0);
+        let from = Vec3::zero();
+        for i in 0..50 {
+            let (point, pdf) = sphere.sample_towards(position, from, i as f32 / 50.0, 0.37).unwrap();
+            assert!(((point - position).mag() - 2.0).abs() < 1e-3);
+            // Only the side facing `from` is sampled
+            assert!((point - position).dot(from - position) > 0.0);
+            assert_eq!(pdf, sphere.pdf_towards(position, from, point));
+        }
+        assert!(sphere.sample_towards(position, position, 0.5, 0.5).is_none());
+    }
+
+    #[test]
+    fn test_subtended_solid_angle() {
+        // Far away the cone's solid angle tends to the projected disc area over distance squared
+        let sphere = Sphere::new(1.0);
+        let pdf = sphere.pdf_towards(Vec3::new(0.0, 0.0, 1000.0), Vec3::zero(), Vec3::zero());
+        let expected = 1000.0 * 1000.0 / PI;
+        assert!((pdf - expected).abs() < expected * 1e-3, "{} vs {}", pdf, expected);
+    }
 }
@@ -0,0 +1,452 @@
+use std::sync::Arc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::noise;
+use crate::rgb::Rgb;
+use crate::vec2::Vec2;
+use crate::vec3::Vec3;
+
+/// Where a texture is looked up on a surface.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TexCoord {
+    pub uv: Vec2,
+    pub point: Vec3,
+    /// `point` relative to the entity's position, so the pattern moves with the object.
+    pub local: Vec3,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "ImageData"))]
+#[derive(Clone, PartialEq, Debug)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    /// Linear RGB, row-major from the top-left.
+    pixels: Arc<Vec<Vec3>>,
+}
+
+/// An image as saved, checked on loading like one decoded from a PNG or JPEG.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct ImageData {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<ImageData> for Image {
+    type Error = &'static str;
+
+    fn try_from(data: ImageData) -> Result<Self, Self::Error> {
+        Image::new(data.width, data.height, data.pixels)
+    }
+}
+
+impl Image {
+    /// Builds an image from 8-bit sRGB-encoded RGBA, as produced by decoding a PNG or JPEG.
+    pub fn from_rgba8(width: usize, height: usize, rgba: &[u8]) -> Result<Self, &'static str> {
+        Self::decode(width, height, rgba, |p| {
+            Vec3::new(srgb_to_linear(p[0]), srgb_to_linear(p[1]), srgb_to_linear(p[2]))
+        })
+    }
+
+    /// As `from_rgba8`, for images holding data such as normals or heights, which aren't sRGB-encoded.
+    pub fn from_rgba8_data(width: usize, height: usize, rgba: &[u8]) -> Result<Self, &'static str> {
+        Self::decode(width, height, rgba, |p| {
+            Vec3::new(unorm(p[0]), unorm(p[1]), unorm(p[2]))
+        })
+    }
+
+    /// Builds a greyscale image from the alpha channel of 8-bit RGBA, for masks.
+    pub fn from_rgba8_alpha(width: usize, height: usize, rgba: &[u8]) -> Result<Self, &'static str> {
+        Self::decode(width, height, rgba, |p| Vec3::new(1.0, 1.0, 1.0) * unorm(p[3]))
+    }
+
+    fn decode(width: usize, height: usize, rgba: &[u8], pixel: impl Fn(&[u8]) -> Vec3) -> Result<Self, &'static str> {
+        if rgba.len() % 4 != 0 {
+            return Err("pixel data does not match the image size");
+        }
+        Self::new(width, height, rgba.chunks_exact(4).map(pixel).collect())
+    }
+
+    fn new(width: usize, height: usize, pixels: Vec<Vec3>) -> Result<Self, &'static str> {
+        if width == 0 || height == 0 {
+            return Err("image must not be empty");
+        }
+        if width.checked_mul(height) != Some(pixels.len()) {
+            return Err("pixel data does not match the image size");
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pixels: Arc::new(pixels),
+        })
+    }
+
+    fn texel(&self, x: isize, y: isize) -> Vec3 {
+        let x = x.rem_euclid(self.width as isize) as usize;
+        let y = y.rem_euclid(self.height as isize) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    /// Bilinearly filtered lookup that wraps in both directions, with `v = 0` along the top row.
+    pub fn sample(&self, uv: Vec2) -> Vec3 {
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = uv.y * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+
+        let top = Vec3::lerp(self.texel(x0, y0), self.texel(x0 + 1, y0), fx);
+        let bottom = Vec3::lerp(self.texel(x0, y0 + 1), self.texel(x0 + 1, y0 + 1), fx);
+        Vec3::lerp(top, bottom, fy)
+    }
+}
+
+fn unorm(value: u8) -> f32 {
+    value as f32 / 255.0
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let c = value as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Which scalar field a noise texture blends its two colours by, each mapped onto `[0, 1]`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+enum NoiseKind {
+    #[default]
+    Value,
+    Perlin,
+    Fbm {
+        octaves: u32,
+    },
+    Turbulence {
+        octaves: u32,
+    },
+    /// Distance to the nearest cell feature point.
+    Worley,
+    Marble,
+    Wood,
+}
+
+impl NoiseKind {
+    fn eval(self, p: Vec3) -> f32 {
+        let t = match self {
+            NoiseKind::Value => noise::value(p),
+            NoiseKind::Perlin => 0.5 + 0.5 * noise::perlin(p),
+            NoiseKind::Fbm { octaves } => 0.5 + 0.5 * noise::fbm(p, octaves),
+            NoiseKind::Turbulence { octaves } => noise::turbulence(p, octaves),
+            NoiseKind::Worley => noise::worley(p).0,
+            NoiseKind::Marble => noise::marble(p),
+            NoiseKind::Wood => noise::wood(p),
+        };
+        t.clamp(0.0, 1.0)
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Debug)]
+enum Pattern {
+    Constant(Vec3),
+    Image(Image),
+    /// Alternating squares, `scale` of them per unit of UV.
+    Checker {
+        even: Vec3,
+        odd: Vec3,
+        scale: f32,
+    },
+    /// Blends from top to bottom along `v`.
+    Gradient {
+        from: Vec3,
+        to: Vec3,
+    },
+    /// A noise field over space, `scale` cells per unit, needing no UVs.
+    Noise {
+        low: Vec3,
+        high: Vec3,
+        scale: f32,
+        #[cfg_attr(feature = "serde", serde(default))]
+        kind: NoiseKind,
+    },
+}
+
+#[wasm_bindgen]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Debug)]
+pub struct Texture {
+    pattern: Pattern,
+    /// Evaluate spatial patterns relative to the entity rather than in world space.
+    #[cfg_attr(feature = "serde", serde(default))]
+    object_space: bool,
+}
+
+impl Texture {
+    fn new(pattern: Pattern) -> Self {
+        Self {
+            pattern,
+            object_space: false,
+        }
+    }
+
+    fn noise_of(low: Rgb, high: Rgb, scale: f32, kind: NoiseKind) -> Self {
+        Self::new(Pattern::Noise {
+            low: Vec3::from(low),
+            high: Vec3::from(high),
+            scale,
+            kind,
+        })
+    }
+
+    pub fn sample(&self, at: TexCoord) -> Vec3 {
+        let point = if self.object_space { at.local } else { at.point };
+        match &self.pattern {
+            Pattern::Constant(colour) => *colour,
+            Pattern::Image(image) => image.sample(at.uv),
+            Pattern::Checker { even, odd, scale } => {
+                let cell = (at.uv.x * scale).floor() as i64 + (at.uv.y * scale).floor() as i64;
+                if cell.rem_euclid(2) == 0 {
+                    *even
+                } else {
+                    *odd
+                }
+            }
+            Pattern::Gradient { from, to } => Vec3::lerp(*from, *to, at.uv.y.clamp(0.0, 1.0)),
+            Pattern::Noise { low, high, scale, kind } => Vec3::lerp(*low, *high, kind.eval(point * *scale)),
+        }
+    }
+
+    /// Scalar material channels read the texture's red channel.
+    pub fn sample_scalar(&self, at: TexCoord) -> f32 {
+        self.sample(at).x
+    }
+}
+
+#[wasm_bindgen]
+impl Texture {
+    pub fn constant(colour: Rgb) -> Self {
+        Self::new(Pattern::Constant(Vec3::from(colour)))
+    }
+
+    /// Wraps decoded 8-bit sRGB RGBA pixels, e.g. from `getImageData` on a PNG or JPEG.
+    pub fn image(width: u32, height: u32, rgba: &[u8]) -> Result<Texture, JsError> {
+        let image = Image::from_rgba8(width as usize, height as usize, rgba).map_err(JsError::new)?;
+        Ok(Self::new(Pattern::Image(image)))
+    }
+
+    /// Wraps the alpha channel of 8-bit RGBA pixels as a greyscale texture, e.g. for `Material::with_opacity`
+    /// from a PNG with transparency.
+    pub fn alpha_image(width: u32, height: u32, rgba: &[u8]) -> Result<Texture, JsError> {
+        let image = Image::from_rgba8_alpha(width as usize, height as usize, rgba).map_err(JsError::new)?;
+        Ok(Self::new(Pattern::Image(image)))
+    }
+
+    /// Wraps 8-bit RGBA pixels that hold data rather than colour, like a normal or height map, so they're read
+    /// as they are without sRGB decoding.
+    pub fn data_image(width: u32, height: u32, rgba: &[u8]) -> Result<Texture, JsError> {
+        let image = Image::from_rgba8_data(width as usize, height as usize, rgba).map_err(JsError::new)?;
+        Ok(Self::new(Pattern::Image(image)))
+    }
+
+    pub fn checker(even: Rgb, odd: Rgb, scale: f32) -> Self {
+        Self::new(Pattern::Checker {
+            even: Vec3::from(even),
+            odd: Vec3::from(odd),
+            scale,
+        })
+    }
+
+    pub fn gradient(from: Rgb, to: Rgb) -> Self {
+        Self::new(Pattern::Gradient {
+            from: Vec3::from(from),
+            to: Vec3::from(to),
+        })
+    }
+
+    /// Smooth value noise.
+    pub fn noise(low: Rgb, high: Rgb, scale: f32) -> Self {
+        Self::noise_of(low, high, scale, NoiseKind::Value)
+    }
+
+    pub fn perlin(low: Rgb, high: Rgb, scale: f32) -> Self {
+        Self::noise_of(low, high, scale, NoiseKind::Perlin)
+    }
+
+    pub fn fbm(low: Rgb, high: Rgb, scale: f32, octaves: u32) -> Self {
+        Self::noise_of(low, high, scale, NoiseKind::Fbm { octaves })
+    }
+
+    pub fn turbulence(low: Rgb, high: Rgb, scale: f32, octaves: u32) -> Self {
+        Self::noise_of(low, high, scale, NoiseKind::Turbulence { octaves })
+    }
+
+    /// Cellular noise, `low` at each cell's feature point fading to `high` away from it.
+    pub fn worley(low: Rgb, high: Rgb, scale: f32) -> Self {
+        Self::noise_of(low, high, scale, NoiseKind::Worley)
+    }
+
+    /// `low` is the base stone and `high` the veins.
+    pub fn marble(low: Rgb, high: Rgb, scale: f32) -> Self {
+        Self::noise_of(low, high, scale, NoiseKind::Marble)
+    }
+
+    /// Rings around the vertical axis through the entity, so this is usually combined with `in_object_space`.
+    pub fn wood(light: Rgb, dark: Rgb, scale: f32) -> Self {
+        Self::noise_of(light, dark, scale, NoiseKind::Wood)
+    }
+
+    /// Evaluates spatial patterns relative to the entity's position, so they move with it.
+    pub fn in_object_space(self) -> Self {
+        Self {
+            object_space: true,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(u: f32, v: f32) -> TexCoord {
+        TexCoord {
+            uv: Vec2::new(u, v),
+            point: Vec3::new(u, v, 0.0),
+            local: Vec3::new(u, v, 0.0) - 10.0,
+        }
+    }
+
+    fn black() -> Rgb {
+        Rgb::new(0.0, 0.0, 0.0)
+    }
+
+    fn white() -> Rgb {
+        Rgb::new(1.0, 1.0, 1.0)
+    }
+
+    #[test]
+    fn test_constant() {
+        let texture = Texture::constant(Rgb::new(0.1, 0.2, 0.3));
+        assert_eq!(texture.sample(at(0.7, 0.2)), Vec3::new(0.1, 0.2, 0.3));
+        assert_eq!(texture.sample_scalar(at(0.7, 0.2)), 0.1);
+    }
+
+    #[test]
+    fn test_checker() {
+        let texture = Texture::checker(black(), white(), 2.0);
+        assert_eq!(texture.sample(at(0.1, 0.1)), Vec3::zero());
+        assert_eq!(texture.sample(at(0.6, 0.1)), Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(texture.sample(at(0.6, 0.6)), Vec3::zero());
+        assert_eq!(texture.sample(at(-0.1, 0.1)), Vec3::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_gradient() {
+        let texture = Texture::gradient(black(), white());
+        assert_eq!(texture.sample(at(0.3, 0.0)), Vec3::zero());
+        assert_eq!(texture.sample(at(0.3, 0.25)), Vec3::new(0.25, 0.25, 0.25));
+        assert_eq!(texture.sample(at(0.3, 2.0)), Vec3::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_noise_is_smooth_and_bounded() {
+        let texture = Texture::noise(black(), white(), 4.0);
+        let mut previous = texture.sample(at(0.0, 0.3)).x;
+        for i in 1..200 {
+            let value = texture.sample(at(i as f32 * 0.001, 0.3)).x;
+            assert!((0.0..1.0).contains(&value));
+            assert!(
+                (value - previous).abs() < 0.05,
+                "jumped from {} to {} at {}",
+                previous,
+                value,
+                i
+            );
+            previous = value;
+        }
+    }
+
+    #[test]
+    fn test_srgb_to_linear() {
+        assert_eq!(srgb_to_linear(0), 0.0);
+        assert_eq!(srgb_to_linear(255), 1.0);
+        assert!((srgb_to_linear(188) - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_image_sampling() {
+        // 2x1: black then white
+        let image = Image::from_rgba8(2, 1, &[0, 0, 0, 255, 255, 255, 255, 255]).unwrap();
+        assert_eq!(image.sample(Vec2::new(0.25, 0.5)), Vec3::zero());
+        assert_eq!(image.sample(Vec2::new(0.75, 0.5)), Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(image.sample(Vec2::new(0.5, 0.5)), Vec3::new(0.5, 0.5, 0.5));
+        // wraps around the right edge back to black
+        assert_eq!(image.sample(Vec2::new(1.25, 0.5)), Vec3::zero());
+    }
+
+    #[test]
+    fn test_data_images_are_not_decoded() {
+        let image = Image::from_rgba8_data(1, 1, &[128, 255, 0, 255]).unwrap();
+        assert_eq!(image.sample(Vec2::new(0.5, 0.5)), Vec3::new(128.0 / 255.0, 1.0, 0.0));
+        let alpha = Image::from_rgba8_alpha(1, 1, &[128, 255, 0, 51]).unwrap();
+        assert_eq!(alpha.sample(Vec2::new(0.5, 0.5)), Vec3::new(0.2, 0.2, 0.2));
+    }
+
+    #[test]
+    fn test_image_rejects_bad_sizes() {
+        assert!(Image::from_rgba8(2, 2, &[0; 4]).is_err());
+        assert!(Image::from_rgba8(0, 0, &[]).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_loaded_image_is_checked() {
+        let image = Image::from_rgba8(2, 1, &[0, 0, 0, 255, 255, 255, 255, 255]).unwrap();
+        let json = serde_json::to_string(&image).unwrap();
+        assert_eq!(serde_json::from_str::<Image>(&json).unwrap(), image);
+        let pixel = r#"{"x":1.0,"y":1.0,"z":1.0}"#;
+        let empty = format!(r#"{{"width":0,"height":1,"pixels":[{}]}}"#, pixel);
+        assert!(serde_json::from_str::<Image>(&empty).is_err());
+        let short = format!(r#"{{"width":2,"height":2,"pixels":[{}]}}"#, pixel);
+        assert!(serde_json::from_str::<Image>(&short).is_err());
+    }
+
+    #[test]
+    fn test_noise_kinds_are_bounded() {
+        let textures = [
+            Texture::perlin(black(), white(), 3.0),
+            Texture::fbm(black(), white(), 3.0, 5),
+            Texture::turbulence(black(), white(), 3.0, 5),
+            Texture::worley(black(), white(), 3.0),
+            Texture::marble(black(), white(), 3.0),
+            Texture::wood(black(), white(), 3.0),
+        ];
+        for texture in textures {
+            for i in 0..100 {
+                let value = texture.sample(at(i as f32 * 0.37, i as f32 * 0.11)).x;
+                assert!((0.0..=1.0).contains(&value), "{:?} gave {}", texture, value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_object_space() {
+        let world = Texture::perlin(black(), white(), 1.0);
+        let object = world.clone().in_object_space();
+        let at = TexCoord {
+            uv: Vec2::new(0.0, 0.0),
+            point: Vec3::new(10.3, 4.6, 2.2),
+            local: Vec3::new(0.3, 0.6, 0.2),
+        };
+        assert_eq!(object.sample(at), world.sample(TexCoord { point: at.local, ..at }));
+        assert_ne!(object.sample(at), world.sample(at));
+    }
+}
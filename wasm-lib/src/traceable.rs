@@ -1,6 +1,17 @@
-use crate::{ray::Ray, vec3::Vec3};
+use crate::{ray::Ray, vec2::Vec2, vec3::Vec3};
 
 pub trait Traceable {
     fn bounds(&self, position: Vec3) -> Result<(Vec3, Vec3), &'static str>;
     fn intersect(&self, ray: Ray, position: Vec3) -> Option<(f32, Vec3)>;
+    fn uv(&self, point: Vec3, position: Vec3) -> Vec2;
+    /// The direction `u` increases in across the surface at `point`, which anisotropic materials stretch their
+    /// highlights along. Need not be normalised, and may be zero where `u` is degenerate.
+    fn tangent(&self, point: Vec3, position: Vec3) -> Vec3;
+    /// Surface area, which weights how often an emissive shape is picked as a light. Infinite for unbounded shapes.
+    fn area(&self) -> f32;
+    /// Picks a point on the shape as seen from `from`, returning it with the solid angle density of its direction.
+    /// `None` if the shape can't be sampled from there.
+    fn sample_towards(&self, position: Vec3, from: Vec3, u1: f32, u2: f32) -> Option<(Vec3, f32)>;
+    /// The solid angle density of `sample_towards` picking `point` from `from`.
+    fn pdf_towards(&self, position: Vec3, from: Vec3, point: Vec3) -> f32;
 }
@@ -1,129 +1,594 @@
 use rand::Rng;
 
+use crate::aov::FirstHit;
+use crate::bsdf::{reflect, Bsdf, Frame};
 use crate::bvh::Tree;
-use crate::entity::Entity;
 use crate::intersection::Intersection;
+use crate::material::Surface;
+use crate::media::{FreeFlight, HenyeyGreenstein, Interior, Medium, MediumStack};
 use crate::ray::Ray;
+use crate::spectrum::Wavelengths;
+use crate::texture::Texture;
 use crate::vec3::Vec3;
+use crate::world::World;
 
-pub fn find_intersection(ray: Ray, bvh_tree: &Tree) -> Option<Intersection> {
-    bvh_tree.find_intersection(ray)
+pub fn find_intersection(ray: Ray, bvh_tree: &Tree, textures: &[Texture], rng: &mut impl Rng) -> Option<Intersection> {
+    bvh_tree.find_intersection(ray, textures, rng)
 }
 
-fn sky_color(ray: Ray) -> Vec3 {
-    let t = (0.5 * (ray.direction.y + ray.direction.x + 1.0)).clamp(0.0, 1.0);
-    (Vec3::new(1.0, 1.0, 1.0) * (1.0 - t) + Vec3::new(0.5, 0.7, 1.0) * t) * 175.0
+/// Power heuristic weight for a sample drawn from the strategy with density `pdf_a`.
+pub fn power_heuristic(pdf_a: f32, pdf_b: f32) -> f32 {
+    let a = pdf_a * pdf_a;
+    let b = pdf_b * pdf_b;
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+/// Bounces every path takes before Russian roulette can end it.
+const ROULETTE_DEPTH: u32 = 3;
+
+/// Scattering events a subsurface random walk can take before it is given up on. They don't count against a
+/// path's bounces, as light typically scatters dozens of times inside skin or wax before it gets back out.
+const MAX_WALK_STEPS: u32 = 256;
+
+/// Everything carried from one bounce of a path to the next.
+struct PathState {
+    ray: Ray,
+    /// Bounces left before the path is cut off.
+    steps: u32,
+    /// Scattering events so far in the subsurface random walk the path is on, if it is on one.
+    walk: u32,
+    depth: u32,
+    /// How much of the light found from here on reaches the camera.
+    throughput: Vec3,
+    /// Light gathered so far, already weighted by the throughput it was found with.
+    radiance: Vec3,
+    /// The density `ray` was sampled with when it came off a non-specular lobe, which is what light and
+    /// environment sampling compete with under MIS.
+    bsdf_pdf: Option<f32>,
+    /// Where the path last scattered, which is where light sampling drew its competing sample from. Crossing a
+    /// boundary that changes nothing moves `ray` on but leaves this behind.
+    vertex: Vec3,
+    /// The transmissive objects `ray` is travelling inside.
+    media: MediumStack,
+    /// In spectral mode, the wavelengths the path carries in place of red, green and blue.
+    wavelengths: Option<Wavelengths>,
 }
 
-pub fn trace(ray: Ray, bvh_tree: &Tree, steps: u32, rng: &mut impl Rng) -> Vec3 {
-    if steps == 0 {
-        return Vec3::new(0.0, 0.0, 0.0);
+impl PathState {
+    fn new(ray: Ray, steps: u32, wavelengths: Option<Wavelengths>) -> Self {
+        Self {
+            ray,
+            steps,
+            walk: 0,
+            depth: 0,
+            throughput: Vec3::new(1.0, 1.0, 1.0),
+            radiance: Vec3::zero(),
+            bsdf_pdf: None,
+            vertex: ray.origin,
+            media: MediumStack::empty(),
+            wavelengths,
+        }
     }
+}
+
+/// Chance of a path carrying on after its next bounce. Below `ROULETTE_DEPTH` every path survives, after that
+/// dim paths are likely to be ended so the time goes on the ones that matter.
+fn survival_probability(path: &PathState) -> f32 {
+    if path.depth < ROULETTE_DEPTH {
+        return 1.0;
+    }
+    let t = path.throughput;
+    t.x.max(t.y).max(t.z).min(1.0)
+}
 
-    match find_intersection(ray, bvh_tree) {
-        Some(intersection) => {
-            let entity: Entity = intersection.entity.unwrap();
-            let material = entity.material();
-            let emitted = Vec3::from(material.emission);
+/// Traces a camera ray, also returning what it hit first for the AOV buffers.
+pub fn trace(ray: Ray, world: &World, steps: u32, rng: &mut impl Rng) -> (Vec3, Option<FirstHit>) {
+    let mut path = PathState::new(ray, steps, world.spectral.then(|| Wavelengths::sample(rng.gen())));
+    let mut first_hit = None;
 
-            let mut normal = intersection.normal;
-            let mut ni_over_nt = 1.0 / material.ior;
-            let mut cos_theta = (ray.direction * -1.0).dot(normal);
+    while path.steps > 0 {
+        let hit = find_intersection(path.ray, &world.bvh, &world.textures, rng);
+        if path.depth == 0 && first_hit.is_none() {
+            first_hit = hit
+                .as_ref()
+                .map(|h| FirstHit::new(path.ray, h, &h.surface(&world.textures)));
+        }
 
-            if cos_theta < 0.0 {
-                // Ray is inside the object, flip normal and IOR
-                normal = normal * -1.0;
-                cos_theta = -cos_theta;
-                ni_over_nt = material.ior;
-            } else {
-                cos_theta = cos_theta.min(1.0);
+        // The ray may scatter in whatever it is travelling through before it gets to the surface
+        let speed = path.ray.direction.mag();
+        let reach = hit.as_ref().map_or(f32::INFINITY, |h| h.dist * speed);
+        if let Some((medium, near, far)) =
+            segment_medium(&path.media, world, path.ray, reach, path.wavelengths.as_ref())
+        {
+            let flight = fly(&medium, world, path.ray, near, far, rng);
+            path.throughput = path.throughput * flight.weight;
+            if path.throughput == Vec3::zero() {
+                break;
+            }
+            if let Some(distance) = flight.distance {
+                let point = path.ray.origin + path.ray.direction * ((near + distance) / speed);
+                if !scatter_in_medium(&mut path, point, &medium, world, rng) {
+                    break;
+                }
+                continue;
             }
+        }
 
-            let albedo = Vec3::from(material.albedo);
-            let r = rng.gen::<f32>();
+        let Some(intersection) = hit else {
+            let radiance = light(
+                path.wavelengths.as_ref(),
+                world.environment.radiance(path.ray.direction),
+            );
+            let weight = match path.bsdf_pdf {
+                Some(pdf) => power_heuristic(pdf, world.environment.pdf(path.ray.direction)),
+                None => 1.0,
+            };
+            path.radiance += path.throughput * radiance * weight;
+            break;
+        };
 
-            let (bounce_ray, brdf_weight) = if material.transmission > 0.0 {
-                // Handle Dielectric (Glass/Transparent)
-                let reflectance = Vec3::reflectance(cos_theta, ni_over_nt);
+        if !shade(&mut path, &intersection, world, rng) {
+            break;
+        }
+    }
 
-                if r < reflectance {
-                    // Reflection
-                    let reflected = ray.direction.reflect(normal);
-                    let direction = (reflected + Vec3::rng_normal(rng) * material.roughness).normalize();
-                    let origin = intersection.point + normal * 0.001;
-                    (Ray { origin, direction }, Vec3::new(1.0, 1.0, 1.0))
-                } else {
-                    // Refraction (Transmission)
-                    let refracted = Vec3::refract(ray.direction, normal, ni_over_nt);
-                    match refracted {
-                        Some(refracted_dir) => {
-                            let direction = (refracted_dir + Vec3::rng_normal(rng) * material.roughness).normalize();
-                            let origin = intersection.point - normal * 0.001;
-                            (Ray { origin, direction }, albedo)
-                        }
-                        None => {
-                            // Total Internal Reflection
-                            let reflected = ray.direction.reflect(normal);
-                            let direction = (reflected + Vec3::rng_normal(rng) * material.roughness).normalize();
-                            let origin = intersection.point + normal * 0.001;
-                            (Ray { origin, direction }, Vec3::new(1.0, 1.0, 1.0))
-                        }
-                    }
+    let radiance = match path.wavelengths {
+        Some(wavelengths) => wavelengths.to_rgb(path.radiance),
+        None => path.radiance,
+    };
+    (radiance, first_hit)
+}
+
+/// An RGB light value as a path carrying `wavelengths` sees it.
+fn light(wavelengths: Option<&Wavelengths>, rgb: Vec3) -> Vec3 {
+    wavelengths.map_or(rgb, |w| w.radiance(rgb))
+}
+
+/// A surface as a path carrying `wavelengths` sees it.
+fn surface_at(wavelengths: Option<&Wavelengths>, surface: Surface) -> Surface {
+    wavelengths.map_or(surface, |w| surface.at_wavelengths(w))
+}
+
+/// The medium a ray is travelling through, with the stretch of the first `max` of it that the medium covers.
+/// Inside an object its interior decides; out in the open it is the fog, if there is any.
+fn segment_medium(
+    media: &MediumStack,
+    world: &World,
+    ray: Ray,
+    max: f32,
+    wavelengths: Option<&Wavelengths>,
+) -> Option<(Medium, f32, f32)> {
+    match media.current() {
+        Some(interior) => interior.medium.map(|medium| (medium, 0.0, max)),
+        None => world.fog.and_then(|fog| {
+            let medium = wavelengths.map_or(fog.medium, |w| fog.medium.at_wavelengths(w));
+            fog.span(ray, max).map(|(near, far)| (medium, near, far))
+        }),
+    }
+}
+
+/// Samples where along `ray` between `near` and `far` it scatters in `medium`, if it does. Distances are
+/// relative to `near`.
+fn fly(medium: &Medium, world: &World, ray: Ray, near: f32, far: f32, rng: &mut impl Rng) -> FreeFlight {
+    match medium.density().and_then(|handle| world.volumes.get(handle as usize)) {
+        Some(field) => {
+            let direction = ray.direction.normalize();
+            medium.track(field, ray.origin + direction * near, direction, far - near, rng)
+        }
+        None => medium.sample_distance(far - near, rng),
+    }
+}
+
+/// The fraction of light that makes it along `ray` between `near` and `far` through `medium`.
+fn segment_transmittance(medium: &Medium, world: &World, ray: Ray, near: f32, far: f32, rng: &mut impl Rng) -> Vec3 {
+    match medium.density().and_then(|handle| world.volumes.get(handle as usize)) {
+        Some(field) => {
+            let direction = ray.direction.normalize();
+            medium.track_transmittance(field, ray.origin + direction * near, direction, far - near, rng)
+        }
+        None => medium.transmittance(far - near),
+    }
+}
+
+/// Moves the path on to its next bounce along `ray`, then gives Russian roulette its chance to end it. Callers
+/// take the bounce out of whichever budget it counts against.
+fn bounce(path: &mut PathState, ray: Ray, weight: Vec3, pdf: Option<f32>, rng: &mut impl Rng) -> bool {
+    path.depth += 1;
+    path.throughput = path.throughput * weight;
+    // Ending some paths early and boosting the survivors to match keeps the estimate unbiased
+    let survival = survival_probability(path);
+    if rng.gen::<f32>() >= survival {
+        return false;
+    }
+    path.throughput = path.throughput / survival;
+    path.ray = ray;
+    path.bsdf_pdf = pdf;
+    path.vertex = ray.origin;
+    true
+}
+
+/// Gathers the light scattered towards the path at `point` inside `medium` and picks a new direction from the
+/// phase function.
+fn scatter_in_medium(path: &mut PathState, point: Vec3, medium: &Medium, world: &World, rng: &mut impl Rng) -> bool {
+    let wo = path.ray.direction.normalize() * -1.0;
+    let phase = medium.phase();
+
+    if path.media.current().is_some_and(|i| i.subsurface) {
+        // Lights can't be seen through the surface of the object, so light is only gathered once the walk is out
+        path.walk += 1;
+        if path.walk > MAX_WALK_STEPS {
+            return false;
+        }
+    } else {
+        path.steps -= 1;
+        let vertex = Vertex::Medium { phase, wo };
+        let direct = sample_direct(point, &vertex, &path.media, world, path.wavelengths.as_ref(), rng);
+        path.radiance += path.throughput * direct;
+    }
+
+    let direction = phase.sample(wo, rng.gen(), rng.gen());
+    let ray = Ray {
+        origin: point,
+        direction,
+    };
+    // Sampling the phase function exactly leaves a weight of one
+    bounce(
+        path,
+        ray,
+        Vec3::new(1.0, 1.0, 1.0),
+        Some(phase.eval(wo, direction)),
+        rng,
+    )
+}
+
+/// The IOR ratio across an entity's surface, from the media on either side rather than assuming it sits in air.
+fn boundary_eta(media: &MediumStack, entity_id: u32, surface: &Surface, entering: bool) -> f32 {
+    if entering {
+        surface.ior / media.ior()
+    } else if media.contains(entity_id) {
+        media.remove(entity_id).ior() / media.ior()
+    } else {
+        // Leaving something the path never entered, e.g. a camera inside it
+        media.ior() / surface.ior
+    }
+}
+
+/// Gathers the light leaving `intersection` towards the path and picks where the path goes next, returning
+/// whether it carries on.
+fn shade(path: &mut PathState, intersection: &Intersection, world: &World, rng: &mut impl Rng) -> bool {
+    let ray = path.ray;
+    let entity = intersection.entity.unwrap();
+    let material = surface_at(path.wavelengths.as_ref(), intersection.surface(&world.textures));
+
+    // Lights are also sampled directly, so a BSDF sample that happens to hit one only gets its MIS share
+    let emitted = match path.bsdf_pdf {
+        Some(pdf) if material.emission != Vec3::zero() => {
+            let light_pdf = world.lights.pdf(entity.id(), path.vertex, intersection.point);
+            material.emission * power_heuristic(pdf, light_pdf)
+        }
+        _ => material.emission,
+    };
+
+    let mut normal = intersection.normal;
+    let entering = ray.direction.dot(normal) < 0.0;
+    if !entering {
+        normal = normal * -1.0;
+    }
+
+    let media = path.media;
+    let interior = Interior::new(entity.id(), &material);
+    let transmissive = material.is_transmissive();
+    let eta = boundary_eta(&media, entity.id(), &material, entering);
+    if transmissive && (media.is_false_interface(&interior) || material.is_invisible_boundary(eta)) {
+        // The boundary lies inside a higher priority medium or changes nothing, so carry on as if it wasn't there
+        path.ray = Ray {
+            origin: offset(intersection.point, normal, ray.direction),
+            direction: ray.direction,
+        };
+        path.media = media.cross(interior, entering);
+        return true;
+    }
+
+    if material.is_dispersive() {
+        // Refraction only follows the hero wavelength from here, so the others stop contributing
+        if let Some(wavelengths) = &mut path.wavelengths {
+            path.throughput = wavelengths.terminate_secondary(path.throughput);
+        }
+    }
+
+    // Shade with any normal or bump map, but keep the geometric normal for deciding which side rays are on
+    let wo = ray.direction * -1.0;
+    let shading = intersection.shading_normal(&world.textures);
+    let shading = above_horizon(if entering { shading } else { shading * -1.0 }, normal, wo);
+    let frame = Frame::from_normal_and_tangent(shading, intersection.tangent);
+    let bsdf = Bsdf::oriented(&material, frame, wo, eta);
+
+    let direct = if bsdf.has_non_delta() {
+        let vertex = Vertex::Surface {
+            bsdf: &bsdf,
+            normal,
+            interior,
+            entering,
+            transmissive,
+        };
+        sample_direct(
+            intersection.point,
+            &vertex,
+            &media,
+            world,
+            path.wavelengths.as_ref(),
+            rng,
+        )
+    } else {
+        Vec3::zero()
+    };
+    path.radiance += path.throughput * (emitted + direct);
+
+    let Some(sample) = bsdf.sample(rng) else {
+        return false;
+    };
+    if !transmissive && sample.direction.dot(normal) <= 0.0 {
+        // A tilted shading normal reflected the path into the surface
+        return false;
+    }
+
+    if transmissive && sample.direction.dot(normal) < 0.0 {
+        path.media = media.cross(interior, entering);
+    }
+    path.steps -= 1;
+    path.walk = 0;
+    let ray = Ray {
+        origin: offset(intersection.point, normal, sample.direction),
+        direction: sample.direction,
+    };
+    bounce(path, ray, sample.weight, sample.pdf, rng)
+}
+
+/// Somewhere a path scatters: off a surface by its BSDF or inside a medium by its phase function.
+enum Vertex<'a> {
+    Surface {
+        bsdf: &'a Bsdf,
+        normal: Vec3,
+        interior: Interior,
+        entering: bool,
+        transmissive: bool,
+    },
+    Medium {
+        phase: HenyeyGreenstein,
+        wo: Vec3,
+    },
+}
+
+impl Vertex<'_> {
+    /// How much light arriving from `wi` is scattered towards the path, including the cosine term for surfaces.
+    fn eval(&self, wi: Vec3) -> Vec3 {
+        match self {
+            Vertex::Surface {
+                bsdf,
+                normal,
+                transmissive,
+                ..
+            } => {
+                if !transmissive && wi.dot(*normal) <= 0.0 {
+                    // Light from behind an opaque surface can't reach it, however its shading normal is tilted
+                    return Vec3::zero();
                 }
-            } else {
-                // Handle Metallic/Diffuse
-                let f0_dielectric = Vec3::new(0.04, 0.04, 0.04);
-                let f0 = Vec3::lerp(f0_dielectric, albedo, material.metallic);
-                let fresnel = Vec3::fresnel_schlick(f0, cos_theta);
-                let reflectance = ((fresnel.x + fresnel.y + fresnel.z) / 3.0).clamp(0.05, 0.95);
-
-                if r < reflectance {
-                    // Specular Reflection
-                    let reflected = ray.direction.reflect(normal);
-                    let direction = (reflected + Vec3::rng_normal(rng) * material.roughness).normalize();
-                    let origin = intersection.point + normal * 0.001;
-
-                    let specular_color = Vec3::lerp(Vec3::new(1.0, 1.0, 1.0), albedo, material.metallic);
-                    (
-                        Ray { origin, direction },
-                        specular_color * (fresnel * (1.0 / reflectance)),
-                    )
+                bsdf.eval(wi)
+            }
+            Vertex::Medium { phase, wo } => Vec3::new(1.0, 1.0, 1.0) * phase.eval(*wo, wi),
+        }
+    }
+
+    fn pdf(&self, wi: Vec3) -> f32 {
+        match self {
+            Vertex::Surface { bsdf, .. } => bsdf.pdf(wi),
+            Vertex::Medium { phase, wo } => phase.eval(*wo, wi),
+        }
+    }
+
+    /// Where a shadow ray towards `wi` starts from and the media it starts in.
+    fn leave(&self, point: Vec3, wi: Vec3, media: &MediumStack) -> (Vec3, MediumStack) {
+        match self {
+            Vertex::Surface {
+                normal,
+                interior,
+                entering,
+                transmissive,
+                ..
+            } => {
+                let media = if *transmissive && wi.dot(*normal) < 0.0 {
+                    media.cross(*interior, *entering)
                 } else {
-                    // Diffuse Reflection
-                    let diffuse_weight = 1.0 - material.metallic;
-                    if diffuse_weight < 0.001 {
-                        return emitted;
-                    }
-
-                    let direction = (normal + Vec3::rng_hemisphere(normal, rng)).normalize();
-                    let origin = intersection.point + normal * 0.001;
-                    let diffuse_prob = 1.0 - reflectance;
-
-                    let one_minus_fresnel = Vec3::new(1.0, 1.0, 1.0) - fresnel;
-                    (
-                        Ray { origin, direction },
-                        albedo * one_minus_fresnel * (diffuse_weight / diffuse_prob),
-                    )
+                    *media
+                };
+                (offset(point, *normal, wi), media)
+            }
+            Vertex::Medium { .. } => (point, *media),
+        }
+    }
+}
+
+/// How many invisible boundaries a shadow ray will pass through before giving up.
+const MAX_SHADOW_CROSSINGS: u32 = 16;
+
+/// Follows a shadow ray along the unit `direction` for `distance`, through media and boundaries that don't bend
+/// light. Returns the transmittance along the way and the first surface within `distance` that stopped it.
+fn trace_shadow(
+    origin: Vec3,
+    direction: Vec3,
+    distance: f32,
+    media: MediumStack,
+    world: &World,
+    wavelengths: Option<&Wavelengths>,
+    rng: &mut impl Rng,
+) -> (Vec3, Option<Intersection>) {
+    let mut ray = Ray { origin, direction };
+    let mut media = media;
+    let mut remaining = distance;
+    let mut transmittance = Vec3::new(1.0, 1.0, 1.0);
+
+    for _ in 0..MAX_SHADOW_CROSSINGS {
+        let hit = find_intersection(ray, &world.bvh, &world.textures, rng).filter(|h| h.dist < remaining - 0.002);
+        let reach = hit.as_ref().map_or(remaining, |h| h.dist);
+        if let Some((medium, near, far)) = segment_medium(&media, world, ray, reach, wavelengths) {
+            transmittance = transmittance * segment_transmittance(&medium, world, ray, near, far, rng);
+        }
+
+        let Some(hit) = hit else {
+            return (transmittance, None);
+        };
+        let entity = hit.entity.unwrap();
+        let surface = surface_at(wavelengths, hit.surface(&world.textures));
+        let entering = direction.dot(hit.normal) < 0.0;
+        let interior = Interior::new(entity.id(), &surface);
+        let eta = boundary_eta(&media, entity.id(), &surface, entering);
+        let passes =
+            surface.is_transmissive() && (media.is_false_interface(&interior) || surface.is_invisible_boundary(eta));
+        if !passes {
+            return (transmittance, Some(hit));
+        }
+
+        media = media.cross(interior, entering);
+        remaining -= hit.dist;
+        ray.origin = offset(hit.point, hit.normal, direction);
+    }
+
+    (Vec3::zero(), None)
+}
+
+/// Next-event estimation: samples the environment and one emissive entity, each MIS'd against the BSDF or phase
+/// function, and one analytic light.
+fn sample_direct(
+    point: Vec3,
+    vertex: &Vertex,
+    media: &MediumStack,
+    world: &World,
+    wavelengths: Option<&Wavelengths>,
+    rng: &mut impl Rng,
+) -> Vec3 {
+    let mut direct = Vec3::zero();
+
+    if let Some(sample) = world.environment.sample(rng) {
+        let f = vertex.eval(sample.direction);
+        if f != Vec3::zero() {
+            let (origin, media) = vertex.leave(point, sample.direction, media);
+            let (transmittance, blocker) =
+                trace_shadow(origin, sample.direction, f32::INFINITY, media, world, wavelengths, rng);
+            if blocker.is_none() {
+                let weight = power_heuristic(sample.pdf, vertex.pdf(sample.direction));
+                direct += light(wavelengths, sample.radiance) * transmittance * f * (weight / sample.pdf);
+            }
+        }
+    }
+
+    if let Some(sample) = world.lights.sample(point, rng) {
+        let direction = (sample.point - point).normalize();
+        let f = vertex.eval(direction);
+        if f != Vec3::zero() {
+            // The light is visible if it is the first thing the shadow ray stops at, which also gives us the
+            // point's textured emission
+            let (origin, media) = vertex.leave(point, direction, media);
+            let (transmittance, blocker) =
+                trace_shadow(origin, direction, f32::INFINITY, media, world, wavelengths, rng);
+            if let Some(hit) = blocker {
+                if hit.entity.is_some_and(|e| e.id() == sample.entity_id) {
+                    let emission = light(wavelengths, hit.surface(&world.textures).emission);
+                    let weight = power_heuristic(sample.pdf, vertex.pdf(direction));
+                    direct += emission * transmittance * f * (weight / sample.pdf);
                 }
-            };
+            }
+        }
+    }
 
-            let incoming = trace(bounce_ray, bvh_tree, steps - 1, rng);
-            emitted + (incoming * brdf_weight)
+    if let Some(sample) = world.lights.illuminate(point, rng) {
+        let f = vertex.eval(sample.direction);
+        if f != Vec3::zero() {
+            let (origin, media) = vertex.leave(point, sample.direction, media);
+            let (transmittance, blocker) = trace_shadow(
+                origin,
+                sample.direction,
+                sample.distance,
+                media,
+                world,
+                wavelengths,
+                rng,
+            );
+            if blocker.is_none() {
+                direct += light(wavelengths, sample.radiance) * transmittance * f;
+            }
         }
-        _ => sky_color(ray),
+    }
+
+    direct
+}
+
+/// How far above the surface a mirror reflection off a tilted shading normal must leave, as a cosine.
+const MIN_REFLECTION_COS: f32 = 0.01;
+
+/// Turns a shading normal tilted by a normal or bump map back towards the geometric `normal` just far enough that
+/// `wo` mirrors to a direction above the surface, so glossy reflections don't head into it and go black. The
+/// construction is Cycles' `ensure_valid_specular_reflection`: the new normal stays in the plane of `normal` and
+/// the old one, and solving for its height there is a quadratic in its square.
+fn above_horizon(shading: Vec3, normal: Vec3, wo: Vec3) -> Vec3 {
+    let wo_z = wo.dot(normal);
+    // Never ask for a reflection steeper than the path arrived at
+    let least = MIN_REFLECTION_COS.min(0.9 * wo_z);
+    if reflect(wo, shading).dot(normal) >= least {
+        return shading;
+    }
+    let along = shading - normal * shading.dot(normal);
+    if along.mag_squared() < 1e-12 {
+        return normal;
+    }
+    let x = along.normalize();
+    let wo_x = wo.dot(x);
+    let a = wo_x * wo_x + wo_z * wo_z;
+    let b = 2.0 * (a + wo_z * least);
+    let c = (least + wo_z) * (least + wo_z);
+    let z2 = (0.25 * (b + (b * b - 4.0 * a * c).max(0.0).sqrt()) / a).clamp(0.0, 1.0);
+    x * (1.0 - z2).sqrt() + normal * z2.sqrt()
+}
+
+/// Nudges a ray's origin off the surface on the side it is leaving towards.
+fn offset(point: Vec3, normal: Vec3, direction: Vec3) -> Vec3 {
+    if direction.dot(normal) >= 0.0 {
+        point + normal * 0.001
+    } else {
+        point - normal * 0.001
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::camera::Camera;
+    use crate::entity::Entity;
+    use crate::environment::{Environment, HDR_SCALE};
+    use crate::hdr::HdrImage;
+    use crate::light::Light;
     use crate::material::Material;
+    use crate::media::Fog;
     use crate::rgb::Rgb;
+    use crate::scene::Scene;
+    use crate::volume::DensityField;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+    use std::f32::consts::PI;
 
     fn test_material() -> Material {
         Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 0.0, 0.0, 1.5)
     }
 
+    /// Averages `count` paths traced along `ray`, with the generator seeded from `seed`.
+    fn mean_radiance(ray: Ray, world: &World, steps: u32, count: u32, seed: u64) -> Vec3 {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        (0..count).fold(Vec3::zero(), |acc, _| acc + trace(ray, world, steps, &mut rng).0) / count as f32
+    }
+
     #[test]
     fn test_find_intersection() {
         let sphere1 = Entity::new_sphere(Vec3::new(0.0, 0.0, 10.0), test_material(), 2.0);
@@ -136,8 +601,608 @@ mod tests {
             direction: Vec3::new(0.0, 0.0, 1.0),
         };
 
-        let intersection = find_intersection(ray, &tree).unwrap();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let intersection = find_intersection(ray, &tree, &[], &mut rng).unwrap();
         assert_eq!(intersection.dist, 4.0);
         assert_eq!(intersection.point, Vec3::new(0.0, 0.0, 4.0));
     }
+
+    #[test]
+    fn test_cut_out_surfaces_are_passed_through() {
+        let masked = test_material().with_opacity(1.0, Some(0));
+        let front = Entity::new_sphere(Vec3::new(0.0, 0.0, 5.0), masked, 1.0);
+        let back = Entity::new_sphere(Vec3::new(0.0, 0.0, 10.0), test_material(), 2.0);
+        let tree = Tree::build(&[front, back]);
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let solid = [Texture::constant(Rgb::new(1.0, 1.0, 1.0))];
+        assert_eq!(find_intersection(ray, &tree, &solid, &mut rng).unwrap().dist, 4.0);
+        let clear = [Texture::constant(Rgb::new(0.0, 0.0, 0.0))];
+        assert_eq!(find_intersection(ray, &tree, &clear, &mut rng).unwrap().dist, 8.0);
+    }
+
+    #[test]
+    fn test_partial_opacity_blends_with_what_is_behind() {
+        let material = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(0.0, 0.0, 0.0), 0.0, 1.0, 0.0, 1.0);
+        let screen = Entity::new_plane(
+            Vec3::new(0.0, 0.0, 5.0),
+            material.with_opacity(0.25, None),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+        let world = World::new(&[screen], uniform_environment(1.0), vec![]);
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+
+        let mean = mean_radiance(ray, &world, 2, 20_000, 3);
+        assert!((mean.x - 0.75 * 255.0).abs() < 255.0 * 0.03, "mean was {}", mean);
+    }
+
+    #[test]
+    fn test_partial_opacity_casts_a_partial_shadow() {
+        let floor = Entity::new_plane(Vec3::zero(), test_material(), Vec3::new(0.0, -1.0, 0.0)).with_ids(0, 0);
+        let material = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(0.0, 0.0, 0.0), 0.0, 1.0, 0.0, 1.0);
+        let material = material.with_opacity(0.5, None);
+        let corner = |x: f32, z: f32| Vec3::new(x, -5.0, z);
+        let screen = [
+            Entity::new_triangle(
+                Vec3::zero(),
+                corner(-2.0, -2.0),
+                corner(2.0, -2.0),
+                corner(2.0, 2.0),
+                material,
+            ),
+            Entity::new_triangle(
+                Vec3::zero(),
+                corner(-2.0, -2.0),
+                corner(2.0, 2.0),
+                corner(-2.0, 2.0),
+                material,
+            ),
+        ];
+        let bulb = Light::point(Vec3::new(0.0, -10.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 400.0 * PI, 0.0);
+        let render = |entities: &[Entity]| {
+            let world =
+                World::new(entities, Environment::constant(Rgb::new(0.0, 0.0, 0.0)), vec![]).with_lights(vec![bulb]);
+            // Every shadow ray crosses the screen at the same point, so only fresh random choices can average out
+            let ray = Ray {
+                origin: Vec3::new(0.5, -1.0, -1.0),
+                direction: Vec3::new(0.0, 1.0, 1.0).normalize(),
+            };
+            mean_radiance(ray, &world, 1, 4_000, 6).x
+        };
+
+        let lit = render(&[floor]);
+        let shaded = render(&[floor, screen[0], screen[1]]);
+        assert!((shaded - lit * 0.5).abs() < lit * 0.03, "{} vs {}", shaded, lit);
+    }
+
+    fn uniform_environment(value: f32) -> Environment {
+        let image = HdrImage {
+            width: 8,
+            height: 4,
+            pixels: vec![Vec3::new(value, value, value); 32],
+        };
+        Environment::image(image, 0.0, 1.0)
+    }
+
+    #[test]
+    fn test_power_heuristic() {
+        assert_eq!(power_heuristic(1.0, 0.0), 1.0);
+        assert_eq!(power_heuristic(0.0, 1.0), 0.0);
+        assert_eq!(power_heuristic(1.0, 1.0), 0.5);
+        assert_eq!(power_heuristic(0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_roulette_spares_shallow_paths() {
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+        let mut path = PathState::new(ray, 32, None);
+        path.throughput = Vec3::new(0.01, 0.02, 0.01);
+        assert_eq!(survival_probability(&path), 1.0);
+        path.depth = ROULETTE_DEPTH;
+        assert_eq!(survival_probability(&path), 0.02);
+        path.throughput = Vec3::new(3.0, 0.0, 0.0);
+        assert_eq!(survival_probability(&path), 1.0);
+    }
+
+    #[test]
+    fn test_roulette_is_unbiased() {
+        // From the centre of a glowing mirror ball every bounce is at normal incidence, so each one reflects
+        // exactly the albedo and the radiance is the geometric series E / (1 - albedo)
+        let mirror = Material::new(Rgb::new(10.0, 10.0, 10.0), Rgb::new(0.5, 0.5, 0.5), 1.0, 0.0, 0.0, 1.5);
+        let ball = Entity::new_sphere(Vec3::zero(), mirror, 5.0).with_ids(0, 0);
+        let world = World::new(&[ball], Environment::default(), vec![]);
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.3, -0.4, 1.0).normalize(),
+        };
+
+        let mean = mean_radiance(ray, &world, 64, 20_000, 17).x;
+        assert!((mean - 20.0).abs() < 20.0 * 0.03, "mean was {}", mean);
+    }
+
+    #[test]
+    fn test_deep_paths_run_in_constant_stack() {
+        // A perfect mirror ball never loses energy, so roulette never ends the path and it runs every bounce
+        let mirror = Material::new(Rgb::new(0.01, 0.01, 0.01), Rgb::new(1.0, 1.0, 1.0), 1.0, 0.0, 0.0, 1.5);
+        let ball = Entity::new_sphere(Vec3::zero(), mirror, 5.0).with_ids(0, 0);
+        let world = World::new(&[ball], Environment::default(), vec![]);
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+
+        let mut rng = SmallRng::seed_from_u64(1);
+        let radiance = trace(ray, &world, 100_000, &mut rng).0.x;
+        assert!((radiance - 1_000.0).abs() < 1.0, "{}", radiance);
+    }
+
+    #[test]
+    fn test_loop_matches_recursive_tracer() {
+        let floor = Entity::new_plane(Vec3::zero(), test_material(), Vec3::new(0.0, -1.0, 0.0)).with_ids(0, 0);
+        let glass = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 0.0, 1.0, 1.5)
+            .with_absorption(Rgb::new(0.8, 0.6, 0.4), 2.0);
+        let ball = Entity::new_sphere(Vec3::new(0.0, -1.5, 6.0), glass, 1.5).with_ids(1, 1);
+        let lamp = Material::new(Rgb::new(40.0, 30.0, 20.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 1.0, 0.0, 1.5);
+        let bulb = Entity::new_sphere(Vec3::new(2.0, -5.0, 6.0), lamp, 0.5).with_ids(2, 2);
+        let world = World::new(&[floor, ball, bulb], uniform_environment(0.5), vec![]);
+        let ray = Ray {
+            origin: Vec3::new(0.0, -2.0, 0.0),
+            direction: Vec3::new(0.0, 0.1, 1.0).normalize(),
+        };
+
+        // Captured from the recursive tracer with seeds 0 to 7. The loop draws the same random numbers and adds up
+        // the same terms, but multiplies the throughput along the path rather than back up it, so it agrees to
+        // within rounding rather than bit for bit.
+        let recursive = [
+            Vec3::new(69.30107, 45.03775, 24.534945),
+            Vec3::new(127.50053, 82.86079, 45.13961),
+            Vec3::new(127.50049, 82.86077, 45.139606),
+            Vec3::new(1.5259043, 0.9915856, 0.5401381),
+            Vec3::new(127.50053, 82.86079, 45.13961),
+            Vec3::new(153.34303, 99.655495, 54.288784),
+            Vec3::new(36.70113, 23.851486, 12.993412),
+            Vec3::new(127.50052, 82.86078, 45.139606),
+        ];
+        for (seed, expected) in recursive.into_iter().enumerate() {
+            let mut rng = SmallRng::seed_from_u64(seed as u64);
+            let radiance = trace(ray, &world, 8, &mut rng).0;
+            assert!(
+                (radiance - expected).mag() <= expected.mag() * 1e-5,
+                "seed {}: {} vs {}",
+                seed,
+                radiance,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_diffuse_under_uniform_environment_is_unbiased() {
+        // A furnace-style check: MIS between environment and BSDF samples must sum to the analytic answer
+        let floor = Entity::new_plane(Vec3::zero(), test_material(), Vec3::new(0.0, -1.0, 0.0));
+        let world = World::new(&[floor], uniform_environment(1.0), vec![]);
+        let ray = Ray {
+            origin: Vec3::new(0.0, -5.0, 0.0),
+            direction: Vec3::new(0.0, 1.0, 0.0),
+        };
+
+        let mean = mean_radiance(ray, &world, 2, 20_000, 1);
+
+        // A white surface under a uniform sky reflects all of it, between the diffuse and Fresnel lobes
+        let expected = 255.0;
+        assert!((mean.x - expected).abs() < expected * 0.03, "mean was {}", mean);
+    }
+
+    #[test]
+    fn test_tilted_normals_stay_above_the_horizon() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let shading = Vec3::new(-0.8, 0.0, 0.6);
+        let bent = above_horizon(shading, normal, wo);
+        assert!((reflect(wo, bent).dot(normal) - MIN_REFLECTION_COS).abs() < 1e-4);
+        assert!((bent.mag() - 1.0).abs() < 1e-5);
+        // Leaning towards the viewer is fine as it is
+        let towards = Vec3::new(0.3, 0.0, 0.954).normalize();
+        assert_eq!(above_horizon(towards, normal, wo), towards);
+        assert_eq!(above_horizon(normal, normal, wo), normal);
+        // At grazing angles an untilted normal is left alone
+        let grazing = Vec3::new(1.0, 0.0, 0.001).normalize();
+        assert_eq!(above_horizon(normal, normal, grazing), normal);
+    }
+
+    #[test]
+    fn test_normal_mapped_floor_neither_leaks_nor_blows_up() {
+        let material = test_material().with_normal_map(0, 1.0);
+        let floor = Entity::new_plane(Vec3::zero(), material, Vec3::new(0.0, -1.0, 0.0));
+        let world = World::new(
+            &[floor],
+            uniform_environment(1.0),
+            vec![Texture::constant(Rgb::new(0.7, 0.5, 0.9))],
+        );
+        for direction in [Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.9, 0.3, 0.0).normalize()] {
+            let ray = Ray {
+                origin: direction * -5.0,
+                direction,
+            };
+            let mean = mean_radiance(ray, &world, 2, 20_000, 4);
+            assert!(
+                mean.x > 255.0 * 0.5 && mean.x < 255.0 * 1.05,
+                "mean was {} from {}",
+                mean,
+                direction
+            );
+        }
+    }
+
+    #[test]
+    fn test_trace_reports_first_hit() {
+        let sphere = Entity::new_sphere(Vec3::new(0.0, 0.0, 10.0), test_material(), 2.0).with_ids(3, 1);
+        let world = World::new(&[sphere], Environment::default(), vec![]);
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+        let hit = trace(ray, &world, 4, &mut rng).1.unwrap();
+        assert_eq!(hit.depth, 8.0);
+        assert_eq!(hit.position, Vec3::new(0.0, 0.0, 8.0));
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(hit.albedo, Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!((hit.entity_id, hit.material_id), (3, 1));
+
+        let miss = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.0, 0.0, -1.0),
+        };
+        assert!(trace(miss, &world, 4, &mut rng).1.is_none());
+    }
+
+    #[test]
+    fn test_first_hit_albedo_is_textured() {
+        let mut material = test_material();
+        material.albedo_texture = Some(0);
+        let sphere = Entity::new_sphere(Vec3::new(0.0, 0.0, 10.0), material, 2.0);
+        let world = World::new(
+            &[sphere],
+            Environment::default(),
+            vec![Texture::constant(Rgb::new(0.25, 0.5, 0.75))],
+        );
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+        let hit = trace(ray, &world, 4, &mut rng).1.unwrap();
+        assert_eq!(hit.albedo, Vec3::new(0.25, 0.5, 0.75));
+    }
+
+    #[test]
+    fn test_absorption_darkens_thick_glass() {
+        let brightness = |radius: f32| {
+            let glass = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 0.0, 1.0, 1.5)
+                .with_absorption(Rgb::new(0.5, 0.5, 0.5), 1.0);
+            let world = World::new(
+                &[Entity::new_sphere(Vec3::new(0.0, 0.0, 10.0), glass, radius)],
+                uniform_environment(1.0),
+                vec![],
+            );
+            let ray = Ray {
+                origin: Vec3::zero(),
+                direction: Vec3::new(0.0, 0.0, 1.0),
+            };
+            mean_radiance(ray, &world, 8, 2_000, 6).x
+        };
+
+        let thin = brightness(0.5);
+        let thick = brightness(3.0);
+        // Straight through the centre is one unit of glass for the thin sphere and six for the thick one
+        assert!((thin - 255.0 * 0.5).abs() < 255.0 * 0.06, "thin was {}", thin);
+        // What's left is mostly the 4% reflected off the front
+        assert!(thick < 255.0 * 0.07, "thick was {}", thick);
+    }
+
+    #[test]
+    fn test_index_matched_nested_glass_is_invisible() {
+        let glass = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 0.0, 1.0, 1.5);
+        let centre = Vec3::new(0.0, 0.0, 10.0);
+        let outer = Entity::new_sphere(centre, glass, 3.0).with_ids(0, 0);
+        let inner = Entity::new_sphere(centre, glass, 1.5).with_ids(1, 0);
+        let ray = Ray {
+            origin: Vec3::new(0.0, 1.0, 0.0),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+
+        let render = |entities: &[Entity]| {
+            let world = World::new(entities, Environment::default(), vec![]);
+            mean_radiance(ray, &world, 8, 4_000, 3)
+        };
+
+        // Glass inside glass of the same IOR has no optical interface between them
+        let alone = render(&[outer]);
+        let nested = render(&[outer, inner]);
+        assert!((alone - nested).mag() < alone.mag() * 0.03, "{} vs {}", alone, nested);
+    }
+
+    #[test]
+    fn test_light_through_index_matched_shell_is_unchanged() {
+        let floor = Entity::new_plane(Vec3::zero(), test_material(), Vec3::new(0.0, -1.0, 0.0)).with_ids(0, 0);
+        let glow = Material::new(Rgb::new(2.0, 2.0, 2.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 1.0, 0.0, 1.5);
+        let lamp = Entity::new_sphere(Vec3::new(0.0, -6.0, 0.0), glow, 4.0).with_ids(1, 1);
+        // Air-filled, so crossing it changes nothing and the path carries straight on
+        let air = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 0.0, 1.0, 1.0);
+        let shell = Entity::new_sphere(Vec3::new(0.0, -6.0, 0.0), air, 5.0).with_ids(2, 2);
+        let ray = Ray {
+            origin: Vec3::new(0.0, -1.0, -3.0),
+            direction: Vec3::new(0.0, 1.0, 3.0).normalize(),
+        };
+
+        let render = |entities: &[Entity]| {
+            let world = World::new(entities, Environment::constant(Rgb::new(0.0, 0.0, 0.0)), vec![]);
+            mean_radiance(ray, &world, 2, 40_000, 8).x
+        };
+
+        let bare = render(&[floor, lamp]);
+        let shelled = render(&[floor, lamp, shell]);
+        assert!((bare - shelled).abs() < bare * 0.01, "{} vs {}", bare, shelled);
+    }
+
+    #[test]
+    fn test_small_light_is_sampled_directly() {
+        let floor = Entity::new_plane(Vec3::zero(), test_material(), Vec3::new(0.0, -1.0, 0.0)).with_ids(0, 0);
+        let bulb = Material::new(
+            Rgb::new(100.0, 100.0, 100.0),
+            Rgb::new(1.0, 1.0, 1.0),
+            0.0,
+            1.0,
+            0.0,
+            1.5,
+        );
+        let light = Entity::new_sphere(Vec3::new(0.0, -10.0, 0.0), bulb, 1.0).with_ids(1, 1);
+        let world = World::new(&[floor, light], Environment::constant(Rgb::new(0.0, 0.0, 0.0)), vec![]);
+        let ray = Ray {
+            origin: Vec3::new(0.0, -5.0, -5.0),
+            direction: Vec3::new(0.0, 1.0, 1.0).normalize(),
+        };
+
+        let mean = mean_radiance(ray, &world, 2, 2_000, 11).x;
+
+        // A sphere of radiance L straight overhead gives an irradiance of pi L (r / d)², and the diffuse lobe
+        // keeps whatever the 45 degree Fresnel reflection doesn't
+        let fresnel = 0.04 + 0.96 * (1.0 - 0.5f32.sqrt()).powi(5);
+        let expected = (1.0 - fresnel) * 100.0 * (1.0 / 10.0f32).powi(2);
+        assert!(
+            (mean - expected).abs() < expected * 0.03,
+            "mean was {}, expected {}",
+            mean,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_analytic_light_lights_but_is_unseen() {
+        let floor = Entity::new_plane(Vec3::zero(), test_material(), Vec3::new(0.0, -1.0, 0.0)).with_ids(0, 0);
+        let bulb = Light::point(Vec3::new(0.0, -10.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 400.0 * PI, 0.0);
+        let world =
+            World::new(&[floor], Environment::constant(Rgb::new(0.0, 0.0, 0.0)), vec![]).with_lights(vec![bulb]);
+        let mut rng = SmallRng::seed_from_u64(11);
+
+        let at_light = Ray {
+            origin: Vec3::new(0.0, -5.0, 0.0),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+        };
+        assert_eq!(trace(at_light, &world, 2, &mut rng).0, Vec3::zero());
+
+        // 100 W/sr from 10 away is an irradiance of 1, on the same scale as HDR environments
+        let at_floor = Ray {
+            origin: Vec3::new(0.0, -5.0, -5.0),
+            direction: Vec3::new(0.0, 1.0, 1.0).normalize(),
+        };
+        let radiance = trace(at_floor, &world, 2, &mut rng).0.x;
+        let fresnel = 0.04 + 0.96 * (1.0 - 0.5f32.sqrt()).powi(5);
+        let expected = (1.0 - fresnel) * HDR_SCALE / PI;
+        assert!(
+            (radiance - expected).abs() < expected * 1e-3,
+            "{} vs {}",
+            radiance,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_emitter_given_power_matches_a_light_of_that_power() {
+        let floor = Entity::new_plane(Vec3::zero(), test_material(), Vec3::new(0.0, -1.0, 0.0)).with_ids(0, 0);
+        let glow = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 1.0, 0.0, 1.5)
+            .with_emission_power(Rgb::new(1.0, 1.0, 1.0), 400.0 * PI);
+        let bulb = Entity::new_sphere(Vec3::new(0.0, -10.0, 0.0), glow, 0.5).with_ids(1, 1);
+        let world = World::new(&[floor, bulb], Environment::constant(Rgb::new(0.0, 0.0, 0.0)), vec![]);
+        let ray = Ray {
+            origin: Vec3::new(0.0, -5.0, -5.0),
+            direction: Vec3::new(0.0, 1.0, 1.0).normalize(),
+        };
+
+        let mean = mean_radiance(ray, &world, 2, 2_000, 11).x;
+
+        // The same as the point light in the test above, whatever the bulb's size
+        let fresnel = 0.04 + 0.96 * (1.0 - 0.5f32.sqrt()).powi(5);
+        let expected = (1.0 - fresnel) * HDR_SCALE / PI;
+        assert!(
+            (mean - expected).abs() < expected * 0.03,
+            "mean was {}, expected {}",
+            mean,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_scattering_volume_conserves_energy() {
+        // A white, non-absorbing cloud under a uniform sky bounces light around but can't change its total
+        let smoke = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 0.0, 1.0, 1.0)
+            .with_medium(Medium::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 0.5));
+        let cloud = Entity::new_sphere(Vec3::new(0.0, 0.0, 10.0), smoke, 2.0).with_ids(0, 0);
+        let world = World::new(&[cloud], uniform_environment(1.0), vec![]);
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+
+        let mean = mean_radiance(ray, &world, 64, 4_000, 9).x;
+        assert!((mean - 255.0).abs() < 255.0 * 0.03, "mean was {}", mean);
+    }
+
+    #[test]
+    fn test_fog_hazes_the_environment() {
+        let fog = Fog {
+            medium: Medium::new(Rgb::new(0.1, 0.1, 0.1), Rgb::new(0.0, 0.0, 0.0), 0.0),
+            centre: Vec3::zero(),
+            radius: 10.0,
+        };
+        let world = World::new(&[], uniform_environment(1.0), vec![]).with_fog(Some(fog));
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+        let mut rng = SmallRng::seed_from_u64(0);
+        let radiance = trace(ray, &world, 4, &mut rng).0.x;
+        assert!((radiance - 255.0 * (-1.0f32).exp()).abs() < 0.01, "{}", radiance);
+    }
+
+    #[test]
+    fn test_fog_scatters_light_towards_camera() {
+        // Looking past a light into the dark, fog is the only thing that can send any of it back
+        let bulb = Light::point(Vec3::new(0.0, -3.0, 5.0), Rgb::new(1.0, 1.0, 1.0), 1000.0, 0.0);
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+        let render = |fog: Option<Fog>| {
+            let world = World::new(&[], Environment::constant(Rgb::new(0.0, 0.0, 0.0)), vec![])
+                .with_lights(vec![bulb])
+                .with_fog(fog);
+            mean_radiance(ray, &world, 4, 500, 2).x
+        };
+
+        assert_eq!(render(None), 0.0);
+        let fog = Fog {
+            medium: Medium::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(0.05, 0.05, 0.05), 0.0),
+            centre: Vec3::zero(),
+            radius: 20.0,
+        };
+        assert!(render(Some(fog)) > 1.0);
+    }
+
+    #[test]
+    fn test_volume_density_scales_its_medium() {
+        let camera = Camera::new(Vec3::zero(), Vec3::zero(), 1, 1, 0.0);
+        let mut scene = Scene::new(1, 1, camera, 1, 8);
+        scene.set_environment(uniform_environment(1.0));
+        let mut grid = vec![];
+        for value in [1u32, 1, 1] {
+            grid.extend_from_slice(&value.to_le_bytes());
+        }
+        grid.extend_from_slice(&0.25f32.to_le_bytes());
+        let ink = Medium::new(Rgb::new(1.0, 1.0, 1.0), Rgb::new(0.0, 0.0, 0.0), 0.0);
+        scene.add_volume(
+            Vec3::new(-1.0, -1.0, 4.0),
+            Vec3::new(1.0, 1.0, 6.0),
+            ink,
+            DensityField::grid(&grid).unwrap(),
+        );
+        let world = World::build(&scene);
+
+        // A quarter of the medium's absorption over two units of depth
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+        let mean = mean_radiance(ray, &world, 8, 4_000, 4).x;
+        let expected = 255.0 * (-0.5f32).exp();
+        assert!(
+            (mean - expected).abs() < expected * 0.03,
+            "mean was {}, expected {}",
+            mean,
+            expected
+        );
+    }
+
+    fn wax(albedo: Rgb) -> Material {
+        Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 0.0, 0.0, 1.4)
+            .with_subsurface(Rgb::new(0.25, 0.25, 0.25), albedo)
+    }
+
+    #[test]
+    fn test_subsurface_conserves_energy() {
+        // The walk inside takes far more scattering events than the path has bounces, but none of them count
+        let candle = Entity::new_sphere(Vec3::new(0.0, 0.0, 10.0), wax(Rgb::new(1.0, 1.0, 1.0)), 2.0).with_ids(0, 0);
+        let world = World::new(&[candle], uniform_environment(1.0), vec![]);
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+
+        let mean = mean_radiance(ray, &world, 8, 4_000, 17).x;
+        assert!((mean - 255.0).abs() < 255.0 * 0.03, "mean was {}", mean);
+    }
+
+    #[test]
+    fn test_subsurface_is_lit_and_coloured_from_inside() {
+        // Only an analytic light, which the walk can only find once it is back out through the surface
+        let candle = Entity::new_sphere(Vec3::new(0.0, 0.0, 10.0), wax(Rgb::new(0.99, 0.6, 0.6)), 2.0).with_ids(0, 0);
+        let bulb = Light::point(Vec3::new(0.0, -10.0, 10.0), Rgb::new(1.0, 1.0, 1.0), 400.0 * PI, 0.0);
+        let world =
+            World::new(&[candle], Environment::constant(Rgb::new(0.0, 0.0, 0.0)), vec![]).with_lights(vec![bulb]);
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+
+        let mean = mean_radiance(ray, &world, 8, 2_000, 23);
+        assert!(mean.y > 0.0, "{}", mean);
+        // Red survives more scattering events, so more of it makes it round from the lit side
+        assert!(mean.x > mean.y * 1.5, "{}", mean);
+    }
+
+    #[test]
+    fn test_spectral_mode_matches_rgb() {
+        let orange = Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(0.8, 0.4, 0.2), 0.0, 1.0, 0.0, 1.5);
+        let floor = Entity::new_plane(Vec3::zero(), orange, Vec3::new(0.0, -1.0, 0.0)).with_ids(0, 0);
+        let ray = Ray {
+            origin: Vec3::new(0.0, -5.0, 0.0),
+            direction: Vec3::new(0.0, 1.0, 0.0),
+        };
+        let mean = |world: &World| mean_radiance(ray, world, 2, 20_000, 5);
+
+        let rgb = mean(&World::new(&[floor], uniform_environment(1.0), vec![]));
+        let spectral = mean(&World::new(&[floor], uniform_environment(1.0), vec![]).with_spectral(true));
+        assert!((spectral - rgb).mag() < rgb.mag() * 0.05, "{} vs {}", spectral, rgb);
+    }
+
+    #[test]
+    fn test_dispersive_glass_stays_white_on_average() {
+        // Each path only follows one wavelength through the glass, but together they still add up to white
+        let flint =
+            Material::new(Rgb::new(0.0, 0.0, 0.0), Rgb::new(1.0, 1.0, 1.0), 0.0, 0.0, 1.0, 1.6).with_cauchy(0.02);
+        let ball = Entity::new_sphere(Vec3::new(0.0, 0.0, 10.0), flint, 2.0).with_ids(0, 0);
+        let world = World::new(&[ball], uniform_environment(1.0), vec![]).with_spectral(true);
+        let ray = Ray {
+            origin: Vec3::new(0.0, 0.0, 0.0),
+            direction: Vec3::new(0.05, 0.0, 1.0).normalize(),
+        };
+
+        let mean = mean_radiance(ray, &world, 16, 20_000, 31);
+        for channel in [mean.x, mean.y, mean.z] {
+            assert!((channel - 255.0).abs() < 255.0 * 0.05, "mean was {}", mean);
+        }
+    }
 }
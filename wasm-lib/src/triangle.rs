@@ -1,8 +1,11 @@
 use crate::ray::Ray;
 use crate::traceable::Traceable;
+use crate::vec2::Vec2;
 use crate::vec3::Vec3;
 
 #[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "TriangleData", into = "TriangleData"))]
 pub struct Triangle {
     pub a: Vec3,
     pub b: Vec3,
@@ -10,9 +13,51 @@ pub struct Triangle {
     edge1: Vec3,
     edge2: Vec3,
     normal: Vec3,
+    uvs: [Vec2; 3],
+}
+
+/// Triangles with the default texture coordinates are saved as just their vertices.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum TriangleData {
+    Vertices([Vec3; 3]),
+    Textured { vertices: [Vec3; 3], uvs: [Vec2; 3] },
+}
+
+#[cfg(feature = "serde")]
+impl From<TriangleData> for Triangle {
+    fn from(data: TriangleData) -> Self {
+        match data {
+            TriangleData::Vertices(vertices) => Triangle::from(vertices),
+            TriangleData::Textured { vertices, uvs } => Triangle::from(vertices).with_uvs(uvs),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Triangle> for TriangleData {
+    fn from(triangle: Triangle) -> Self {
+        let vertices = [triangle.a, triangle.b, triangle.c];
+        if triangle.uvs == Triangle::DEFAULT_UVS {
+            TriangleData::Vertices(vertices)
+        } else {
+            TriangleData::Textured {
+                vertices,
+                uvs: triangle.uvs,
+            }
+        }
+    }
 }
 
 impl Triangle {
+    /// Maps the corners onto the unit triangle, so the UVs are the barycentric coordinates.
+    const DEFAULT_UVS: [Vec2; 3] = [
+        Vec2 { x: 0.0, y: 0.0 },
+        Vec2 { x: 1.0, y: 0.0 },
+        Vec2 { x: 0.0, y: 1.0 },
+    ];
+
     pub fn new(a: Vec3, b: Vec3, c: Vec3) -> Self {
         let edge1 = b - a;
         let edge2 = c - a;
@@ -24,8 +69,25 @@ impl Triangle {
             edge1,
             edge2,
             normal,
+            uvs: Self::DEFAULT_UVS,
         }
     }
+
+    pub fn with_uvs(self, uvs: [Vec2; 3]) -> Self {
+        Self { uvs, ..self }
+    }
+}
+
+impl From<[Vec3; 3]> for Triangle {
+    fn from([a, b, c]: [Vec3; 3]) -> Self {
+        Triangle::new(a, b, c)
+    }
+}
+
+impl From<Triangle> for [Vec3; 3] {
+    fn from(triangle: Triangle) -> Self {
+        [triangle.a, triangle.b, triangle.c]
+    }
 }
 
 impl Traceable for Triangle {
@@ -84,6 +146,60 @@ impl Traceable for Triangle {
 
         Some((t, normal))
     }
+
+    fn uv(&self, point: Vec3, position: Vec3) -> Vec2 {
+        let offset = point - (self.a + position);
+        let d00 = self.edge1.dot(self.edge1);
+        let d01 = self.edge1.dot(self.edge2);
+        let d11 = self.edge2.dot(self.edge2);
+        let d20 = offset.dot(self.edge1);
+        let d21 = offset.dot(self.edge2);
+        let denom = d00 * d11 - d01 * d01;
+
+        let beta = (d11 * d20 - d01 * d21) / denom;
+        let gamma = (d00 * d21 - d01 * d20) / denom;
+        let alpha = 1.0 - beta - gamma;
+
+        self.uvs[0] * alpha + self.uvs[1] * beta + self.uvs[2] * gamma
+    }
+
+    /// Solves for the direction of increasing `u` from the edges and their UV differences, falling back to the
+    /// first edge if the UVs are degenerate.
+    fn tangent(&self, _point: Vec3, _position: Vec3) -> Vec3 {
+        let duv1 = self.uvs[1] - self.uvs[0];
+        let duv2 = self.uvs[2] - self.uvs[0];
+        let det = duv1.x * duv2.y - duv2.x * duv1.y;
+        if det.abs() < 1e-12 {
+            return self.edge1;
+        }
+        (self.edge1 * duv2.y - self.edge2 * duv1.y) / det
+    }
+
+    fn area(&self) -> f32 {
+        0.5 * self.edge1.cross(self.edge2).mag()
+    }
+
+    /// Picks a point uniformly by area, then converts the density to solid angle as seen from `from`.
+    fn sample_towards(&self, position: Vec3, from: Vec3, u1: f32, u2: f32) -> Option<(Vec3, f32)> {
+        let su = u1.sqrt();
+        let point = self.a + position + self.edge1 * (su * (1.0 - u2)) + self.edge2 * (su * u2);
+        let pdf = self.pdf_towards(position, from, point);
+        if pdf > 0.0 {
+            Some((point, pdf))
+        } else {
+            None
+        }
+    }
+
+    fn pdf_towards(&self, _position: Vec3, from: Vec3, point: Vec3) -> f32 {
+        let to_point = point - from;
+        let dist2 = to_point.mag_squared();
+        let cos_light = self.normal.dot(to_point).abs() / dist2.sqrt();
+        if cos_light < 1e-6 {
+            return 0.0;
+        }
+        dist2 / (self.area() * cos_light)
+    }
 }
 
 #[cfg(test)]
@@ -141,6 +257,39 @@ mod tests {
         assert_eq!(normal, Vec3::new(0.0, 0.0, 1.0));
     }
 
+    #[test]
+    fn test_default_uv_is_barycentric() {
+        let t = flat_triangle();
+        let uv = t.uv(Vec3::new(1.0, -1.0, 5.0), Vec3::zero());
+        assert_eq!(uv, Vec2::new(1.0, 0.0));
+        let uv = t.uv(Vec3::new(0.0, 1.0, 5.0), Vec3::zero());
+        assert_eq!(uv, Vec2::new(0.0, 1.0));
+    }
+
+    #[test]
+    fn test_tangent_follows_u() {
+        let t = flat_triangle();
+        let position = Vec3::zero();
+        let point = Vec3::new(0.0, -0.5, 5.0);
+        let tangent = t.tangent(point, position);
+        assert!(tangent.dot(t.normal).abs() < 1e-6);
+        let step = t.uv(point + tangent * 0.1, position) - t.uv(point, position);
+        assert!((step.x - 0.1).abs() < 1e-5 && step.y.abs() < 1e-5, "{:?}", step);
+
+        // Swapping the UVs around turns the tangent with them
+        let swapped = t.with_uvs([Vec2::new(0.0, 0.0), Vec2::new(0.0, 1.0), Vec2::new(1.0, 0.0)]);
+        let tangent = swapped.tangent(point, position);
+        let step = swapped.uv(point + tangent * 0.1, position) - swapped.uv(point, position);
+        assert!((step.x - 0.1).abs() < 1e-5 && step.y.abs() < 1e-5, "{:?}", step);
+    }
+
+    #[test]
+    fn test_uv_interpolates_vertex_uvs() {
+        let t = flat_triangle().with_uvs([Vec2::new(0.0, 1.0), Vec2::new(1.0, 1.0), Vec2::new(0.5, 0.0)]);
+        let uv = t.uv(Vec3::new(0.0, -1.0, 5.0), Vec3::zero());
+        assert_eq!(uv, Vec2::new(0.5, 1.0));
+    }
+
     #[test]
     fn test_bounds() {
         let t = flat_triangle();
@@ -148,4 +297,31 @@ mod tests {
         assert_eq!(min, Vec3::new(-1.0, -1.0, 5.0));
         assert_eq!(max, Vec3::new(1.0, 1.0, 5.0));
     }
+
+    #[test]
+    fn test_sample_towards() {
+        let t = flat_triangle();
+        assert_eq!(t.area(), 2.0);
+        let (point, pdf) = t.sample_towards(Vec3::zero(), Vec3::zero(), 0.25, 0.5).unwrap();
+        assert!((point.z - 5.0).abs() < 1e-6);
+        assert!(t
+            .intersect(
+                Ray {
+                    origin: Vec3::zero(),
+                    direction: point.normalize()
+                },
+                Vec3::zero()
+            )
+            .is_some());
+        assert_eq!(pdf, point.mag_squared() / (2.0 * point.normalize().z));
+        // Edge-on triangles can't be sampled
+        assert!(t
+            .sample_towards(
+                Vec3::zero(),
+                Vec3::new(0.0, 0.0, 5.0) - Vec3::new(10.0, 0.0, 0.0),
+                0.5,
+                0.5
+            )
+            .is_none());
+    }
 }
@@ -6,6 +6,7 @@ use std::ops::Sub;
 
 #[wasm_bindgen()]
 #[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vec2 {
     pub x: f32,
     pub y: f32,
@@ -9,6 +9,7 @@ use crate::rgb::Rgb;
 
 #[wasm_bindgen]
 #[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -83,6 +84,10 @@ impl Vec3 {
         vectors.iter().fold(Vec3::zero(), |sum, &val| sum + val) * (1.0 / vectors.len() as f32)
     }
 
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+
     pub fn gamma(self, gamma: f32) -> Self {
         let gamma_correction = 1.0 / gamma;
         Vec3::new(
@@ -122,11 +127,37 @@ impl Vec3 {
         }
     }
 
+    /// Cosine-weighted direction about `normal`, with a density of `cos(theta) / PI`.
+    pub fn rng_cosine_hemisphere(normal: Vec3, rng: &mut impl Rng) -> Self {
+        loop {
+            let direction = normal + Self::rng_normal(rng).normalize();
+            if direction.mag_squared() > 1e-8 {
+                return direction.normalize();
+            }
+        }
+    }
+
+    /// Two unit vectors that, together with `self`, form an orthonormal basis. `self` must be normalised.
+    pub fn orthonormal_basis(&self) -> (Vec3, Vec3) {
+        let sign = 1.0f32.copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+        (
+            Vec3::new(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x),
+            Vec3::new(b, sign + self.y * self.y * a, -self.y),
+        )
+    }
+
     pub fn fresnel_schlick(f0: Vec3, cos_theta: f32) -> Vec3 {
         let c = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
         f0 + (Vec3::new(1.0, 1.0, 1.0) - f0) * c
     }
 
+    /// Component-wise `e^x`.
+    pub fn exp(self) -> Vec3 {
+        Vec3::new(self.x.exp(), self.y.exp(), self.z.exp())
+    }
+
     pub fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
         a * (1.0 - t) + b * t
     }
@@ -370,6 +401,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::excessive_precision)]
     fn test_mag() {
         let a = Vec3::new(1.0, 2.0, 3.0);
 
@@ -585,4 +617,51 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_rng_cosine_hemisphere_same_side_as_normal() {
+        let mut rng = rand::thread_rng();
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        for _ in 0..100 {
+            let v = Vec3::rng_cosine_hemisphere(normal, &mut rng);
+            assert!(v.dot(normal) >= 0.0);
+            assert!((v.mag() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_rng_cosine_hemisphere_mean_cosine() {
+        // E[cos] under a cos/PI density is 2/3
+        let mut rng = rand::thread_rng();
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let count = 20_000;
+        let mean = (0..count)
+            .map(|_| Vec3::rng_cosine_hemisphere(normal, &mut rng).dot(normal))
+            .sum::<f32>()
+            / count as f32;
+        assert!((mean - 2.0 / 3.0).abs() < 0.02, "mean cosine was {}", mean);
+    }
+
+    #[test]
+    fn test_luminance() {
+        assert!((Vec3::new(1.0, 1.0, 1.0).luminance() - 1.0).abs() < 1e-6);
+        assert_eq!(Vec3::zero().luminance(), 0.0);
+    }
+
+    #[test]
+    fn test_orthonormal_basis() {
+        for n in [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 2.0, 3.0).normalize(),
+            Vec3::new(0.0, -1.0, 0.0),
+        ] {
+            let (t, b) = n.orthonormal_basis();
+            assert!(t.dot(n).abs() < 1e-6);
+            assert!(b.dot(n).abs() < 1e-6);
+            assert!(t.dot(b).abs() < 1e-6);
+            assert!((t.mag() - 1.0).abs() < 1e-5);
+            assert!((b.mag() - 1.0).abs() < 1e-5);
+        }
+    }
 }
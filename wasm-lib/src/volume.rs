@@ -0,0 +1,276 @@
+use std::sync::Arc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::noise;
+use crate::vec3::Vec3;
+
+/// Densities on a regular grid of voxels, x varying fastest and then y.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "GridData"))]
+#[derive(Clone, PartialEq, Debug)]
+pub struct Grid {
+    width: usize,
+    height: usize,
+    depth: usize,
+    values: Arc<Vec<f32>>,
+}
+
+/// A grid as saved, checked on loading like one read from bytes.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct GridData {
+    width: usize,
+    height: usize,
+    depth: usize,
+    values: Vec<f32>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<GridData> for Grid {
+    type Error = &'static str;
+
+    fn try_from(data: GridData) -> Result<Self, Self::Error> {
+        Grid::new(data.width, data.height, data.depth, data.values)
+    }
+}
+
+impl Grid {
+    /// Checks the grid's size against its voxels, which must be finite; negative densities are clamped to zero.
+    fn new(width: usize, height: usize, depth: usize, mut values: Vec<f32>) -> Result<Self, &'static str> {
+        if width == 0 || height == 0 || depth == 0 {
+            return Err("grid must not be empty");
+        }
+        let count = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(depth))
+            .ok_or("grid is too large")?;
+        if values.len() != count {
+            return Err("voxel data does not match the grid size");
+        }
+        if values.iter().any(|v| !v.is_finite()) {
+            return Err("voxel densities must be finite");
+        }
+        for value in &mut values {
+            *value = value.max(0.0);
+        }
+        Ok(Self {
+            width,
+            height,
+            depth,
+            values: Arc::new(values),
+        })
+    }
+
+    /// Reads the binary grid format: width, height and depth as little-endian `u32`s, followed by that many
+    /// little-endian `f32` densities with x varying fastest and z slowest.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        if bytes.len() < 12 {
+            return Err("grid is missing its dimensions");
+        }
+        let dimension = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]) as usize;
+        let (width, height, depth) = (dimension(0), dimension(4), dimension(8));
+        let length = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(depth))
+            .and_then(|n| n.checked_mul(4))
+            .ok_or("grid is too large")?;
+        if bytes.len() - 12 != length {
+            return Err("voxel data does not match the grid size");
+        }
+
+        let values = bytes[12..]
+            .chunks_exact(4)
+            .map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+            .collect();
+        Self::new(width, height, depth, values)
+    }
+
+    fn voxel(&self, x: isize, y: isize, z: isize) -> f32 {
+        let clamp = |v: isize, size: usize| v.clamp(0, size as isize - 1) as usize;
+        let (x, y, z) = (clamp(x, self.width), clamp(y, self.height), clamp(z, self.depth));
+        self.values[(z * self.height + y) * self.width + x]
+    }
+
+    /// Trilinearly filtered lookup at `p` in `[0, 1]` across the grid, zero outside.
+    pub fn sample(&self, p: Vec3) -> f32 {
+        if !(0.0..=1.0).contains(&p.x) || !(0.0..=1.0).contains(&p.y) || !(0.0..=1.0).contains(&p.z) {
+            return 0.0;
+        }
+        let x = p.x * self.width as f32 - 0.5;
+        let y = p.y * self.height as f32 - 0.5;
+        let z = p.z * self.depth as f32 - 0.5;
+        let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+        let (x0, y0, z0) = (x0 as isize, y0 as isize, z0 as isize);
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let face = |z: isize| {
+            lerp(
+                lerp(self.voxel(x0, y0, z), self.voxel(x0 + 1, y0, z), fx),
+                lerp(self.voxel(x0, y0 + 1, z), self.voxel(x0 + 1, y0 + 1, z), fx),
+                fy,
+            )
+        };
+        lerp(face(z0), face(z0 + 1), fz)
+    }
+
+    fn max(&self) -> f32 {
+        self.values.iter().copied().fold(0.0, f32::max)
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Debug)]
+enum Field {
+    /// Fractal noise, cut off below `threshold` so clouds have gaps and edges.
+    Noise {
+        scale: f32,
+        octaves: u32,
+        threshold: f32,
+    },
+    Grid(Grid),
+}
+
+/// How thick a volume is from place to place, as a multiple of its medium's coefficients.
+#[wasm_bindgen]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, PartialEq, Debug)]
+pub struct DensityField {
+    field: Field,
+    /// The box the field fills, set when it is added to a scene.
+    min: Vec3,
+    max: Vec3,
+}
+
+impl DensityField {
+    fn new(field: Field) -> Self {
+        Self {
+            field,
+            min: Vec3::zero(),
+            max: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// The field stretched over the box from `min` to `max`.
+    pub fn placed(self, min: Vec3, max: Vec3) -> Self {
+        Self { min, max, ..self }
+    }
+
+    pub fn density(&self, p: Vec3) -> f32 {
+        match &self.field {
+            Field::Noise {
+                scale,
+                octaves,
+                threshold,
+            } => {
+                let n = 0.5 + 0.5 * noise::fbm((p - self.min) * *scale, *octaves);
+                ((n - threshold) / (1.0 - threshold).max(1e-3)).clamp(0.0, 1.0)
+            }
+            Field::Grid(grid) => grid.sample((p - self.min) / (self.max - self.min)),
+        }
+    }
+
+    /// An upper bound on `density` anywhere, which tracking uses to step through the volume.
+    pub fn max_density(&self) -> f32 {
+        match &self.field {
+            Field::Noise { .. } => 1.0,
+            Field::Grid(grid) => grid.max(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl DensityField {
+    /// Billowing fractal noise with features about `1 / scale` across. Raising `threshold` towards 1 leaves
+    /// sparser, more broken-up clouds.
+    pub fn noise(scale: f32, octaves: u32, threshold: f32) -> Self {
+        Self::new(Field::Noise {
+            scale,
+            octaves,
+            threshold: threshold.clamp(0.0, 1.0),
+        })
+    }
+
+    /// A voxel grid in the binary format described on `Grid::from_bytes`.
+    pub fn grid(bytes: &[u8]) -> Result<DensityField, JsError> {
+        let grid = Grid::from_bytes(bytes).map_err(JsError::new)?;
+        Ok(Self::new(Field::Grid(grid)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(width: u32, height: u32, depth: u32, values: &[f32]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for dimension in [width, height, depth] {
+            bytes.extend_from_slice(&dimension.to_le_bytes());
+        }
+        for value in values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_grid_reads_voxels() {
+        let values: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        let grid = Grid::from_bytes(&encode(2, 2, 2, &values)).unwrap();
+        // Voxel centres sit a quarter of the way in, x fastest
+        assert_eq!(grid.sample(Vec3::new(0.25, 0.25, 0.25)), 0.0);
+        assert_eq!(grid.sample(Vec3::new(0.75, 0.25, 0.25)), 1.0);
+        assert_eq!(grid.sample(Vec3::new(0.25, 0.75, 0.25)), 2.0);
+        assert_eq!(grid.sample(Vec3::new(0.25, 0.25, 0.75)), 4.0);
+        assert_eq!(grid.sample(Vec3::new(0.5, 0.25, 0.25)), 0.5);
+        assert_eq!(grid.sample(Vec3::new(1.5, 0.5, 0.5)), 0.0);
+        assert_eq!(grid.max(), 7.0);
+    }
+
+    #[test]
+    fn test_grid_rejects_bad_data() {
+        assert!(Grid::from_bytes(&[0, 0]).is_err());
+        assert!(Grid::from_bytes(&encode(0, 1, 1, &[])).is_err());
+        assert!(Grid::from_bytes(&encode(2, 1, 1, &[1.0])).is_err());
+        // Fits in a 64-bit count of voxels, but not of bytes
+        assert_eq!(
+            Grid::from_bytes(&encode(1 << 31, 1 << 31, 2, &[])),
+            Err("grid is too large")
+        );
+        assert!(Grid::from_bytes(&encode(1, 1, 1, &[f32::NAN])).is_err());
+        assert!(Grid::from_bytes(&encode(1, 1, 1, &[f32::INFINITY])).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_loaded_grid_is_checked() {
+        let grid = Grid::from_bytes(&encode(2, 1, 1, &[1.0, 2.0])).unwrap();
+        let json = serde_json::to_string(&grid).unwrap();
+        assert_eq!(serde_json::from_str::<Grid>(&json).unwrap(), grid);
+        assert!(serde_json::from_str::<Grid>(r#"{"width":1024,"height":1024,"depth":1024,"values":[]}"#).is_err());
+        let negative = serde_json::from_str::<Grid>(r#"{"width":2,"height":1,"depth":1,"values":[-1.0,2.0]}"#).unwrap();
+        assert_eq!(negative.sample(Vec3::new(0.25, 0.5, 0.5)), 0.0);
+    }
+
+    #[test]
+    fn test_grid_is_stretched_over_its_box() {
+        let grid = Grid::from_bytes(&encode(1, 1, 1, &[3.0])).unwrap();
+        let field = DensityField::new(Field::Grid(grid)).placed(Vec3::new(-2.0, 0.0, 0.0), Vec3::new(2.0, 1.0, 1.0));
+        assert_eq!(field.density(Vec3::new(-1.9, 0.5, 0.5)), 3.0);
+        assert_eq!(field.density(Vec3::new(2.1, 0.5, 0.5)), 0.0);
+    }
+
+    #[test]
+    fn test_noise_density_is_bounded() {
+        let field = DensityField::noise(0.7, 4, 0.4);
+        let mut any = false;
+        for i in 0..500 {
+            let p = Vec3::new(i as f32 * 0.13, i as f32 * 0.07, i as f32 * 0.29);
+            let density = field.density(p);
+            assert!((0.0..=field.max_density()).contains(&density));
+            any |= density > 0.0;
+        }
+        assert!(any);
+    }
+}
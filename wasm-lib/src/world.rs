@@ -0,0 +1,66 @@
+use crate::bvh::Tree;
+use crate::entity::Entity;
+use crate::environment::Environment;
+use crate::light::Light;
+use crate::lights::Lights;
+use crate::media::Fog;
+use crate::scene::Scene;
+use crate::texture::Texture;
+use crate::volume::DensityField;
+
+/// Everything the tracer needs to know about a scene, prepared once per render.
+pub struct World {
+    pub bvh: Tree,
+    pub environment: Environment,
+    pub textures: Vec<Texture>,
+    pub lights: Lights,
+    pub fog: Option<Fog>,
+    /// Density fields of heterogeneous media, by handle.
+    pub volumes: Vec<DensityField>,
+    /// Trace wavelengths rather than RGB, for dispersion.
+    pub spectral: bool,
+}
+
+impl World {
+    /// Emitted powers are spread over their entities' surfaces here, so a scene keeps its materials as given.
+    pub fn new(entities: &[Entity], environment: Environment, textures: Vec<Texture>) -> Self {
+        let entities: Vec<Entity> = entities.iter().map(|e| e.with_spread_emission()).collect();
+        Self {
+            bvh: Tree::build(&entities),
+            environment,
+            textures,
+            lights: Lights::build(&entities),
+            fog: None,
+            volumes: vec![],
+            spectral: false,
+        }
+    }
+
+    pub fn with_lights(mut self, lights: Vec<Light>) -> Self {
+        self.lights = self.lights.with_analytic(lights);
+        self
+    }
+
+    pub fn with_fog(mut self, fog: Option<Fog>) -> Self {
+        self.fog = fog;
+        self
+    }
+
+    pub fn with_volumes(mut self, volumes: Vec<DensityField>) -> Self {
+        self.volumes = volumes;
+        self
+    }
+
+    pub fn with_spectral(mut self, spectral: bool) -> Self {
+        self.spectral = spectral;
+        self
+    }
+
+    pub fn build(scene: &Scene) -> Self {
+        Self::new(scene.entities(), scene.environment().clone(), scene.textures().to_vec())
+            .with_lights(scene.lights().to_vec())
+            .with_fog(scene.fog())
+            .with_volumes(scene.volumes().to_vec())
+            .with_spectral(scene.spectral)
+    }
+}